@@ -0,0 +1,313 @@
+use chrono::NaiveDate;
+use clap::{ArgAction, ArgGroup, Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::downloader::AlbumGroups;
+
+/// Download songs from Spotify
+#[derive(Debug, Parser)]
+#[command(
+	version,
+	args_conflicts_with_subcommands = true,
+	subcommand_negates_reqs = true,
+	arg_required_else_help = true
+)]
+pub struct Cli {
+	#[command(subcommand)]
+	command: Option<Command>,
+
+	/// Without a subcommand, the arguments are those of `download`
+	#[command(flatten)]
+	download: Args,
+}
+
+impl Cli {
+	/// Subcommand to run, `download` if none was given
+	pub fn into_command(self) -> Command {
+		self.command
+			.unwrap_or_else(|| Command::Download(Box::new(self.download)))
+	}
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+	/// Download a search result, or tracks, albums, playlists or artists by URL (default)
+	Download(Box<Args>),
+	/// Print search results without downloading
+	Search(SearchArgs),
+	/// Print the tracks of the last run's queue, or of a report
+	Queue(QueueArgs),
+	/// Print where the settings are, or the settings themselves
+	Config(ConfigArgs),
+}
+
+impl Command {
+	/// Default log filter for the verbosity
+	pub fn log_level(&self) -> &'static str {
+		let verbose = match self {
+			Command::Download(args) => args.verbose,
+			Command::Search(args) => args.verbose,
+			Command::Queue(args) => args.verbose,
+			Command::Config(args) => args.verbose,
+		};
+		match verbose {
+			0 => "error",
+			1 => "info",
+			2 => "debug",
+			_ => "trace",
+		}
+	}
+}
+
+#[derive(Debug, clap::Args)]
+pub struct SearchArgs {
+	/// Search term
+	#[arg(required = true)]
+	pub query: Vec<String>,
+
+	/// Print the results as JSON
+	#[arg(long)]
+	pub json: bool,
+
+	/// Log more, -v for info and -vv for debug messages
+	#[arg(short, long, action = ArgAction::Count)]
+	pub verbose: u8,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct QueueArgs {
+	/// Report to print instead of the queue of the last run
+	#[arg(value_name = "PATH")]
+	pub report: Option<PathBuf>,
+
+	/// Only print the tracks that failed or didn't finish, which `download --resume` downloads
+	#[arg(long)]
+	pub unfinished: bool,
+
+	/// Print the queue as JSON
+	#[arg(long)]
+	pub json: bool,
+
+	/// Log more, -v for info and -vv for debug messages
+	#[arg(short, long, action = ArgAction::Count)]
+	pub verbose: u8,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ConfigArgs {
+	/// Print the loaded settings as JSON, with passwords and secrets hidden
+	#[arg(long)]
+	pub show: bool,
+
+	/// Log more, -v for info and -vv for debug messages
+	#[arg(short, long, action = ArgAction::Count)]
+	pub verbose: u8,
+}
+
+#[derive(Debug, clap::Args)]
+#[command(group(ArgGroup::new("listing").args(["list", "probe"])))]
+pub struct Args {
+	/// Search term, or track, album, playlist or artist URL
+	#[arg(required_unless_present_any = ["list", "probe", "watch", "clear_cache", "login", "resume", "from_file"])]
+	pub input: Vec<String>,
+
+	/// Print the tracks of a URL without downloading them
+	#[arg(long, value_name = "URL", conflicts_with = "input")]
+	pub list: Option<String>,
+
+	/// Print the quality each track of a URL would be downloaded in, without downloading
+	#[arg(long, value_name = "URL", conflicts_with = "input")]
+	pub probe: Option<String>,
+
+	/// Keep running and download URLs added to a text file, or to .txt files in a folder
+	#[arg(long, value_name = "PATH", conflicts_with_all = ["input", "listing"])]
+	pub watch: Option<PathBuf>,
+
+	/// Download the URLs in a text file, one per line, lines starting with # are skipped
+	#[arg(
+		long,
+		value_name = "PATH",
+		conflicts_with_all = ["input", "listing", "watch", "resume", "exact"]
+	)]
+	pub from_file: Option<PathBuf>,
+
+	/// Remove the cached login credentials and exit
+	#[arg(long, conflicts_with_all = ["input", "listing", "watch"])]
+	pub clear_cache: bool,
+
+	/// Log in to Spotify in the browser to download private playlists and saved tracks, then exit
+	#[arg(long, conflicts_with_all = ["input", "listing", "watch", "clear_cache"])]
+	pub login: bool,
+
+	/// Write the outcome of every download to a JSON report when done
+	#[arg(long, value_name = "PATH", conflicts_with_all = ["listing", "watch"])]
+	pub report: Option<PathBuf>,
+
+	/// Skip tracks listed in this file and add every downloaded track to it
+	#[arg(long, value_name = "PATH", conflicts_with = "listing")]
+	pub download_archive: Option<PathBuf>,
+
+	/// Write a page listing the downloaded tracks with links to their files when done
+	#[arg(long, value_name = "PATH", conflicts_with_all = ["listing", "watch"])]
+	pub html_index: Option<PathBuf>,
+
+	/// Download the tracks of a report that failed or didn't finish, the last queue if no PATH is given
+	#[arg(
+		long,
+		value_name = "PATH",
+		num_args = 0..=1,
+		conflicts_with_all = ["input", "listing", "watch"]
+	)]
+	pub resume: Option<Option<PathBuf>>,
+
+	/// Only download the tracks of the URL that aren't in FOLDER yet, matched by their Spotify id
+	#[arg(
+		long,
+		value_name = "FOLDER",
+		requires = "input",
+		conflicts_with_all = ["listing", "watch", "resume", "exact"]
+	)]
+	pub sync: Option<PathBuf>,
+
+	/// Print the --list or --probe output as JSON, or download progress as JSON lines
+	#[arg(long, conflicts_with = "watch")]
+	pub json: bool,
+
+	/// Only download albums of artists
+	#[arg(long, conflicts_with = "singles_only")]
+	pub albums_only: bool,
+
+	/// Only download singles of artists
+	#[arg(long)]
+	pub singles_only: bool,
+
+	/// Also download compilations of artists
+	#[arg(long)]
+	pub include_compilations: bool,
+
+	/// Also download albums artists appear on
+	#[arg(long)]
+	pub include_appears_on: bool,
+
+	/// Download at most N tracks, dropping the rest of the queue
+	#[arg(long, value_name = "N")]
+	pub limit_total: Option<usize>,
+
+	/// Only download playlist tracks added on or after DATE (YYYY-MM-DD)
+	#[arg(long, value_name = "DATE")]
+	pub since: Option<NaiveDate>,
+
+	/// Skip unavailable tracks instead of reporting them as errors
+	#[arg(long)]
+	pub skip_unavailable: bool,
+
+	/// Download the search result matching title and artist exactly, fail instead of asking
+	#[arg(long, conflicts_with_all = ["listing", "watch"])]
+	pub exact: bool,
+
+	/// Download the first search result instead of asking
+	#[arg(long, conflicts_with_all = ["select", "exact"])]
+	pub first: bool,
+
+	/// Download the Nth search result instead of asking
+	#[arg(
+		long,
+		value_name = "N",
+		value_parser = clap::value_parser!(u64).range(1..),
+		conflicts_with = "exact"
+	)]
+	pub select: Option<u64>,
+
+	/// Log more, -v for info and -vv for debug messages
+	#[arg(short, long, action = ArgAction::Count)]
+	pub verbose: u8,
+
+	/// Download large albums, playlists and artists without asking
+	#[arg(short, long)]
+	pub yes: bool,
+}
+
+impl Args {
+	/// Search result to download without asking, counted from 1
+	pub fn selection(&self) -> Option<usize> {
+		match self.first {
+			true => Some(1),
+			false => self.select.map(|n| n as usize),
+		}
+	}
+
+	/// Merge album group flags into the configured groups
+	pub fn album_groups(&self, mut groups: AlbumGroups) -> AlbumGroups {
+		if self.albums_only || self.singles_only {
+			groups = AlbumGroups {
+				album: self.albums_only,
+				single: self.singles_only,
+				compilation: false,
+				appears_on: false,
+			};
+		}
+		groups.compilation |= self.include_compilations;
+		groups.appears_on |= self.include_appears_on;
+		groups
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use clap::CommandFactory;
+
+	fn parse(args: &[&str]) -> Command {
+		Cli::try_parse_from([&["down_on_spot"], args].concat())
+			.unwrap()
+			.into_command()
+	}
+
+	#[test]
+	fn cli_is_valid() {
+		Cli::command().debug_assert();
+	}
+
+	#[test]
+	fn download_is_default() {
+		for args in [
+			&["https://open.spotify.com/album/x", "-y"][..],
+			&["download", "https://open.spotify.com/album/x", "-y"],
+		] {
+			let Command::Download(download) = parse(args) else {
+				panic!("{:?} isn't a download", args);
+			};
+			assert_eq!(download.input, ["https://open.spotify.com/album/x"]);
+			assert!(download.yes);
+		}
+		let Command::Download(download) = parse(&["never", "gonna"]) else {
+			panic!("search term isn't a download");
+		};
+		assert_eq!(download.input, ["never", "gonna"]);
+		assert!(matches!(parse(&["--login"]), Command::Download(d) if d.login));
+		// Subcommand names after download flags are part of the search term
+		let Command::Download(download) = parse(&["--first", "search", "me"]) else {
+			panic!("search term isn't a download");
+		};
+		assert_eq!(download.input, ["search", "me"]);
+	}
+
+	#[test]
+	fn subcommands() {
+		let Command::Search(search) = parse(&["search", "--json", "never", "gonna"]) else {
+			panic!("not a search");
+		};
+		assert_eq!(search.query, ["never", "gonna"]);
+		assert!(search.json);
+		assert!(matches!(parse(&["queue", "--unfinished"]), Command::Queue(q) if q.unfinished));
+		assert!(matches!(parse(&["config", "--show"]), Command::Config(c) if c.show));
+		assert_eq!(parse(&["search", "-vv", "x"]).log_level(), "debug");
+	}
+
+	#[test]
+	fn missing_arguments() {
+		assert!(Cli::try_parse_from(["down_on_spot", "search"]).is_err());
+		assert!(Cli::try_parse_from(["down_on_spot"]).is_err());
+		assert!(Cli::try_parse_from(["down_on_spot", "--yes"]).is_err());
+	}
+}
@@ -24,12 +24,7 @@ impl AudioConverter {
 		quality: Quality,
 	) -> Result<AudioConverter, SpotifyError> {
 		// Create encoder
-		let bitrate = match quality {
-			Quality::Q320 => 320,
-			Quality::Q256 => 256,
-			Quality::Q160 => 160,
-			Quality::Q96 => 96,
-		};
+		let bitrate = quality.kbps() as i32;
 
 		let mut lame = lame::Lame::new().unwrap();
 
@@ -48,7 +43,10 @@ impl AudioConverter {
 		};
 
 		match format {
-			AudioFormat::Aac => todo!(),
+			AudioFormat::Aac | AudioFormat::Mp4 => Err(LameConverterError(format!(
+				"Decoding {:?} is not supported",
+				format
+			))),
 			// Lewton decoder
 			AudioFormat::Ogg => {
 				let decoder = OggStreamReader::new(ReadWrap::new(Box::new(read)))?;
@@ -1,36 +1,48 @@
-use async_std::channel::{bounded, Receiver, Sender};
+use async_std::channel::{bounded, unbounded, Receiver, Sender};
+use async_std::sync::Mutex;
 use async_stream::try_stream;
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use futures::future::{AbortHandle, AbortRegistration, Abortable};
 use futures::stream::FuturesUnordered;
-use futures::{pin_mut, select, FutureExt, Stream, StreamExt};
+use futures::{pin_mut, select, FutureExt, Stream, StreamExt, TryStreamExt};
 use librespot::audio::{AudioDecrypt, AudioFile};
-use librespot::core::audio_key::AudioKey;
 use librespot::core::session::Session;
-use librespot::core::spotify_id::SpotifyId;
-use librespot::metadata::{FileFormat, Metadata, Track};
-use reqwest::StatusCode;
-use rspotify::clients::BaseClient;
-use rspotify::model::{Id, IdError, TrackId};
+use librespot::core::spotify_id::{FileId, SpotifyId};
+use librespot::metadata::{Episode, FileFormat, Metadata, Track};
+use rspotify::model::{
+	AlbumType, Country, FullAlbum, FullEpisode, Id, Image, RecommendationsAttribute,
+	SimplifiedArtist, TrackId,
+};
 use sanitize_filename::sanitize;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::io::Read;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{stdin, IsTerminal, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::OnceCell;
 
 use crate::converter::AudioConverter;
 use crate::error::SpotifyError;
-use crate::spotify::{Spotify, SpotifyItem};
-use crate::tag::{Field, TagWrap};
+use crate::history::History;
+use crate::lyrics::{BeautifulLyrics, Lrclib, Lyrics, LyricsProvider, LyricsQuery, LyricsSource};
+use crate::report::Report;
+use crate::settings::get_config_folder_path;
+use crate::spotify::{MetadataClient, RequestKind, RequestStats, Spotify, SpotifyItem};
+use crate::tag::{sort_name, CoverType, Field, MultivalueMode, TagWrap};
 
 /// Wrapper for use with UI
 #[derive(Debug, Clone)]
 pub struct Downloader {
 	rx: Receiver<Response>,
 	tx: Sender<Message>,
+	events: Receiver<DownloadEvent>,
 
 	spotify: Spotify,
+	config: DownloaderConfig,
 }
 impl Downloader {
 	/// Create new instance
@@ -38,71 +50,213 @@ impl Downloader {
 		let (tx_0, rx_0) = bounded(1);
 		let (tx_1, rx_1) = bounded(1);
 
+		let (events_tx, events) = unbounded();
+
 		let tx_clone = tx_1.clone();
+		spotify.requests.set_limit(config.requests_per_minute);
 		let spotify_clone = spotify.clone();
+		let config_clone = config.clone();
 		tokio::spawn(async move {
-			communication_thread(config, spotify_clone, rx_1, tx_0, tx_clone).await
+			communication_thread(config_clone, spotify_clone, rx_1, tx_0, tx_clone, events_tx).await
 		});
 		Downloader {
 			rx: rx_0,
 			tx: tx_1,
+			events,
 			spotify,
+			config,
 		}
 	}
 	/// Add item to download queue
 	pub async fn add_to_queue(&self, download: Download) {
-		self.tx
-			.send(Message::AddToQueue(vec![download]))
-			.await
-			.unwrap();
+		self.add_to_queue_multiple(vec![download]).await;
 	}
 
 	/// Add multiple items to queue
-	pub async fn add_to_queue_multiple(&self, downloads: Vec<Download>) {
+	pub async fn add_to_queue_multiple(&self, mut downloads: Vec<Download>) {
+		if let Some(remaining) = self.remaining_capacity().await {
+			if downloads.len() > remaining {
+				warn!(
+					"Track limit of {} reached, {} track(s) dropped.",
+					self.config.limit_total.unwrap_or_default(),
+					downloads.len() - remaining
+				);
+				downloads.truncate(remaining);
+			}
+		}
+		if downloads.is_empty() {
+			return;
+		}
 		self.tx.send(Message::AddToQueue(downloads)).await.unwrap();
 	}
 
+	/// Number of tracks that can still be queued under `limit_total`
+	async fn remaining_capacity(&self) -> Option<usize> {
+		let limit = self.config.limit_total?;
+		Some(limit.saturating_sub(self.get_downloads().await.len()))
+	}
+
+	/// Handle command line arguments, queued one by one if all of them are links,
+	/// otherwise they are joined into a single input
+	pub async fn handle_inputs(
+		&self,
+		inputs: &[String],
+	) -> Result<Option<Vec<SearchResult>>, SpotifyError> {
+		let links = inputs.len() > 1
+			&& inputs
+				.iter()
+				.all(|i| i.starts_with("recommend:") || Spotify::parse_uri(i).is_ok());
+		if !links {
+			return self.handle_input(&inputs.join(" ")).await;
+		}
+		for input in inputs {
+			// One broken link doesn't keep the others from downloading
+			if let Err(e) = self.handle_input(input).await {
+				error!("Queueing {} failed: {}", input, e);
+			}
+		}
+		Ok(None)
+	}
+
 	/// handle input, either link or search
 	pub async fn handle_input(
 		&self,
 		input: &str,
 	) -> Result<Option<Vec<SearchResult>>, SpotifyError> {
+		// The user library is only readable after --login
+		match library_input(input) {
+			Some(Library::LikedSongs) => {
+				let total = self.spotify.saved_tracks(1, 0).await?.total;
+				self.confirm_large("Liked Songs", total as usize)?;
+				let batches = self.spotify.saved_track_batches();
+				self.add_batches_to_queue(batches, Some(total)).await?;
+				return Ok(None);
+			}
+			Some(Library::SavedAlbums) => {
+				self.add_saved_albums().await?;
+				return Ok(None);
+			}
+			None => {}
+		}
+		if let Some(seed) = input.trim().strip_prefix("recommend:") {
+			self.add_recommendations(seed).await?;
+			return Ok(None);
+		}
 		if let Ok(uri) = Spotify::parse_uri(input) {
 			self.add_uri(&uri).await?;
 			Ok(None)
 		} else {
-			let results: Vec<SearchResult> = self
-				.spotify
-				.search(input)
-				.await?
-				.into_iter()
-				.map(SearchResult::from)
-				.collect();
-
+			let results = search_tracks(&self.spotify, input, &self.config).await?;
 			Ok(Some(results))
 		}
 	}
 
+	/// Add recommendations for a track or artist URL or URI to queue
+	pub async fn add_recommendations(&self, seed: &str) -> Result<(), SpotifyError> {
+		let seed = Spotify::parse_uri(seed)?;
+		let config = &self.config.recommendations;
+		let tracks = self
+			.spotify
+			.recommendations(&seed, config.attributes(), config.limit)
+			.await?;
+		info!("Queueing {} recommendations for {}", tracks.len(), seed);
+		self.add_to_queue_multiple(tracks.into_iter().map(|t| t.into()).collect())
+			.await;
+		Ok(())
+	}
+
 	/// Add URL or URI to queue
 	pub async fn add_uri(&self, uri: &str) -> Result<(), SpotifyError> {
 		let uri = Spotify::parse_uri(uri)?;
-		let item = self.spotify.resolve_uri(&uri).await?;
+		let item = self.spotify.resolve_uri(&uri, self.config.market).await?;
 		match item {
 			SpotifyItem::Track(t) => self.add_to_queue(t.into()).await,
 			SpotifyItem::Album(a) => {
-				let tracks = self.spotify.full_album(a.id.id()).await?;
-				let queue: Vec<Download> = tracks.into_iter().map(|t| t.into()).collect();
-				self.add_to_queue_multiple(queue).await;
+				self.confirm_large(&a.name, a.tracks.total as usize)?;
+				// Album tracks don't carry their album, so it's set for per album limits.
+				// Existing tracks are found before queueing, so re-runs show them right away
+				let album_id = a.id.id().to_string();
+				let album = AlbumTags::new(&a, self.config.various_artists_threshold);
+				let batches = self.spotify.album_batches(a.id.id()).map_ok(|batch| {
+					batch
+						.into_iter()
+						.map(|t| {
+							let existing = self.config.skip_existing
+								&& self.config.existing_audio(&TemplateTrack::from(&t), &album);
+							let mut download = Download {
+								album_id: Some(album_id.clone()),
+								..t.into()
+							};
+							if existing {
+								download.state = DownloadState::Skipped(
+									SpotifyError::AlreadyDownloaded.to_string(),
+								);
+							}
+							download
+						})
+						.collect::<Vec<Download>>()
+				});
+				self.add_batches_to_queue(batches, Some(a.tracks.total))
+					.await?;
 			}
 			SpotifyItem::Playlist(p) => {
-				let tracks = self.spotify.full_playlist(p.id.id()).await?;
-				let queue: Vec<Download> = tracks.into_iter().map(|t| t.into()).collect();
-				self.add_to_queue_multiple(queue).await;
+				self.confirm_large(&p.name, p.tracks.total as usize)?;
+				let since = self.config.added_since;
+				let batches = self.spotify.playlist_batches(p.id.id(), since);
+				// The playlist total includes tracks filtered out by date
+				let total = Some(p.tracks.total).filter(|_| since.is_none());
+				self.add_batches_to_queue(batches, total).await?;
 			}
 			SpotifyItem::Artist(a) => {
-				let tracks = self.spotify.full_artist(a.id.id()).await?;
-				let queue: Vec<Download> = tracks.into_iter().map(|t| t.into()).collect();
-				self.add_to_queue_multiple(queue).await;
+				let groups = self.config.album_groups.album_types();
+				// The track count is only known once every album is fetched
+				if self.needs_confirmation() {
+					let tracks = self
+						.spotify
+						.full_artist(a.id.id(), &groups, self.config.max_albums)
+						.await?;
+					self.confirm_large(&a.name, tracks.len())?;
+					self.add_to_queue_multiple(tracks.into_iter().map(|t| t.into()).collect())
+						.await;
+					return Ok(());
+				}
+				let batches =
+					self.spotify
+						.artist_batches(a.id.id(), &groups, self.config.max_albums);
+				self.add_batches_to_queue(batches, None).await?;
+			}
+			SpotifyItem::Episode(e) => self.add_to_queue(e.into()).await,
+			SpotifyItem::Show(s) => {
+				self.confirm_large(&s.name, s.episodes.total as usize)?;
+				// Episodes are numbered by their position in the show, which only the listing knows
+				let show = s.name.clone();
+				let batches = self
+					.spotify
+					.show_episode_batches(s.id.id(), self.config.market)
+					.map_ok(|batch| {
+						batch
+							.into_iter()
+							.map(|(number, e)| Download {
+								id: 0,
+								order: 0,
+								track_id: e.id.id().to_string(),
+								title: e.name,
+								subtitle: show.clone(),
+								state: DownloadState::None,
+								warnings: vec![],
+								timing: DownloadTiming::default(),
+								album_id: None,
+								quality: None,
+								quality_fallback: None,
+								file: None,
+								kind: DownloadKind::Episode {
+									number: Some(number),
+								},
+							})
+							.collect::<Vec<Download>>()
+					});
+				self.add_batches_to_queue(batches, Some(s.episodes.total))
+					.await?;
 			}
 
 			// Unsupported
@@ -114,12 +268,114 @@ impl Downloader {
 		Ok(())
 	}
 
+	/// Queue every track of the albums saved by the user
+	async fn add_saved_albums(&self) -> Result<(), SpotifyError> {
+		// The track count is only known once every album is fetched
+		if self.needs_confirmation() {
+			let tracks = self.spotify.full_saved_albums().await?;
+			self.confirm_large("Saved albums", tracks.len())?;
+			self.add_to_queue_multiple(tracks.into_iter().map(|t| t.into()).collect())
+				.await;
+			return Ok(());
+		}
+		let batches = self.spotify.saved_album_batches();
+		self.add_batches_to_queue(batches, None).await
+	}
+
+	/// Whether large collections are confirmed interactively before queueing
+	fn needs_confirmation(&self) -> bool {
+		self.config.confirm_above.is_some() && !self.config.assume_yes && stdin().is_terminal()
+	}
+
+	/// Ask before queueing a collection with more than `confirm_above` tracks
+	fn confirm_large(&self, name: &str, total: usize) -> Result<(), SpotifyError> {
+		let threshold = self.config.confirm_above.unwrap_or(usize::MAX);
+		if total <= threshold || !self.needs_confirmation() {
+			return Ok(());
+		}
+		println!("{} has {} tracks, download all of them? [y/N]", name, total);
+		let mut answer = String::new();
+		stdin().read_line(&mut answer)?;
+		match answer.trim().to_lowercase().as_str() {
+			"y" | "yes" => Ok(()),
+			_ => Err(SpotifyError::Cancelled),
+		}
+	}
+
+	/// Queue each batch as soon as it is resolved, so downloads start before the whole collection is fetched
+	async fn add_batches_to_queue<T: Into<Download>>(
+		&self,
+		batches: impl Stream<Item = Result<Vec<T>, SpotifyError>>,
+		total: Option<u32>,
+	) -> Result<(), SpotifyError> {
+		pin_mut!(batches);
+		let mut queued = 0;
+		loop {
+			match batches.try_next().await {
+				Ok(Some(batch)) => {
+					queued += batch.len();
+					self.add_to_queue_multiple(batch.into_iter().map(|t| t.into()).collect())
+						.await;
+					// Stop fetching once nothing more can be queued
+					if self.remaining_capacity().await == Some(0) {
+						let dropped = total.map_or(0, |t| (t as usize).saturating_sub(queued));
+						if dropped > 0 {
+							warn!(
+								"Track limit reached, {} remaining track(s) of this collection dropped.",
+								dropped
+							);
+						}
+						return Ok(());
+					}
+				}
+				Ok(None) => return Ok(()),
+				Err(e) => {
+					match total {
+						Some(total) => error!(
+							"Collection expansion failed after queueing {} of {} tracks, {} skipped.",
+							queued,
+							total,
+							(total as usize).saturating_sub(queued)
+						),
+						None => error!(
+							"Collection expansion failed after queueing {} tracks.",
+							queued
+						),
+					}
+					return Err(e);
+				}
+			}
+		}
+	}
+
 	/// Get all downloads
 	pub async fn get_downloads(&self) -> Vec<Download> {
 		self.tx.send(Message::GetDownloads).await.unwrap();
 		let Response::Downloads(d) = self.rx.recv().await.unwrap();
 		d
 	}
+
+	/// Stop a queued or downloading track, it ends up skipped as cancelled
+	pub async fn cancel(&self, id: i64) {
+		self.tx.send(Message::Cancel(id)).await.unwrap();
+	}
+
+	/// Queue a failed or cancelled track again
+	pub async fn retry(&self, id: i64) {
+		self.tx.send(Message::Retry(id)).await.unwrap();
+	}
+
+	/// Events of the download queue, for post processing once a whole album is done
+	pub fn events(&self) -> Receiver<DownloadEvent> {
+		self.events.clone()
+	}
+
+	/// Totals over all downloads of the session
+	pub async fn stats(&self) -> DownloadStats {
+		let mut stats = DownloadStats::from(self.get_downloads().await.as_slice());
+		stats.requests = self.spotify.requests.stats();
+		stats
+	}
 }
 
 async fn communication_thread(
@@ -128,6 +384,7 @@ async fn communication_thread(
 	rx: Receiver<Message>,
 	tx: Sender<Response>,
 	self_tx: Sender<Message>,
+	events_tx: Sender<DownloadEvent>,
 ) {
 	// Downloader
 	let downloader = DownloaderInternal::new(spotify.clone(), self_tx.clone());
@@ -137,18 +394,44 @@ async fn communication_thread(
 	});
 	let mut waiting_for_job = false;
 	let mut queue: Vec<Download> = vec![];
+	// Handles to abort the downloads the worker is running
+	let mut running: HashMap<i64, AbortHandle> = HashMap::new();
+	// Messages the thread sends itself, handled before the next received one
+	let mut pending: VecDeque<Message> = VecDeque::new();
+	// Times each download was queued again after failing
+	let mut requeued: HashMap<i64, u32> = HashMap::new();
+	let mut next_id = 0;
+	// Unfinished downloads per album id, an album is complete once it drops to zero
+	let mut album_pending: HashMap<String, usize> = HashMap::new();
+	let history_file = config
+		.history_file
+		.clone()
+		.unwrap_or_else(|| get_config_folder_path().join("history.txt"));
+	let mut history = match config.history {
+		true => match History::load(&history_file).await {
+			Ok(history) => Some(history),
+			Err(e) => {
+				warn!("Loading download history failed, not resuming: {}", e);
+				None
+			}
+		},
+		false => None,
+	};
 
 	// Receive messages
-	while let Ok(msg) = rx.recv().await {
+	loop {
+		let msg = match pending.pop_front() {
+			Some(msg) => msg,
+			None => match rx.recv().await {
+				Ok(msg) => msg,
+				Err(_) => break,
+			},
+		};
 		match msg {
 			// Send job to worker thread
 			Message::GetJob => {
-				if let Some(d) = queue.iter_mut().find(|i| i.state == DownloadState::None) {
-					d.state = DownloadState::Lock;
-					downloader_tx
-						.send(DownloaderMessage::Job(d.clone().into(), config.clone()))
-						.await
-						.unwrap();
+				if let Some(d) = next_download(&mut queue, &config) {
+					dispatch(d, &config, &downloader_tx, &mut running).await;
 					waiting_for_job = false;
 				} else {
 					waiting_for_job = true;
@@ -157,36 +440,187 @@ async fn communication_thread(
 			// Update state of download
 			Message::UpdateState(id, state) => {
 				let i = queue.iter().position(|i| i.id == id).unwrap();
-				queue[i].state = state.clone();
-				if state == DownloadState::Done {
-					queue.remove(i);
+				// Record timing from the state transitions reported by the worker
+				let timing = &mut queue[i].timing;
+				match &state {
+					DownloadState::Downloading(read, _) => {
+						timing.started.get_or_insert_with(SystemTime::now);
+						timing.bytes = *read;
+					}
+					DownloadState::Done
+					| DownloadState::Untagged(_)
+					| DownloadState::Error(_)
+					| DownloadState::Skipped(_) => {
+						timing.finished = Some(SystemTime::now());
+					}
+					_ => {}
+				}
+				// Untagged files exist too, downloading them again wouldn't fix the tags
+				if let (true, Some(history)) = (state.is_success(), history.as_mut()) {
+					if let Err(e) = history.record(&queue[i].track_id).await {
+						warn!("Recording {} in history failed: {}", queue[i].track_id, e);
+					}
+				}
+				let finished = !state.is_active();
+				if state.is_finished() {
+					running.remove(&id);
+				}
+				let album_done = match (&queue[i].album_id, state.is_finished()) {
+					(Some(album_id), true) if !queue[i].state.is_finished() => {
+						album_pending.get_mut(album_id).is_some_and(|pending| {
+							*pending -= 1;
+							*pending == 0
+						})
+					}
+					_ => false,
+				};
+				queue[i].state = state;
+				if queue[i].state.is_finished() {
+					write_journal(&queue, &config).await;
+				}
+				if album_done {
+					let album_id = queue[i].album_id.clone().unwrap();
+					album_pending.remove(&album_id);
+					let tracks: Vec<Download> = queue
+						.iter()
+						.filter(|d| d.album_id.as_ref() == Some(&album_id))
+						.cloned()
+						.collect();
+					// Written before the album is reported complete, so the files are final by then
+					if config.compute_replaygain_album {
+						write_album_replaygain(&album_id, &tracks, &config).await;
+					}
+					events_tx
+						.send(DownloadEvent::AlbumComplete { album_id, tracks })
+						.await
+						.ok();
+				}
+				// Downloads held back by the per album limit may be dispatched now
+				if waiting_for_job && finished && config.per_album_concurrency.is_some() {
+					if let Some(d) = next_download(&mut queue, &config) {
+						dispatch(d, &config, &downloader_tx, &mut running).await;
+						waiting_for_job = false;
+					}
+				}
+			}
+			Message::AddWarning(id, warning) => {
+				if let Some(d) = queue.iter_mut().find(|i| i.id == id) {
+					d.warnings.push(warning);
+				}
+			}
+			Message::SetQuality(id, quality, fallback) => {
+				if let Some(d) = queue.iter_mut().find(|i| i.id == id) {
+					d.quality = Some(quality);
+					d.quality_fallback = fallback;
+				}
+			}
+			Message::SetFile(id, file) => {
+				if let Some(d) = queue.iter_mut().find(|i| i.id == id) {
+					d.file = Some(file);
 				}
 			}
 			Message::AddToQueue(download) => {
-				// Assign new IDs and reset state
-				let mut id = queue.iter().map(|i| i.id).max().unwrap_or(0);
-				let downloads: Vec<Download> = download
+				// Assign new IDs, states are kept for tracks that can't or needn't be downloaded
+				let mut downloads: Vec<Download> = download
 					.into_iter()
 					.map(|mut d| {
-						d.id = id;
-						d.state = DownloadState::None;
-						id += 1;
+						d.id = next_id;
+						d.order = next_id as usize;
+						next_id += 1;
 						d
 					})
 					.collect();
+				// Skip tracks finished in an earlier run, wherever their files are now
+				if let Some(history) = &history {
+					let mut skipped = 0;
+					for d in &mut downloads {
+						if history.contains(&d.track_id) {
+							d.state =
+								DownloadState::Skipped(SpotifyError::AlreadyDownloaded.to_string());
+							skipped += 1;
+						}
+					}
+					if skipped > 0 {
+						info!("{} track(s) already in download history, skipped.", skipped);
+					}
+				}
+				for d in downloads.iter().filter(|d| !d.state.is_finished()) {
+					if let Some(album_id) = &d.album_id {
+						*album_pending.entry(album_id.clone()).or_default() += 1;
+					}
+				}
 				queue.extend(downloads);
+				write_journal(&queue, &config).await;
 				// Update worker threads if locked
 				if waiting_for_job {
-					let d = queue
-						.iter_mut()
-						.find(|i| i.state == DownloadState::None)
-						.unwrap();
-					d.state = DownloadState::Lock;
-					downloader_tx
-						.send(DownloaderMessage::Job(d.clone().into(), config.clone()))
-						.await
-						.unwrap();
-					waiting_for_job = false;
+					if let Some(d) = next_download(&mut queue, &config) {
+						dispatch(d, &config, &downloader_tx, &mut running).await;
+						waiting_for_job = false;
+					}
+				}
+			}
+			Message::Cancel(id) => {
+				let Some(d) = queue.iter().find(|d| d.id == id) else {
+					continue;
+				};
+				match d.state {
+					// Never handed to the worker, so it is finished right away
+					DownloadState::None => pending.push_back(Message::UpdateState(
+						id,
+						DownloadState::Skipped(SpotifyError::Cancelled.to_string()),
+					)),
+					// The worker reports the cancelled job as skipped
+					DownloadState::Lock | DownloadState::Downloading(..) => {
+						if let Some(handle) = running.get(&id) {
+							handle.abort();
+						}
+					}
+					_ => {}
+				}
+			}
+			Message::Requeue(id, reason) => {
+				let count = requeued.entry(id).or_default();
+				if *count >= config.requeue_attempts {
+					pending.push_back(Message::UpdateState(id, DownloadState::Error(reason)));
+					continue;
+				}
+				*count += 1;
+				let Some(i) = queue.iter().position(|d| d.id == id) else {
+					continue;
+				};
+				// Behind the other queued downloads, giving the connection time to recover
+				let mut d = queue.remove(i);
+				d.state = DownloadState::None;
+				d.timing = DownloadTiming::default();
+				d.warnings.push(format!("Queued again: {}", reason));
+				queue.push(d);
+				running.remove(&id);
+				if waiting_for_job {
+					if let Some(d) = next_download(&mut queue, &config) {
+						dispatch(d, &config, &downloader_tx, &mut running).await;
+						waiting_for_job = false;
+					}
+				}
+			}
+			Message::Retry(id) => {
+				let Some(d) = queue.iter_mut().find(|d| d.id == id && d.state.can_retry()) else {
+					continue;
+				};
+				d.state = DownloadState::None;
+				d.timing = DownloadTiming::default();
+				d.warnings.clear();
+				d.quality = None;
+				d.quality_fallback = None;
+				d.file = None;
+				if let Some(album_id) = &d.album_id {
+					*album_pending.entry(album_id.clone()).or_default() += 1;
+				}
+				write_journal(&queue, &config).await;
+				if waiting_for_job {
+					if let Some(d) = next_download(&mut queue, &config) {
+						dispatch(d, &config, &downloader_tx, &mut running).await;
+						waiting_for_job = false;
+					}
 				}
 			}
 			Message::GetDownloads => {
@@ -196,16 +630,55 @@ async fn communication_thread(
 	}
 }
 
+/// Hand download to the worker, keeping the handle to cancel it
+async fn dispatch(
+	download: &mut Download,
+	config: &DownloaderConfig,
+	downloader_tx: &Sender<DownloaderMessage>,
+	running: &mut HashMap<i64, AbortHandle>,
+) {
+	download.state = DownloadState::Lock;
+	let (handle, registration) = AbortHandle::new_pair();
+	running.insert(download.id, handle);
+	downloader_tx
+		.send(DownloaderMessage::Job(
+			download.clone().into(),
+			config.clone(),
+			registration,
+		))
+		.await
+		.unwrap();
+}
+
+/// Save the queue for `--resume`, a failed write only loses the ability to resume
+async fn write_journal(queue: &[Download], config: &DownloaderConfig) {
+	if !config.queue_journal {
+		return;
+	}
+	let path = Report::journal_path();
+	if let Err(e) = Report::from(queue).save(&path).await {
+		warn!("Writing queue journal {} failed: {}", path.display(), e);
+	}
+}
+
 /// Spotify downloader
 pub struct DownloaderInternal {
 	spotify: Spotify,
 	pub tx: Sender<DownloaderMessage>,
 	rx: Receiver<DownloaderMessage>,
 	event_tx: Sender<Message>,
+	beautiful_lyrics: BeautifulLyrics,
+	/// Album folders created in this run, so per album files are written once
+	album_folders: Mutex<HashSet<PathBuf>>,
+	/// Album level tags by album id, so every track of an album gets the same ones
+	album_tags: Mutex<HashMap<String, Arc<OnceCell<Arc<AlbumTags>>>>>,
+	/// Folders whose cover file was handled in this run
+	cover_folders: Mutex<HashSet<PathBuf>>,
 }
 
 pub enum DownloaderMessage {
-	Job(DownloadJob, DownloaderConfig),
+	/// Job with the registration it is aborted through when cancelled
+	Job(DownloadJob, DownloaderConfig, AbortRegistration),
 }
 
 impl DownloaderInternal {
@@ -217,6 +690,10 @@ impl DownloaderInternal {
 			tx,
 			rx,
 			event_tx,
+			beautiful_lyrics: BeautifulLyrics::new(),
+			album_folders: Mutex::new(HashSet::new()),
+			album_tags: Mutex::new(HashMap::new()),
+			cover_folders: Mutex::new(HashSet::new()),
 		}
 	}
 
@@ -229,20 +706,20 @@ impl DownloaderInternal {
 		loop {
 			select! {
 				job = job_future => {
-					if let Some((job, config)) = job {
+					if let Some((job, config, abort)) = job {
 						if tasks.len() < config.concurrent_downloads {
-							tasks.push(self.download_job_wrapper(job.clone(), config).boxed())
+							tasks.push(self.download_job_wrapper(job, config, abort).boxed())
 						} else {
-							queue.push((job, config));
+							queue.push((job, config, abort));
 						}
 					}
 					job_future = Box::pin(self.get_job()).fuse();
 				},
 				// Task finished
 				() = tasks.select_next_some() => {
-					if let Some((job, config)) = queue.first() {
-						tasks.push(self.download_job_wrapper(job.clone(), config.clone()).boxed());
-						queue.remove(0);
+					if !queue.is_empty() {
+						let (job, config, abort) = queue.remove(0);
+						tasks.push(self.download_job_wrapper(job, config, abort).boxed());
 					}
 				}
 			};
@@ -250,27 +727,71 @@ impl DownloaderInternal {
 	}
 
 	// Get job from parent
-	async fn get_job(&self) -> Option<(DownloadJob, DownloaderConfig)> {
+	async fn get_job(&self) -> Option<(DownloadJob, DownloaderConfig, AbortRegistration)> {
 		self.event_tx.send(Message::GetJob).await.unwrap();
 		match self.rx.recv().await.ok()? {
-			DownloaderMessage::Job(job, config) => Some((job, config)),
+			DownloaderMessage::Job(job, config, abort) => Some((job, config, abort)),
 		}
 	}
 
 	/// Wrapper for download_job for error handling
-	async fn download_job_wrapper(&self, job: DownloadJob, config: DownloaderConfig) {
+	async fn download_job_wrapper(
+		&self,
+		job: DownloadJob,
+		config: DownloaderConfig,
+		abort: AbortRegistration,
+	) {
 		let track_id = job.track_id.clone();
 		let id = job.id;
 		let num_downloads = config.concurrent_downloads;
-		match self.download_job(job, config).await {
+		let skip_unavailable = config.skip_unavailable;
+		let max_requests_per_min = config.requests_per_minute as f32;
+		let result = Abortable::new(self.download_job(job, config), abort)
+			.await
+			.unwrap_or(Err(SpotifyError::Cancelled));
+		match result {
 			Ok(_) => {}
+			// Region locked tracks are expected in albums, so they don't count as failures
+			Err(e @ (SpotifyError::Unavailable | SpotifyError::NoDownloadableFile))
+				if skip_unavailable =>
+			{
+				info!("Skipping unavailable track {}: {}", track_id, e);
+				self.event_tx
+					.send(Message::UpdateState(
+						id,
+						DownloadState::Skipped(e.to_string()),
+					))
+					.await
+					.unwrap();
+			}
+			Err(
+				e @ (SpotifyError::AlreadyDownloaded
+				| SpotifyError::SkippedFormat(_)
+				| SpotifyError::Cancelled),
+			) => {
+				info!("Skipping track {}: {}", track_id, e);
+				self.event_tx
+					.send(Message::UpdateState(
+						id,
+						DownloadState::Skipped(e.to_string()),
+					))
+					.await
+					.unwrap();
+			}
+			// Likely to work on another try, it fails once queued too often
+			Err(e @ (SpotifyError::AudioFileTimeout | SpotifyError::DurationMismatch(_))) => {
+				warn!("Download job for track {} failed: {}", track_id, e);
+				self.event_tx
+					.send(Message::Requeue(id, e.to_string()))
+					.await
+					.unwrap();
+			}
 			Err(e) => {
 				error!("Download job for track {} failed. {:?}", track_id, e);
 				// taken from here: 
 					// https://community.spotify.com/t5/Spotify-for-Developers/Web-API-ratelimit/m-p/5503153/highlight/true#M7931
 				// which references this: 
 					// https://medium.com/mendix/limiting-your-amount-of-calls-in-mendix-most-of-the-time-rest-835dde55b10e#:~:text=The%20Spotify%20API%20service%20has,for%2060%20requests%20per%20minute
-				let max_requests_per_min = 60.0;
 				let timeout:u64 = ((((1.0/(max_requests_per_min/60.0)) * 1000.0) * (num_downloads as f32)) as f32) as u64;
 				// Limit the amount of requests to not get API timed out with HTTP status code 429 responses
 				// could also integrate the retry_after time the HTTP status code 429 response gives (IDK if RSpotify gives this on error)
@@ -292,120 +813,65 @@ impl DownloaderInternal {
 		job: DownloadJob,
 		config: DownloaderConfig,
 	) -> Result<(), SpotifyError> {
-		self.spotify.spotify.request_token().await?;
-		// Fetch metadata
-		let _trash = TrackId::from_id(&job.track_id);
-		if _trash == Err(IdError::InvalidId){
-			return Err(SpotifyError::Unavailable);
+		self.spotify.ensure_token().await?;
+		if let DownloadKind::Episode { number } = job.kind {
+			return self.download_episode_job(job, number, config).await;
 		}
+		// Fetch metadata, malformed ids fail here instead of panicking further down
+		let track_id = TrackId::from_id(&job.track_id)?;
+		let track = self.spotify.track(track_id.id()).await?;
+		let album_id = track.album.id.as_ref().ok_or(SpotifyError::Unavailable)?;
+		let album = self.album_tags(album_id.id(), &config).await?;
+		let album = album.as_ref();
+		let template_track = TemplateTrack::from(&track);
+		let title = template_track.title(config.normalize_feat);
+		let (_, mut lyrics_file) = config.track_paths(&template_track, album, config.quality);
 
-		let track = self
-			.spotify
-			.spotify
-			.track(TrackId::from_id(&job.track_id).unwrap(), None)
-			.await?;
-		let album = self
-			.spotify
-			.spotify
-			.album(track.album.id.unwrap(), None)
+		// Download, the path is only known once the quality is
+		let path_stem = |quality| config.track_paths(&template_track, album, quality).0;
+		let (path, format, normalisation) = self
+			.download_track(&job.track_id, job.kind, path_stem, config.clone(), job.id)
 			.await?;
-
-		let tags: Vec<(&str, String)> = vec![
-			("%title%", sanitize(&track.name)),
-			(
-				"%artist%",
-				sanitize(
-					track
-						.artists
-						.iter()
-						.map(|a| a.name.as_str())
-						.collect::<Vec<&str>>()
-						.first()
-						.unwrap_or(&""),
-				),
-			),
-			(
-				"%artists%",
-				sanitize(
-					track
-						.artists
-						.iter()
-						.map(|a| a.name.as_str())
-						.collect::<Vec<&str>>()
-						.join(", "),
-				),
-			),
-			("%track%", track.track_number.to_string()),
-			("%0track%", format!("{:02}", track.track_number)),
-			("%disc%", track.disc_number.to_string()),
-			("%0disc%", format!("{:02}", track.disc_number)),
-			("%id%", job.track_id.to_string()),
-			("%album%", sanitize(&track.album.name)),
-			(
-				"%albumArtist%",
-				sanitize(
-					track
-						.album
-						.artists
-						.iter()
-						.map(|a| a.name.as_str())
-						.collect::<Vec<&str>>()
-						.first()
-						.unwrap_or(&""),
-				),
-			),
-			(
-				"%albumArtists%",
-				sanitize(
-					track
-						.album
-						.artists
-						.iter()
-						.map(|a| a.name.as_str())
-						.collect::<Vec<&str>>()
-						.join(", "),
-				),
-			),
-		];
-
-		let mut filename_template = config.filename_template.clone();
-		let mut path_template = config.path.clone();
-		for (tag, value) in tags {
-			filename_template = filename_template.replace(tag, &value);
-			path_template = path_template.replace(tag, &value);
+		if config.verify_duration {
+			let expected = track.duration.to_std().unwrap_or_default();
+			self.verify_duration(&job, &path, &format, expected, &config)
+				.await?;
+		}
+		// Renamed files keep their lyrics next to them under the same name
+		if config.lyrics_path.is_none() {
+			lyrics_file = path.with_extension(&config.lyrics_extension);
 		}
-		let path_stem = Path::new(&path_template).join(&filename_template);
-
-		tokio::fs::create_dir_all(path_stem.parent().unwrap()).await?;
-
-		// Download
-		let (path, format) = DownloaderInternal::download_track(
-			&self.spotify.session,
-			&job.track_id,
-			&path_stem,
-			config.clone(),
-			self.event_tx.clone(),
-			job.id,
-		)
-		.await?;
 		// Post processing
 		self.event_tx
 			.send(Message::UpdateState(job.id, DownloadState::Post))
 			.await
 			.ok();
 
-		// Download cover
-		let mut cover = None;
-		if let Some(image) = track.album.images.first() {
-			match DownloaderInternal::download_cover(&image.url).await {
-				Ok(c) => cover = Some(c),
-				Err(e) => warn!("Failed downloading cover! {}", e),
+		// Download cover, not needed for untagged raw passthrough or untaggable formats.
+		// The cover file is written by the first track of each folder only
+		let taggable = !config.raw_passthrough && TagWrap::supports(&format);
+		let embed = taggable && config.embed_cover;
+		let cover_folder = match config.save_cover_file {
+			true => self.claim_cover_folder(path.parent().unwrap()).await,
+			false => None,
+		};
+		let mut covers = vec![];
+		if embed || cover_folder.is_some() {
+			if let Some(cover) = self.download_front_cover(&album.images, &config).await {
+				if let Some(folder) = cover_folder {
+					DownloaderInternal::save_cover_file(&folder, &cover, &config).await;
+				}
+				if embed {
+					covers.push((CoverType::Front, cover));
+				}
 			}
 		}
+		if embed && config.extra_covers {
+			covers.extend(self.download_extra_covers(&album.images).await);
+		}
 
-		let tags = vec![
-			(Field::Title, vec![track.name.to_string()]),
-			(Field::Album, vec![track.album.name.to_string()]),
+		let mut tags = vec![
+			(Field::Title, vec![title]),
 			(
 				Field::Artist,
 				track
@@ -414,198 +880,480 @@ impl DownloaderInternal {
 					.map(|a| a.name.to_string())
 					.collect::<Vec<String>>(),
 			),
-			(
-				Field::AlbumArtist,
-				track
-					.album
-					.artists
-					.iter()
-					.map(|a| a.name.to_string())
-					.collect::<Vec<String>>(),
-			),
 			(Field::TrackNumber, vec![track.track_number.to_string()]),
 			(Field::DiscNumber, vec![track.disc_number.to_string()]),
-			(Field::Genre, album.genres.clone()),
-			(Field::Label, vec![album.label.unwrap().to_string()]),
+			(Field::SpotifyId, vec![job.track_id.clone()]),
 		];
-		let date = album.release_date;
+		tags.extend(album.fields());
+		if config.generate_sort_tags {
+			tags.extend(sort_tags(&tags, &config.sort_articles));
+		}
+		tags.retain(|(field, _)| config.tag_fields.contains(field));
+		// Spotify only knows the album release date, unless overridden per album
+		let original_date = config.write_original_date.then(|| {
+			config
+				.original_date_overrides
+				.get(&album.id)
+				.cloned()
+				.unwrap_or_else(|| album.release_date.clone())
+		});
+		let original_date = original_date.and_then(|d| taggable_date(&d));
+		let date = taggable_date(&album.release_date);
 
-		let download_lrc = config.download_lrc;
-		let sp_dc = &config.sp_dc;
-		let enhanced_lrc = config.enhanced_lrc;
+		let lyrics_query = LyricsQuery {
+			id: track.id.as_ref().map(|id| id.id().to_string()),
+			isrc: track.external_ids.get("isrc").cloned(),
+			artist: first_artist(&track.artists).to_string(),
+			title: track.name.clone(),
+			album: album.name.clone(),
+			duration: track.duration.num_seconds(),
+		};
 
-		// Write tags
-		let config = config.clone();
-		tokio::task::spawn_blocking(move || {
-			DownloaderInternal::write_tags(path, format, tags, date, cover, config)
-		})
-		.await??;
-
-		// Download LRC
-		if download_lrc {
-			DownloaderInternal::download_lrc(
-				path_stem,
-				track.id.unwrap().id(),
-				sp_dc,
-				enhanced_lrc,
-			)
-			.await?;
+		// Lyrics are fetched once for both the LRC file and the tags.
+		// They are supplementary, so failures don't fail the job
+		let write_lrc = config.download_lrc && !(config.skip_existing && lyrics_file.is_file());
+		if config.download_lrc && !write_lrc {
+			info!("Lyrics already exist: {}", lyrics_file.display());
+		}
+		let embed_lyrics = config.embed_lyrics && taggable;
+		let lyrics = match write_lrc || embed_lyrics {
+			true => match self.fetch_lyrics(&lyrics_query, &config).await {
+				Ok(lyrics) => Some(lyrics),
+				Err(e) => {
+					self.lyrics_warning(&job, e).await;
+					None
+				}
+			},
+			false => None,
+		};
+		if let (true, Some(lyrics)) = (write_lrc, &lyrics) {
+			if let Err(e) = lyrics.write_lrc(&lyrics_file, &lyrics_query, &config).await {
+				self.lyrics_warning(&job, e).await;
+			}
 		}
 
-		// Done
-		self.event_tx
-			.send(Message::UpdateState(job.id, DownloadState::Done))
-			.await
-			.ok();
-		Ok(())
-	}
-
-	/// Download cover, returns mime and data
-	async fn download_cover(url: &str) -> Result<(String, Vec<u8>), SpotifyError> {
-		let res = reqwest::get(url).await?;
-		let mime = res
-			.headers()
-			.get("content-type")
-			.ok_or_else(|| SpotifyError::Error("Missing cover mime!".into()))?
-			.to_str()
-			.unwrap()
-			.to_string();
-		let data = res.bytes().await?.to_vec();
-		Ok((mime, data))
+		// Write tags, the audio is already on disk so failures only warn unless strict
+		let tagged_path = path.clone();
+		let tagged_format = format.clone();
+		let tag_config = config.clone();
+		let tagged = match taggable {
+			// Raw passthrough keeps the stream exactly as Spotify delivered it,
+			// untaggable formats were already warned about before downloading
+			false => Ok(()),
+			true => {
+				let metadata = TrackMetadata {
+					fields: tags,
+					date,
+					original_date,
+					replaygain: normalisation.filter(|_| config.compute_replaygain_album),
+					covers,
+					lyrics: lyrics
+						.filter(|_| embed_lyrics)
+						.map(|l| l.to_lrc(&lyrics_query, &config)),
+				};
+				DownloaderInternal::tag_file(path, format, metadata, tag_config).await
+			}
+		};
+		// Kept until the album completes, only files carrying the track gain can get album gain
+		let loudness = match (taggable, normalisation) {
+			(true, Some(n)) if config.compute_replaygain_album => Some(Loudness {
+				gain_db: n.track_gain_db,
+				peak: n.track_peak,
+				seconds: track.duration.num_milliseconds() as f64 / 1000.0,
+				album_tracks: album.total_tracks as usize,
+			}),
+			_ => None,
+		};
+		let file = SavedFile {
+			path: tagged_path,
+			format: tagged_format,
+			album: album.name.to_string(),
+			loudness,
+		};
+		self.finish_job(job.id, file, tagged, &config).await
 	}
 
-	// Download synced lyrics from surfbryce's backend and save as LRC format
-	async fn download_lrc(
-		path: impl AsRef<Path>,
-		id: &str,
-		sp_dc: &str,
-		enhanced_lrc: bool,
+	/// Download and tag a podcast episode, its show takes the place of the album
+	async fn download_episode_job(
+		&self,
+		job: DownloadJob,
+		number: Option<u32>,
+		config: DownloaderConfig,
 	) -> Result<(), SpotifyError> {
-		let url = format!("https://beautiful-lyrics.socalifornian.live/lyrics/{}", id);
-		let client = reqwest::Client::new();
-
-		let token_res = client
-			.get("https://open.spotify.com/get_access_token")
-            .header("Accept", "application/json")
-            .header("User-Agent", "User-Agent: Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/101.0.0.0 Safari/537.36")
-			.header("Cookie", format!("sp_dc={}", sp_dc))
-			.send()
+		let episode = self.spotify.episode(&job.track_id, config.market).await?;
+		let show = AlbumTags::show(&episode);
+		let publisher = [SimplifiedArtist {
+			name: episode.show.publisher.clone(),
+			..Default::default()
+		}];
+		let template_track = TemplateTrack {
+			id: job.track_id.clone(),
+			name: &episode.name,
+			artists: &publisher,
+			track_number: number.unwrap_or_default(),
+			disc_number: 1,
+			kind: job.kind,
+		};
+
+		let path_stem = |quality| config.track_paths(&template_track, &show, quality).0;
+		let (path, format, normalisation) = self
+			.download_track(&job.track_id, job.kind, path_stem, config.clone(), job.id)
 			.await?;
+		self.event_tx
+			.send(Message::UpdateState(job.id, DownloadState::Post))
+			.await
+			.ok();
 
-		if token_res.status() != StatusCode::OK {
-			return Err(SpotifyError::Error(format!(
-				"Failed to get token! {}",
-				token_res.status(),
-			)));
+		let taggable = !config.raw_passthrough && TagWrap::supports(&format);
+		let embed = taggable && config.embed_cover;
+		let cover_folder = match config.save_cover_file {
+			true => self.claim_cover_folder(path.parent().unwrap()).await,
+			false => None,
+		};
+		let mut covers = vec![];
+		if embed || cover_folder.is_some() {
+			if let Some(cover) = self.download_front_cover(&show.images, &config).await {
+				if let Some(folder) = cover_folder {
+					DownloaderInternal::save_cover_file(&folder, &cover, &config).await;
+				}
+				if embed {
+					covers.push((CoverType::Front, cover));
+				}
+			}
 		}
 
-		let token: Value = serde_json::from_str(&token_res.text().await?).unwrap();
+		let mut tags = vec![
+			(Field::Title, vec![episode.name.clone()]),
+			(Field::Artist, show.artists.clone()),
+			(Field::Album, vec![show.name.clone()]),
+			(Field::AlbumArtist, show.artists.clone()),
+			(Field::Genre, vec!["Podcast".to_string()]),
+			(Field::SpotifyId, vec![job.track_id.clone()]),
+		];
+		if let Some(number) = number {
+			tags.push((Field::TrackNumber, vec![number.to_string()]));
+		}
+		if config.generate_sort_tags {
+			tags.extend(sort_tags(&tags, &config.sort_articles));
+		}
+		tags.retain(|(field, _)| config.tag_fields.contains(field));
 
-		let lyrics = client
-			.get(url)
-			.header(
-				"Authorization",
-				format!("Bearer {}", token["accessToken"].as_str().unwrap()),
-			)
-			.send()
-			.await?;
+		let tagged = match taggable {
+			false => Ok(()),
+			true => {
+				let metadata = TrackMetadata {
+					fields: tags,
+					date: taggable_date(&show.release_date),
+					original_date: None,
+					replaygain: normalisation.filter(|_| config.compute_replaygain_album),
+					covers,
+					lyrics: None,
+				};
+				DownloaderInternal::tag_file(path.clone(), format.clone(), metadata, config.clone())
+					.await
+			}
+		};
+		let file = SavedFile {
+			path,
+			format,
+			album: show.name,
+			loudness: None,
+		};
+		self.finish_job(job.id, file, tagged, &config).await
+	}
 
-		if lyrics.content_length().unwrap() == 0
-			|| lyrics.status() == StatusCode::INTERNAL_SERVER_ERROR
-		{
-			warn!("Lyrics not found!");
-			return Ok(());
-		} else if lyrics.status() != StatusCode::OK {
-			return Err(SpotifyError::Error(format!(
-				"Failed to fetch lyrics! {}",
-				lyrics.status()
-			)));
-		}
-
-		let lyric_json: Value = serde_json::from_str(&lyrics.text().await?).unwrap();
-
-		// Convert response JSON to LRC
-		let mut lrc_text = String::new();
-		match lyric_json["Type"].as_str().unwrap() {
-			"Syllable" => {
-				for line in lyric_json["Content"].as_array().unwrap() {
-					let line_ts = (line["Lead"]["StartTime"].as_f64().unwrap() * 1000.0) as u64;
-					let line_ts_min = line_ts / 60000;
-					let line_ts_sec = (line_ts % 60000) / 1000;
-					let line_ts_ms = (line_ts % 1000) / 10; // Truncated to 2 digits
-
-					lrc_text.push_str(&format!(
-						"[{:02}:{:02}.{:02}]",
-						line_ts_min, line_ts_sec, line_ts_ms
-					));
-					for syllable in line["Lead"]["Syllables"].as_array().unwrap() {
-						let syllable_ts = (syllable["StartTime"].as_f64().unwrap() * 1000.0) as u64;
-						let syllable_ts_min = syllable_ts / 60000;
-						let syllable_ts_sec = (syllable_ts % 60000) / 1000;
-						let syllable_ts_ms = (syllable_ts % 100) / 10;
-
-						// Add syllable timestamps if enhanced lrc is enabled
-						if enhanced_lrc {
-							lrc_text.push_str(&format!(
-								"<{:02}:{:02}.{:02}>",
-								syllable_ts_min, syllable_ts_sec, syllable_ts_ms,
-							));
-						}
+	/// Write tags in a blocking task, a panic while tagging is a tagging failure too
+	async fn tag_file(
+		path: PathBuf,
+		format: AudioFormat,
+		metadata: TrackMetadata,
+		config: DownloaderConfig,
+	) -> Result<(), SpotifyError> {
+		tokio::task::spawn_blocking(move || {
+			DownloaderInternal::write_tags(path, format, metadata, config)
+		})
+		.await
+		.unwrap_or_else(|e| Err(e.into()))
+	}
 
-						lrc_text.push_str(syllable["Text"].as_str().unwrap());
+	/// Report the saved file and the final state. The audio is on disk already,
+	/// so tagging failures only fail the job with `strict_tagging`
+	async fn finish_job(
+		&self,
+		job_id: i64,
+		mut file: SavedFile,
+		tagged: Result<(), SpotifyError>,
+		config: &DownloaderConfig,
+	) -> Result<(), SpotifyError> {
+		let untagged = match tagged {
+			Ok(_) => None,
+			Err(e) if config.strict_tagging => return Err(e),
+			Err(e) => {
+				warn!("Tagging {} failed: {}", file.path.display(), e);
+				Some(e.to_string())
+			}
+		};
+		if untagged.is_some() {
+			file.loudness = None;
+		}
+		self.event_tx
+			.send(Message::SetFile(job_id, file))
+			.await
+			.ok();
 
-						if !syllable["IsPartOfWord"].as_bool().unwrap() {
-							lrc_text.push(' ');
-						}
-					}
+		// Done, the untagged audio file is kept
+		let state = match untagged {
+			Some(e) => DownloadState::Untagged(e),
+			None => DownloadState::Done,
+		};
+		self.event_tx
+			.send(Message::UpdateState(job_id, state))
+			.await
+			.ok();
+		Ok(())
+	}
 
-					lrc_text.push('\n');
-				}
+	/// Download cover, dimensions are read from the image header if possible
+	pub async fn download_cover(url: &str) -> Result<CoverArt, SpotifyError> {
+		let res = reqwest::get(url).await?.error_for_status()?;
+		let mime = res
+			.headers()
+			.get("content-type")
+			.and_then(|mime| mime.to_str().ok())
+			.map(|mime| mime.to_string());
+		let data = res.bytes().await?.to_vec();
+		let mime = cover_mime(mime, &data)?;
+		Ok(CoverArt::new(mime, data))
+	}
+
+	/// Download the original resolution variant of a Spotify CDN cover, `None` if there is none
+	async fn download_hires_cover(&self, url: &str) -> Option<CoverArt> {
+		// The id prefix selects the size, 640px covers have an original size variant
+		let hires_url = url.replacen(COVER_640_PREFIX, COVER_ORIGINAL_PREFIX, 1);
+		if hires_url == url {
+			return None;
+		}
+		self.spotify.requests.count(RequestKind::Cover);
+		match DownloaderInternal::download_cover(&hires_url).await {
+			// Only images that decode are trusted over the standard cover
+			Ok(cover) if cover.width.is_some() => Some(cover),
+			Ok(_) => {
+				warn!("Original size cover {} is not a valid image", hires_url);
+				None
+			}
+			Err(e) => {
+				info!("No original size cover at {}: {}", hires_url, e);
+				None
+			}
+		}
+	}
+
+	/// Download the first album image, in its original resolution if `hires_cover` is set
+	async fn download_front_cover(
+		&self,
+		images: &[Image],
+		config: &DownloaderConfig,
+	) -> Option<CoverArt> {
+		let image = images.first()?;
+		if config.hires_cover {
+			if let Some(cover) = self.download_hires_cover(&image.url).await {
+				return Some(cover);
 			}
-			"Line" => {
-				for line in lyric_json["Content"].as_array().unwrap() {
-					let ts = (line["StartTime"].as_f64().unwrap() * 1000.0) as u64;
-					let ts_min = ts / 60000;
-					let ts_sec = (ts % 60000) / 1000;
-					let ts_ms = (ts % 1000) / 10; // Truncated to 2 digits
+		}
+		self.spotify.requests.count(RequestKind::Cover);
+		match DownloaderInternal::download_cover(&image.url).await {
+			Ok(cover) => Some(cover),
+			Err(e) => {
+				warn!("Failed downloading cover! {}", e);
+				None
+			}
+		}
+	}
 
-					let text = line["Text"].as_str().unwrap();
+	/// Claim writing the cover file of a folder, `None` if another track did or there already is one
+	async fn claim_cover_folder(&self, folder: &Path) -> Option<PathBuf> {
+		if !self.cover_folders.lock().await.insert(folder.to_owned()) {
+			return None;
+		}
+		cover_file(folder).is_none().then(|| folder.to_owned())
+	}
 
-					lrc_text.push_str(&format!(
-						"[{:02}:{:02}.{:02}]{}\n",
-						ts_min, ts_sec, ts_ms, text
-					))
-				}
+	/// Write cover next to the tracks of an album
+	async fn save_cover_file(folder: &Path, cover: &CoverArt, config: &DownloaderConfig) {
+		let cover = config.cover_format.convert(cover.clone());
+		let extension = match cover.mime.as_str() {
+			"image/png" => "png",
+			_ => "jpg",
+		};
+		let path = folder.join(COVER_FILE).with_extension(extension);
+		if let Err(e) = tokio::fs::write(&path, &cover.data).await {
+			warn!("Writing cover file {} failed: {}", path.display(), e);
+		}
+	}
+
+	/// Album images other than the front cover, as Spotify lists every cover in several sizes
+	/// only images with a different id than the ones before are downloaded
+	async fn download_extra_covers(&self, images: &[Image]) -> Vec<(CoverType, CoverArt)> {
+		let mut seen: HashSet<&str> = images.iter().take(1).map(|i| cover_id(&i.url)).collect();
+		let mut covers = vec![];
+		for image in images.iter().skip(1) {
+			if !seen.insert(cover_id(&image.url)) {
+				continue;
+			}
+			self.spotify.requests.count(RequestKind::Cover);
+			match DownloaderInternal::download_cover(&image.url).await {
+				Ok(cover) => covers.push((CoverType::Other, cover)),
+				Err(e) => warn!("Failed downloading extra cover {}! {}", image.url, e),
 			}
-			"Static" => {
-				for line in lyric_json["Lines"].as_array().unwrap() {
-					let text = line["Text"].as_str().unwrap();
-					lrc_text.push_str(&format!("{}\n", text));
+		}
+		covers
+	}
+
+	/// Create album folder once per run. Returns whether this job created it,
+	/// concurrent jobs of the same album wait until it exists
+	async fn create_album_folder(&self, path: &Path) -> Result<bool, SpotifyError> {
+		let mut folders = self.album_folders.lock().await;
+		if folders.contains(path) {
+			return Ok(false);
+		}
+		tokio::fs::create_dir_all(path).await?;
+		folders.insert(path.to_owned());
+		Ok(true)
+	}
+
+	/// Album level tags, fetched once per album and shared by its tracks.
+	/// Concurrent jobs of the same album wait for the first one, other albums don't
+	async fn album_tags(
+		&self,
+		id: &str,
+		config: &DownloaderConfig,
+	) -> Result<Arc<AlbumTags>, SpotifyError> {
+		let cell = self
+			.album_tags
+			.lock()
+			.await
+			.entry(id.to_string())
+			.or_default()
+			.clone();
+		// A failed fetch leaves the cell empty, so the next track of the album retries
+		cell.get_or_try_init(|| async {
+			let album = self.spotify.album(id).await?;
+			let mut tags = AlbumTags::new(&album, config.various_artists_threshold);
+			// Discs are in order, so the last track has the highest disc number
+			if album.tracks.next.is_some() {
+				let last = self
+					.spotify
+					.album_tracks(id, 1, album.tracks.total - 1)
+					.await?;
+				if let Some(track) = last.items.last() {
+					tags.total_discs = track.disc_number;
 				}
 			}
-			_ => {
-				return Err(SpotifyError::Error(format!(
-					"Unknown lyric type {}",
-					lyric_json["Type"].as_str().unwrap()
-				)))
+			Ok(Arc::new(tags))
+		})
+		.await
+		.cloned()
+	}
+
+	/// Warn if the downloaded audio is noticeably shorter or longer than Spotify says.
+	/// With `retry_duration_mismatch` the file is removed instead, so it is downloaded again
+	async fn verify_duration(
+		&self,
+		job: &DownloadJob,
+		path: &Path,
+		format: &AudioFormat,
+		expected: Duration,
+		config: &DownloaderConfig,
+	) -> Result<(), SpotifyError> {
+		let measured_path = path.to_owned();
+		let measured_format = format.clone();
+		let duration =
+			tokio::task::spawn_blocking(move || audio_duration(&measured_path, &measured_format))
+				.await
+				.unwrap_or_else(|e| Err(e.into()));
+		let duration = match duration {
+			Ok(Some(duration)) => duration,
+			Ok(None) => {
+				info!("Can't verify the duration of {:?} files", format);
+				return Ok(());
+			}
+			Err(e) => {
+				warn!("Reading duration of {} failed: {}", path.display(), e);
+				return Ok(());
+			}
+		};
+		let difference = match duration > expected {
+			true => duration - expected,
+			false => expected - duration,
+		};
+		if difference > Duration::from_millis(config.duration_tolerance_ms) {
+			let warning = format!(
+				"Duration is {:.1}s, expected {:.1}s",
+				duration.as_secs_f64(),
+				expected.as_secs_f64()
+			);
+			warn!("{}: {}", path.display(), warning);
+			if config.retry_duration_mismatch {
+				tokio::fs::remove_file(path).await?;
+				return Err(SpotifyError::DurationMismatch(warning));
 			}
+			self.event_tx
+				.send(Message::AddWarning(job.id, warning))
+				.await
+				.ok();
 		}
+		Ok(())
+	}
 
-		// Save LRC to path_stem + ".lrc"
-		let path = format!("{}.lrc", path.as_ref().to_str().unwrap());
-		let mut file = File::create(&path).await?;
-		file.write_all(lrc_text.as_bytes()).await?;
+	/// Attach lyrics failure to the download as a warning
+	async fn lyrics_warning(&self, job: &DownloadJob, e: SpotifyError) {
+		warn!("Downloading lyrics for {} failed: {}", job.track_id, e);
+		let warning = match e {
+			SpotifyError::LyricsNotFound | SpotifyError::LyricsToken(_) => e.to_string(),
+			e => format!("Lyrics failed: {}", e),
+		};
+		self.event_tx
+			.send(Message::AddWarning(job.id, warning))
+			.await
+			.ok();
+	}
 
-		Ok(())
+	/// Fetch lyrics, trying each provider until one has them
+	async fn fetch_lyrics(
+		&self,
+		query: &LyricsQuery,
+		config: &DownloaderConfig,
+	) -> Result<Lyrics, SpotifyError> {
+		let mut error = None;
+		for provider in &config.lyrics_providers {
+			self.spotify.requests.count(RequestKind::Lyrics);
+			let result = match provider {
+				LyricsProvider::BeautifulLyrics => self.beautiful_lyrics.fetch(query, config).await,
+				LyricsProvider::Lrclib => Lrclib.fetch(query, config).await,
+			};
+			match result {
+				Ok(Some(lyrics)) => {
+					info!("Lyrics for {} supplied by {:?}", query.title, provider);
+					return Ok(lyrics);
+				}
+				Ok(None) => info!("No lyrics for {} from {:?}", query.title, provider),
+				Err(e) => {
+					warn!(
+						"Lyrics for {} from {:?} failed: {}",
+						query.title, provider, e
+					);
+					error = Some(e);
+				}
+			}
+		}
+		Err(error.unwrap_or(SpotifyError::LyricsNotFound))
 	}
 
 	/// Write tags to file ( BLOCKING )
 	fn write_tags(
 		path: impl AsRef<Path>,
 		format: AudioFormat,
-		tags: Vec<(Field, Vec<String>)>,
-		date: String,
-		cover: Option<(String, Vec<u8>)>,
+		metadata: TrackMetadata,
 		config: DownloaderConfig,
 	) -> Result<(), SpotifyError> {
 		let mut tag_wrap = TagWrap::new(path, format)?;
@@ -616,19 +1364,59 @@ impl DownloaderInternal {
 
 		let tag = tag_wrap.get_tag();
 		tag.set_separator(&config.separator);
-		for (field, value) in tags {
+		tag.set_multivalue_mode(config.multivalue_mode);
+		for (field, value) in metadata.fields {
 			tag.set_field(field, value);
 		}
-		tag.set_release_date(&date);
-		// Cover
-		if let Some((mime, data)) = cover {
-			tag.add_cover(&mime, data);
+		if let Some(date) = metadata.date {
+			tag.set_release_date(&date);
+		}
+		if let Some(original_date) = metadata.original_date {
+			tag.set_original_release_date(&original_date);
+		}
+		if let Some(lyrics) = metadata.lyrics {
+			tag.set_lyrics(&lyrics);
+		}
+		// Album gain is only usable next to the track gain
+		if let Some(n) = metadata.replaygain {
+			tag.set_replaygain("TRACK", n.track_gain_db, n.track_peak);
+			tag.set_replaygain("ALBUM", n.album_gain_db, n.album_peak);
+		}
+		// Covers are only downloaded for embedding when enabled, this keeps it that way
+		if config.embed_cover {
+			tag.add_covers(
+				metadata
+					.covers
+					.into_iter()
+					.map(|(cover_type, cover)| (cover_type, config.cover_format.convert(cover)))
+					.collect(),
+			);
+		}
+		if config.tag_provenance {
+			tag.set_encoder(&format!("DownOnSpot {}", env!("CARGO_PKG_VERSION")));
 		}
 		tag.save()?;
 		Ok(())
 	}
 
-	async fn find_alternative(session: &Session, track: Track) -> Result<Track, SpotifyError> {
+	/// Replace the album gain tags of an already tagged file
+	fn write_album_gain(
+		path: &Path,
+		format: AudioFormat,
+		gain_db: f32,
+		peak: f32,
+		id3v24: bool,
+	) -> Result<(), SpotifyError> {
+		let mut tag_wrap = TagWrap::new(path, format)?;
+		if let TagWrap::Id3(id3) = &mut tag_wrap {
+			id3.use_id3_v24(id3v24)
+		}
+		let tag = tag_wrap.get_tag();
+		tag.set_replaygain("ALBUM", gain_db, peak);
+		tag.save()
+	}
+
+	pub async fn find_alternative(session: &Session, track: Track) -> Result<Track, SpotifyError> {
 		for alt in track.alternatives {
 			let t = Track::get(session, alt).await?;
 			if t.available {
@@ -639,54 +1427,96 @@ impl DownloaderInternal {
 		Err(SpotifyError::Unavailable)
 	}
 
-	/// Download track by id
+	/// File in the configured quality, or the best worse one. Formats are tried in preference order
+	pub fn select_file<'a>(
+		id: SpotifyId,
+		files: &'a HashMap<FileFormat, FileId>,
+		config: &DownloaderConfig,
+	) -> Option<(&'a FileId, FileFormat, Quality)> {
+		let mut quality = config.quality;
+		loop {
+			for format in config.file_formats(&quality) {
+				if let Some(f) = files.get(&format) {
+					return Some((f, format, quality));
+				}
+			}
+			// Fallback to worser quality
+			quality = quality.fallback()?;
+			warn!("{} Falling back to: {:?}", id.to_base62().unwrap(), quality);
+		}
+	}
+
+	/// Download track or episode by id, `path_stem` gives the path without extension for the selected quality
 	async fn download_track(
-		session: &Session,
+		&self,
 		id: &str,
-		path: impl AsRef<Path>,
+		kind: DownloadKind,
+		path_stem: impl FnOnce(Quality) -> PathBuf,
 		config: DownloaderConfig,
-		tx: Sender<Message>,
 		job_id: i64,
-	) -> Result<(PathBuf, AudioFormat), SpotifyError> {
+	) -> Result<(PathBuf, AudioFormat, Option<Normalisation>), SpotifyError> {
+		let session = &self.spotify.session;
+		let tx = self.event_tx.clone();
 		let id = SpotifyId::from_base62(id)?;
-		let mut track = Track::get(session, id).await?;
-
-		// Fallback if unavailable
-		if !track.available {
-			track = DownloaderInternal::find_alternative(session, track).await?;
-		}
-
-		// Quality fallback
-		let mut quality = config.quality;
-		let (mut file_id, mut file_format) = (None, None);
-		'outer: loop {
-			for format in quality.get_file_formats() {
-				if let Some(f) = track.files.get(&format) {
-					info!("{} Using {:?} format.", id.to_base62().unwrap(), format);
-					file_id = Some(f);
-					file_format = Some(format);
-					break 'outer;
+		let (id, files) = match kind {
+			DownloadKind::Track => {
+				let mut track = Track::get(session, id).await?;
+				// Fallback if unavailable
+				if !track.available {
+					track = DownloaderInternal::find_alternative(session, track).await?;
 				}
+				(track.id, track.files)
 			}
-			// Fallback to worser quality
-			match quality.fallback() {
-				Some(q) => quality = q,
-				None => break,
+			// Episodes have no alternatives
+			DownloadKind::Episode { .. } => {
+				let episode = Episode::get(session, id).await?;
+				if !episode.available {
+					return Err(SpotifyError::Unavailable);
+				}
+				(episode.id, episode.files)
 			}
-			warn!("{} Falling back to: {:?}", id.to_base62().unwrap(), quality);
-		}
+		};
 
-		let file_id = file_id.ok_or(SpotifyError::Unavailable)?;
-		let file_format = file_format.unwrap();
+		// Quality fallback
+		let (file_id, file_format, quality) = DownloaderInternal::select_file(id, &files, &config)
+			.ok_or(SpotifyError::Unavailable)?;
+		info!(
+			"{} Using {:?} format.",
+			id.to_base62().unwrap(),
+			file_format
+		);
+		let fallback = match quality == config.quality {
+			true => None,
+			false => Some(format!(
+				"No {} file available, downloaded {}",
+				config.quality.to_string(),
+				quality.to_string()
+			)),
+		};
+		tx.send(Message::SetQuality(job_id, quality, fallback))
+			.await
+			.ok();
+		let path_stem = path_stem(quality);
+		self.create_album_folder(path_stem.parent().unwrap())
+			.await?;
 
 		// Path with extension
+		let mut convert = config.convert_to_mp3 && !config.raw_passthrough;
 		let mut audio_format: AudioFormat = file_format.into();
+		// Tracks only available as AAC or MP4 follow `non_ogg_policy`
+		if matches!(audio_format, AudioFormat::Aac | AudioFormat::Mp4) {
+			match config.non_ogg_policy {
+				NonOggPolicy::Accept => {}
+				NonOggPolicy::Convert => convert = true,
+				NonOggPolicy::Skip => return Err(SpotifyError::SkippedFormat(audio_format)),
+			}
+		}
 		let path = format!(
 			"{}.{}",
-			path.as_ref().to_str().unwrap(),
-			match config.convert_to_mp3 {
-				true => "mp3".to_string(),
-				false => audio_format.extension(),
+			path_stem.to_str().unwrap(),
+			match convert {
+				true => config.converter_backend.extension(&config),
+				false => config.extension(&audio_format),
 			}
 		);
 		let path = Path::new(&path).to_owned();
@@ -695,69 +1525,227 @@ impl DownloaderInternal {
 		if config.skip_existing && path.is_file() {
 			return Err(SpotifyError::AlreadyDownloaded);
 		}
+		// Keep the existing file and save under a numbered name next to it
+		let path = match config.rename_existing && path.is_file() {
+			true => config.unique_path(&path),
+			false => path,
+		};
 
-		let path_clone = path.clone();
+		// Find out before the slow download whether the output can be tagged at all
+		let output_format = match convert {
+			true => config.converter_backend.output_format(),
+			false => audio_format.clone(),
+		};
+		if !config.raw_passthrough && !TagWrap::supports(&output_format) {
+			if config.strict_tagging {
+				return Err(SpotifyError::UnsupportedTagFormat(output_format));
+			}
+			warn!(
+				"{} {:?} files can't be tagged, tags will be skipped.",
+				id.to_base62().unwrap(),
+				output_format
+			);
+			tx.send(Message::AddWarning(
+				job_id,
+				SpotifyError::UnsupportedTagFormat(output_format).to_string(),
+			))
+			.await
+			.ok();
+		}
 
-		let key = session.audio_key().request(track.id, *file_id).await?;
-		let encrypted = AudioFile::open(session, *file_id, 1024 * 1024, true).await?;
-		let size = encrypted.get_stream_loader_controller().len();
-		// Download
-		let s = match config.convert_to_mp3 {
-			true => {
-				let s = DownloaderInternal::download_track_convert_stream(
-					path_clone,
-					encrypted,
-					key,
-					audio_format.clone(),
-					quality,
-				)
-				.boxed();
-				audio_format = AudioFormat::Mp3;
-				s
-			}
-			false => DownloaderInternal::download_track_stream(path_clone, encrypted, key).boxed(),
+		let raw_format = audio_format.clone();
+		let stall_timeout = config.stall_timeout_secs.map(Duration::from_secs);
+		let mut attempt = 1;
+		let normalisation = loop {
+			let (decrypted, size, normalisation) = self
+				.open_audio(id, *file_id, &audio_format, &config)
+				.await?;
+			// Download
+			let s = match convert {
+				true => match &config.converter_backend {
+					ConverterBackend::Internal => {
+						DownloaderInternal::download_track_convert_stream(
+							path.clone(),
+							decrypted,
+							audio_format.clone(),
+							quality,
+						)
+						.boxed()
+					}
+					ConverterBackend::Ffmpeg { args, .. } => {
+						DownloaderInternal::download_track_ffmpeg_stream(
+							path.clone(),
+							decrypted,
+							args.clone(),
+						)
+						.boxed()
+					}
+				},
+				false => DownloaderInternal::download_track_stream(path.clone(), decrypted).boxed(),
+			};
+			match self
+				.save_stream(s, &path, size, job_id, stall_timeout)
+				.await
+			{
+				Ok(_) => break normalisation,
+				// Stalled streams don't recover, starting over usually works
+				Err(e @ SpotifyError::DownloadStalled(_)) if attempt <= config.stall_retries => {
+					warn!(
+						"{} {} (attempt {}), retrying...",
+						id.to_base62().unwrap(),
+						e,
+						attempt
+					);
+					attempt += 1;
+				}
+				// The audio itself is fine, so keep it in its original format
+				Err(e) if e.is_conversion() && config.convert_fallback_raw => {
+					warn!(
+						"{} Conversion failed: {}, saving the original {:?} instead.",
+						id.to_base62().unwrap(),
+						e,
+						raw_format
+					);
+					tx.send(Message::AddWarning(
+						job_id,
+						format!("Conversion failed, saved original {:?}", raw_format),
+					))
+					.await
+					.ok();
+					let path = format!(
+						"{}.{}",
+						path_stem.to_str().unwrap(),
+						config.extension(&raw_format)
+					);
+					let path = match config.rename_existing && Path::new(&path).is_file() {
+						true => config.unique_path(Path::new(&path)),
+						false => PathBuf::from(path),
+					};
+					let (decrypted, size, _) =
+						self.open_audio(id, *file_id, &raw_format, &config).await?;
+					let s = DownloaderInternal::download_track_stream(path.clone(), decrypted);
+					self.save_stream(s, &path, size, job_id, stall_timeout)
+						.await?;
+					info!("Done downloading: {}", id.to_base62().unwrap());
+					return Ok((path, raw_format, normalisation));
+				}
+				Err(e) => return Err(e),
+			}
+		};
+		if convert {
+			audio_format = config.converter_backend.output_format();
+		}
+
+		info!("Done downloading: {}", id.to_base62().unwrap());
+		Ok((path, audio_format, normalisation))
+	}
+
+	/// Open and decrypt audio file, OGG files are returned past their header
+	async fn open_audio(
+		&self,
+		id: SpotifyId,
+		file_id: FileId,
+		audio_format: &AudioFormat,
+		config: &DownloaderConfig,
+	) -> Result<(AudioDecrypt<AudioFile>, usize, Option<Normalisation>), SpotifyError> {
+		let session = &self.spotify.session;
+		let key = session.audio_key().request(id, file_id).await?;
+		// Opening can hang on a bad connection, so give up and retry after a while
+		let open_timeout = Duration::from_secs(config.audio_open_timeout);
+		let mut attempt = 1;
+		let encrypted = loop {
+			let open = AudioFile::open(session, file_id, 1024 * 1024, true);
+			match async_std::future::timeout(open_timeout, open).await {
+				Ok(encrypted) => break encrypted?,
+				Err(_) if attempt < config.audio_open_attempts => {
+					warn!(
+						"{} Opening audio file timed out (attempt {}), retrying...",
+						id.to_base62().unwrap(),
+						attempt
+					);
+					attempt += 1;
+				}
+				Err(_) => return Err(SpotifyError::AudioFileTimeout),
+			}
 		};
+		let size = encrypted.get_stream_loader_controller().len();
+		// Skip (i guess encrypted shit), Spotify keeps its loudness data in there.
+		// Only OGG files have the header, other formats would be corrupted by skipping it
+		let decrypted = AudioDecrypt::new(key, encrypted);
+		match audio_format {
+			AudioFormat::Ogg => {
+				let (decrypted, header) =
+					tokio::task::spawn_blocking(move || DownloaderInternal::skip_header(decrypted))
+						.await??;
+				Ok((decrypted, size, Normalisation::parse(&header)))
+			}
+			_ => Ok((decrypted, size, None)),
+		}
+	}
+
+	/// Drive download stream while reporting progress, a partial file is removed on error.
+	/// Fails if no data arrives within `stall_timeout`
+	async fn save_stream(
+		&self,
+		s: impl Stream<Item = Result<usize, SpotifyError>>,
+		path: &Path,
+		size: usize,
+		job_id: i64,
+		stall_timeout: Option<Duration>,
+	) -> Result<(), SpotifyError> {
 		pin_mut!(s);
+		// Removed unless the stream completes, also when the download is cancelled halfway
+		let mut partial = PartialFile(Some(path));
 		// Read progress
 		let mut read = 0;
-		while let Some(result) = s.next().await {
+		loop {
+			let next = match stall_timeout {
+				Some(timeout) => async_std::future::timeout(timeout, s.next())
+					.await
+					.unwrap_or(Some(Err(SpotifyError::DownloadStalled(timeout.as_secs())))),
+				None => s.next().await,
+			};
+			let result = match next {
+				Some(result) => result,
+				None => break,
+			};
 			match result {
 				Ok(r) => {
 					read += r;
-					tx.send(Message::UpdateState(
-						job_id,
-						DownloadState::Downloading(read, size),
-					))
-					.await
-					.ok();
-				}
-				Err(e) => {
-					tokio::fs::remove_file(path).await.ok();
-					return Err(e);
+					self.event_tx
+						.send(Message::UpdateState(
+							job_id,
+							DownloadState::Downloading(read, size),
+						))
+						.await
+						.ok();
 				}
+				Err(e) => return Err(e),
 			}
 		}
+		partial.0 = None;
+		Ok(())
+	}
 
-		info!("Done downloading: {}", track.id.to_base62().unwrap());
-		Ok((path, audio_format))
+	/// Skip header of the decrypted stream, returning it ( BLOCKING )
+	fn skip_header<R: Read>(mut decrypted: R) -> Result<(R, [u8; 0xa7]), SpotifyError> {
+		let mut skip: [u8; 0xa7] = [0; 0xa7];
+		match decrypted.read_exact(&mut skip) {
+			Ok(_) => Ok((decrypted, skip)),
+			// Stream ends before the header, so there is no audio to download
+			Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+				Err(SpotifyError::NoDownloadableFile)
+			}
+			Err(e) => Err(e.into()),
+		}
 	}
 
 	fn download_track_stream(
 		path: impl AsRef<Path>,
-		encrypted: AudioFile,
-		key: AudioKey,
+		mut decrypted: AudioDecrypt<AudioFile>,
 	) -> impl Stream<Item = Result<usize, SpotifyError>> {
 		try_stream! {
 			let mut file = File::create(path).await?;
-			let mut decrypted = AudioDecrypt::new(key, encrypted);
-			// Skip (i guess encrypted shit)
-			let mut skip: [u8; 0xa7] = [0; 0xa7];
-			let mut decrypted = tokio::task::spawn_blocking(move || {
-				match decrypted.read_exact(&mut skip) {
-					Ok(_) => Ok(decrypted),
-					Err(e) => Err(e)
-				}
-			}).await??;
 			// Custom reader loop for decrypting
 			loop {
 				// Blocking reader
@@ -777,25 +1765,82 @@ impl DownloaderInternal {
 			}
 		}
 	}
+	/// Download and convert with ffmpeg, the audio is piped in and the converted file read back
+	fn download_track_ffmpeg_stream(
+		path: impl AsRef<Path>,
+		mut decrypted: AudioDecrypt<AudioFile>,
+		args: Vec<String>,
+	) -> impl Stream<Item = Result<usize, SpotifyError>> {
+		try_stream! {
+			let mut ffmpeg = tokio::process::Command::new("ffmpeg")
+				.args(["-hide_banner", "-loglevel", "error", "-i", "pipe:0"])
+				.args(&args)
+				.arg("pipe:1")
+				.stdin(Stdio::piped())
+				.stdout(Stdio::piped())
+				.stderr(Stdio::piped())
+				.kill_on_drop(true)
+				.spawn()
+				.map_err(|e| match e.kind() {
+					std::io::ErrorKind::NotFound => SpotifyError::FfmpegError(
+						"not found, install it or use the Internal converter_backend".into(),
+					),
+					_ => e.into(),
+				})?;
+			let mut stdin = ffmpeg.stdin.take().unwrap();
+			let mut stdout = ffmpeg.stdout.take().unwrap();
+			// Feed ffmpeg while its output is read, so neither pipe fills up
+			let feeder = tokio::spawn(async move {
+				loop {
+					// Blocking reader
+					let (d, read, buf) = tokio::task::spawn_blocking(move || {
+						let mut buf = vec![0; 1024 * 64];
+						match decrypted.read(&mut buf) {
+							Ok(r) => Ok((decrypted, r, buf)),
+							Err(e) => Err(e)
+						}
+					}).await??;
+					decrypted = d;
+					if read == 0 {
+						break;
+					}
+					stdin.write_all(&buf[0..read]).await?;
+				}
+				Ok::<(), SpotifyError>(())
+			});
+
+			let mut file = File::create(path).await?;
+			let mut buf = vec![0; 1024 * 64];
+			loop {
+				let read = stdout.read(&mut buf).await?;
+				if read == 0 {
+					break;
+				}
+				file.write_all(&buf[0..read]).await?;
+				yield read;
+			}
+			// ffmpeg's own error explains more than the broken pipe feeding it
+			let output = ffmpeg.wait_with_output().await?;
+			if !output.status.success() {
+				Err(SpotifyError::FfmpegError(format!(
+					"{}, {}",
+					output.status,
+					String::from_utf8_lossy(&output.stderr).trim()
+				)))?;
+			}
+			feeder.await??;
+		}
+	}
+
 	/// Download and convert to MP3
 	fn download_track_convert_stream(
 		path: impl AsRef<Path>,
-		encrypted: AudioFile,
-		key: AudioKey,
+		decrypted: AudioDecrypt<AudioFile>,
 		format: AudioFormat,
 		quality: Quality,
 	) -> impl Stream<Item = Result<usize, SpotifyError>> {
 		try_stream! {
 			let mut file = File::create(path).await?;
-			let mut decrypted = AudioDecrypt::new(key, encrypted);
-			// Skip (i guess encrypted shit)
-			let mut skip: [u8; 0xa7] = [0; 0xa7];
-			let decrypted = tokio::task::spawn_blocking(move || {
-				match decrypted.read_exact(&mut skip) {
-					Ok(_) => Ok(decrypted),
-					Err(e) => Err(e)
-				}
-			}).await??;
 			// Convertor
 			let mut decrypted = tokio::task::spawn_blocking(move || {
 				AudioConverter::new(Box::new(decrypted), format, quality)
@@ -806,9 +1851,10 @@ impl DownloaderInternal {
 				// Blocking reader
 				let (d, read, buf) = tokio::task::spawn_blocking(move || {
 					let mut buf = vec![0; 1024 * 64];
+					// Decoding errors end the stream, so errors reading it are the encoder's
 					match decrypted.read(&mut buf) {
 						Ok(r) => Ok((decrypted, r, buf)),
-						Err(e) => Err(e)
+						Err(e) => Err(SpotifyError::LameConverterError(e.to_string()))
 					}
 				}).await??;
 				decrypted = d;
@@ -822,51 +1868,365 @@ impl DownloaderInternal {
 	}
 }
 
+/// Album fields shared by every track of an album, derived from the full album instead of
+/// each track. Only these are kept, not the album with all its tracks
 #[derive(Debug, Clone)]
-pub enum AudioFormat {
-	Ogg,
-	Aac,
-	Mp3,
-	Mp4,
-	Unknown,
+pub struct AlbumTags {
+	pub id: String,
+	pub name: String,
+	/// Album artists, or Various Artists over `various_artists_threshold`
+	pub artists: Vec<String>,
+	pub release_date: String,
+	pub images: Vec<Image>,
+	pub genres: Vec<String>,
+	pub label: Option<String>,
+	pub total_tracks: u32,
+	pub total_discs: i32,
+	pub compilation: bool,
 }
 
-impl AudioFormat {
-	/// Get extension
-	pub fn extension(&self) -> String {
-		match self {
-			AudioFormat::Ogg => "ogg",
-			AudioFormat::Aac => "m4a",
-			AudioFormat::Mp3 => "mp3",
-			AudioFormat::Mp4 => "mp4",
-			AudioFormat::Unknown => "",
+impl AlbumTags {
+	/// Tags of the show of an episode, dated by the episode
+	pub fn show(episode: &FullEpisode) -> AlbumTags {
+		let show = &episode.show;
+		// Episodes have their own cover more often than not
+		let images = match episode.images.is_empty() {
+			true => show.images.clone(),
+			false => episode.images.clone(),
+		};
+		AlbumTags {
+			id: show.id.id().to_string(),
+			name: show.name.clone(),
+			artists: vec![show.publisher.clone()],
+			release_date: episode.release_date.clone(),
+			images,
+			genres: vec![],
+			label: None,
+			total_tracks: 0,
+			total_discs: 1,
+			compilation: false,
 		}
-		.to_string()
 	}
-}
 
-impl From<FileFormat> for AudioFormat {
-	fn from(f: FileFormat) -> Self {
-		match f {
-			FileFormat::OGG_VORBIS_96 => Self::Ogg,
-			FileFormat::OGG_VORBIS_160 => Self::Ogg,
-			FileFormat::OGG_VORBIS_320 => Self::Ogg,
-			FileFormat::MP3_256 => Self::Mp3,
-			FileFormat::MP3_320 => Self::Mp3,
-			FileFormat::MP3_160 => Self::Mp3,
-			FileFormat::MP3_96 => Self::Mp3,
-			FileFormat::MP3_160_ENC => Self::Mp3,
-			FileFormat::MP4_128_DUAL => Self::Mp4,
-			FileFormat::OTHER3 => Self::Unknown,
-			FileFormat::AAC_160 => Self::Aac,
-			FileFormat::AAC_320 => Self::Aac,
-			FileFormat::MP4_128 => Self::Mp4,
-			FileFormat::OTHER5 => Self::Unknown,
+	/// Tags of album, discs are counted from the tracks of its first page only
+	pub fn new(album: &FullAlbum, various_artists_threshold: usize) -> AlbumTags {
+		let artists = album_artists(album, various_artists_threshold);
+		let compilation =
+			album.album_type == AlbumType::Compilation || artists == [VARIOUS_ARTISTS];
+		AlbumTags {
+			id: album.id.id().to_string(),
+			name: album.name.clone(),
+			artists,
+			release_date: album.release_date.clone(),
+			images: album.images.clone(),
+			genres: album.genres.clone(),
+			label: album.label.clone(),
+			total_tracks: album.tracks.total,
+			total_discs: album.tracks.items.last().map_or(1, |t| t.disc_number),
+			compilation,
 		}
 	}
-}
 
-impl Quality {
+	fn fields(&self) -> Vec<(Field, Vec<String>)> {
+		let mut fields = vec![
+			(Field::Album, vec![self.name.clone()]),
+			(Field::AlbumArtist, self.artists.clone()),
+			(Field::TotalTracks, vec![self.total_tracks.to_string()]),
+			(Field::TotalDiscs, vec![self.total_discs.to_string()]),
+			(Field::Genre, self.genres.clone()),
+			(Field::Label, self.label.clone().into_iter().collect()),
+		];
+		if self.compilation {
+			fields.push((Field::Compilation, vec!["1".to_string()]));
+		}
+		fields
+	}
+}
+
+/// Everything written to the tags of a downloaded file
+struct TrackMetadata {
+	fields: Vec<(Field, Vec<String>)>,
+	date: Option<String>,
+	original_date: Option<String>,
+	replaygain: Option<Normalisation>,
+	covers: Vec<(CoverType, CoverArt)>,
+	/// LRC text, embedded as is so players that understand it can sync it
+	lyrics: Option<String>,
+}
+
+/// Name of the cover file saved next to tracks, without extension
+const COVER_FILE: &str = "cover";
+
+/// Cover file saved in folder by `save_cover_file`, if there is one
+pub fn cover_file(folder: &Path) -> Option<PathBuf> {
+	["jpg", "png"]
+		.iter()
+		.map(|ext| folder.join(COVER_FILE).with_extension(ext))
+		.find(|path| path.is_file())
+}
+
+/// Spotify image id prefix of 640px album covers
+const COVER_640_PREFIX: &str = "ab67616d0000b273";
+/// Spotify image id prefix of album covers in their uploaded resolution
+const COVER_ORIGINAL_PREFIX: &str = "ab67616d000082c1";
+
+/// Mime of a cover from the content-type header. Some CDNs leave out the header,
+/// so it's guessed from the image itself
+fn cover_mime(header: Option<String>, data: &[u8]) -> Result<String, SpotifyError> {
+	match header {
+		Some(mime) => Ok(mime),
+		None => Ok(image::guess_format(data)
+			.map_err(|_| SpotifyError::Error("Missing cover mime!".into()))?
+			.to_mime_type()
+			.to_string()),
+	}
+}
+
+/// Sort names of the fields that have one, only where it differs from the name
+fn sort_tags(tags: &[(Field, Vec<String>)], articles: &[String]) -> Vec<(Field, Vec<String>)> {
+	tags.iter()
+		.filter_map(|(field, values)| {
+			let sort_field = field.sort_field()?;
+			let name = values.first()?;
+			let sort_name = sort_name(name, articles);
+			(&sort_name != name).then(|| (sort_field, vec![sort_name]))
+		})
+		.collect()
+}
+
+/// Largest possible OGG page, header and segment table included
+const OGG_MAX_PAGE: u64 = 65307;
+
+/// Playing time of an audio file ( BLOCKING ), from the last OGG page or the MP3 frame headers.
+/// `None` for formats that can't be measured
+fn audio_duration(path: &Path, format: &AudioFormat) -> Result<Option<Duration>, SpotifyError> {
+	let unreadable = || SpotifyError::Error(format!("No {:?} frames found", format));
+	match format {
+		AudioFormat::Ogg => {
+			// The identification header is on the first page, the last page is at most 64 KiB
+			let mut file = std::fs::File::open(path)?;
+			let mut head = vec![];
+			file.by_ref().take(4096).read_to_end(&mut head)?;
+			let len = file.metadata()?.len();
+			file.seek(SeekFrom::Start(len.saturating_sub(OGG_MAX_PAGE)))?;
+			let mut tail = vec![];
+			file.read_to_end(&mut tail)?;
+			ogg_duration(&head, &tail).map(Some).ok_or_else(unreadable)
+		}
+		AudioFormat::Mp3 => mp3_duration(&std::fs::read(path)?)
+			.map(Some)
+			.ok_or_else(unreadable),
+		_ => Ok(None),
+	}
+}
+
+/// Length of an OGG Vorbis stream, the granule position of its last page counts the samples
+fn ogg_duration(head: &[u8], tail: &[u8]) -> Option<Duration> {
+	let ident = head.windows(7).position(|w| w == b"\x01vorbis")?;
+	let rate = u32::from_le_bytes(head.get(ident + 12..ident + 16)?.try_into().ok()?);
+	// Audio data may contain "OggS" too, so only pages with a valid header count
+	let granule = (0..=tail.len().checked_sub(27)?).rev().find_map(|i| {
+		let page = &tail[i..];
+		if &page[..4] != b"OggS" || page[4] != 0 || page[5] > 7 {
+			return None;
+		}
+		// Pages without a finished packet have no position
+		let granule = u64::from_le_bytes(page[6..14].try_into().unwrap());
+		(granule != u64::MAX).then_some(granule)
+	})?;
+	(rate > 0).then(|| Duration::from_secs_f64(granule as f64 / rate as f64))
+}
+
+/// Length of an MP3 file counted frame by frame, so variable bitrates are measured right
+fn mp3_duration(data: &[u8]) -> Option<Duration> {
+	let mut pos = match data.len() >= 10 && data.starts_with(b"ID3") {
+		// The ID3v2 tag size is syncsafe, 7 bits per byte
+		true => {
+			10 + data[6..10]
+				.iter()
+				.fold(0, |size, b| size << 7 | (*b & 0x7f) as usize)
+		}
+		false => 0,
+	};
+	let mut frames = 0;
+	let mut seconds = 0.0;
+	while let Some((length, duration)) = data.get(pos..pos + 4).and_then(mp3_frame) {
+		pos += length;
+		frames += 1;
+		seconds += duration;
+	}
+	(frames > 0).then(|| Duration::from_secs_f64(seconds))
+}
+
+/// Length in bytes and playing time of an MPEG layer III frame, read from its header
+fn mp3_frame(header: &[u8]) -> Option<(usize, f64)> {
+	const MPEG1_KBPS: [usize; 16] = [
+		0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0,
+	];
+	const MPEG2_KBPS: [usize; 16] = [
+		0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+	];
+	if header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+		return None;
+	}
+	// Version 3 is MPEG 1, 2 is MPEG 2 and 0 is MPEG 2.5. Layer 1 is layer III
+	let version = (header[1] >> 3) & 3;
+	let layer = (header[1] >> 1) & 3;
+	if version == 1 || layer != 1 {
+		return None;
+	}
+	let bitrate = (header[2] >> 4) as usize;
+	let kbps = match version {
+		3 => MPEG1_KBPS[bitrate],
+		_ => MPEG2_KBPS[bitrate],
+	};
+	let sample_rate = [44100, 48000, 32000, 0][((header[2] >> 2) & 3) as usize]
+		/ match version {
+			3 => 1,
+			2 => 2,
+			_ => 4,
+		};
+	if kbps == 0 || sample_rate == 0 {
+		return None;
+	}
+	let samples = match version {
+		3 => 1152,
+		_ => 576,
+	};
+	let padding = ((header[2] >> 1) & 1) as usize;
+	let length = samples / 8 * kbps * 1000 / sample_rate + padding;
+	Some((length, samples as f64 / sample_rate as f64))
+}
+
+/// Deepest existing folder of a path template that doesn't depend on the track
+fn download_root(template: &str) -> PathBuf {
+	let mut root: PathBuf = Path::new(template)
+		.components()
+		.take_while(|c| !c.as_os_str().to_string_lossy().contains('%'))
+		.collect();
+	while !root.is_dir() {
+		if !root.pop() || root.as_os_str().is_empty() {
+			return PathBuf::from(".");
+		}
+	}
+	root
+}
+
+/// Image id of a Spotify CDN cover without the size prefix, the same for every size of a cover
+fn cover_id(url: &str) -> &str {
+	let id = url.rsplit('/').next().unwrap_or(url);
+	match id.len() == 40 {
+		true => &id[COVER_640_PREFIX.len()..],
+		false => id,
+	}
+}
+
+/// Cover image, the dimensions are unknown if the image couldn't be decoded
+#[derive(Debug, Clone)]
+pub struct CoverArt {
+	pub mime: String,
+	pub data: Vec<u8>,
+	pub width: Option<u32>,
+	pub height: Option<u32>,
+}
+
+impl CoverArt {
+	/// Read dimensions from the image header, undecodable images are still embedded
+	pub fn new(mime: String, data: Vec<u8>) -> CoverArt {
+		let dimensions = image::io::Reader::new(std::io::Cursor::new(&data))
+			.with_guessed_format()
+			.map_err(image::ImageError::from)
+			.and_then(|reader| reader.into_dimensions());
+		let (width, height) = match dimensions {
+			Ok((width, height)) => (Some(width), Some(height)),
+			Err(e) => {
+				warn!("Reading cover dimensions failed: {}", e);
+				(None, None)
+			}
+		};
+		CoverArt {
+			mime,
+			data,
+			width,
+			height,
+		}
+	}
+}
+
+/// Loudness data Spotify stores in the header of its OGG files
+#[derive(Debug, Clone, Copy)]
+pub struct Normalisation {
+	pub track_gain_db: f32,
+	pub track_peak: f32,
+	pub album_gain_db: f32,
+	pub album_peak: f32,
+}
+
+impl Normalisation {
+	/// Offset of the values in the header
+	const OFFSET: usize = 144;
+
+	/// Parse from header, four little endian floats
+	pub fn parse(header: &[u8]) -> Option<Normalisation> {
+		let value = |i: usize| -> Option<f32> {
+			let start = Normalisation::OFFSET + i * 4;
+			let bytes = header.get(start..start + 4)?.try_into().ok()?;
+			Some(f32::from_le_bytes(bytes))
+		};
+		Some(Normalisation {
+			track_gain_db: value(0)?,
+			track_peak: value(1)?,
+			album_gain_db: value(2)?,
+			album_peak: value(3)?,
+		})
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum AudioFormat {
+	Ogg,
+	Aac,
+	Mp3,
+	Mp4,
+	Unknown,
+}
+
+impl AudioFormat {
+	/// Get extension
+	pub fn extension(&self) -> String {
+		match self {
+			AudioFormat::Ogg => "ogg",
+			AudioFormat::Aac => "m4a",
+			AudioFormat::Mp3 => "mp3",
+			AudioFormat::Mp4 => "mp4",
+			AudioFormat::Unknown => "",
+		}
+		.to_string()
+	}
+}
+
+impl From<FileFormat> for AudioFormat {
+	fn from(f: FileFormat) -> Self {
+		match f {
+			FileFormat::OGG_VORBIS_96 => Self::Ogg,
+			FileFormat::OGG_VORBIS_160 => Self::Ogg,
+			FileFormat::OGG_VORBIS_320 => Self::Ogg,
+			FileFormat::MP3_256 => Self::Mp3,
+			FileFormat::MP3_320 => Self::Mp3,
+			FileFormat::MP3_160 => Self::Mp3,
+			FileFormat::MP3_96 => Self::Mp3,
+			FileFormat::MP3_160_ENC => Self::Mp3,
+			FileFormat::MP4_128_DUAL => Self::Mp4,
+			FileFormat::OTHER3 => Self::Unknown,
+			FileFormat::AAC_160 => Self::Aac,
+			FileFormat::AAC_320 => Self::Aac,
+			FileFormat::MP4_128 => Self::Mp4,
+			FileFormat::OTHER5 => Self::Unknown,
+		}
+	}
+}
+
+impl Quality {
 	/// Get librespot AudioFileFormat
 	pub fn get_file_formats(&self) -> Vec<FileFormat> {
 		match self {
@@ -885,6 +2245,16 @@ impl Quality {
 		}
 	}
 
+	/// Bitrate in kbps
+	pub fn kbps(&self) -> u32 {
+		match self {
+			Quality::Q320 => 320,
+			Quality::Q256 => 256,
+			Quality::Q160 => 160,
+			Quality::Q96 => 96,
+		}
+	}
+
 	/// Fallback to lower quality
 	pub fn fallback(&self) -> Option<Quality> {
 		match self {
@@ -900,6 +2270,7 @@ impl Quality {
 pub struct DownloadJob {
 	pub id: i64,
 	pub track_id: String,
+	pub kind: DownloadKind,
 }
 
 #[derive(Debug, Clone)]
@@ -908,8 +2279,20 @@ pub enum Message {
 	GetJob,
 	// Update state of download
 	UpdateState(i64, DownloadState),
+	// Attach non-fatal warning to download
+	AddWarning(i64, String),
+	// Quality downloaded at and the reason for falling back
+	SetQuality(i64, Quality, Option<String>),
+	// File the track was saved to
+	SetFile(i64, SavedFile),
 	//add to download
 	AddToQueue(Vec<Download>),
+	// Cancel queued or downloading track
+	Cancel(i64),
+	// Queue failed or cancelled track again
+	Retry(i64),
+	// Queue track that failed for a passing reason again, up to `requeue_attempts` times
+	Requeue(i64, String),
 	// Get all downloads to UI
 	GetDownloads,
 }
@@ -919,27 +2302,370 @@ pub enum Response {
 	Downloads(Vec<Download>),
 }
 
+/// Events of the download queue, see [`Downloader::events`]
+#[derive(Debug, Clone)]
+pub enum DownloadEvent {
+	/// Every queued track of the album finished, failed or was skipped
+	AlbumComplete {
+		album_id: String,
+		tracks: Vec<Download>,
+	},
+}
+
 #[derive(Debug, Clone)]
 pub struct Download {
 	pub id: i64,
+	/// Position in enqueue order, kept for display while downloads finish out of order
+	pub order: usize,
 	pub track_id: String,
 	pub title: String,
 	pub subtitle: String,
 	pub state: DownloadState,
+	pub warnings: Vec<String>,
+	pub timing: DownloadTiming,
+	/// Album of the track if known, for `per_album_concurrency`
+	pub album_id: Option<String>,
+	/// Quality downloaded at, and why it is lower than configured
+	pub quality: Option<Quality>,
+	pub quality_fallback: Option<String>,
+	/// Set once the audio is saved
+	pub file: Option<SavedFile>,
+	pub kind: DownloadKind,
+}
+
+/// What is downloaded, podcast episodes are fetched and tagged differently from tracks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadKind {
+	#[default]
+	Track,
+	/// Number counted from the oldest episode of the show, if queued from the show
+	Episode { number: Option<u32> },
 }
 
+/// File a track was saved to
 #[derive(Debug, Clone)]
+pub struct SavedFile {
+	pub path: PathBuf,
+	pub format: AudioFormat,
+	pub album: String,
+	/// Set when the track gain was written with `compute_replaygain_album`
+	pub loudness: Option<Loudness>,
+}
+
+/// Rewrite the album gain of every measured file of a completed album
+async fn write_album_replaygain(album_id: &str, tracks: &[Download], config: &DownloaderConfig) {
+	let files: Vec<(PathBuf, AudioFormat, Loudness)> = tracks
+		.iter()
+		.filter_map(|d| {
+			let file = d.file.as_ref()?;
+			Some((file.path.clone(), file.format.clone(), file.loudness?))
+		})
+		.collect();
+	let loudness: Vec<Loudness> = files.iter().map(|(_, _, l)| *l).collect();
+	let Some((gain_db, peak)) = album_replaygain(&loudness) else {
+		info!(
+			"Not every track of album {} was measured, keeping Spotify's album gain",
+			album_id
+		);
+		return;
+	};
+	let id3v24 = config.id3v24;
+	tokio::task::spawn_blocking(move || {
+		for (path, format, _) in files {
+			if let Err(e) =
+				DownloaderInternal::write_album_gain(&path, format, gain_db, peak, id3v24)
+			{
+				warn!("Writing album gain to {} failed: {}", path.display(), e);
+			}
+		}
+	})
+	.await
+	.ok();
+}
+
+/// File being written, removed when dropped before it is complete
+struct PartialFile<'a>(Option<&'a Path>);
+
+impl Drop for PartialFile<'_> {
+	fn drop(&mut self) {
+		if let Some(path) = self.0 {
+			std::fs::remove_file(path).ok();
+		}
+	}
+}
+
+/// Track loudness collected until the album completes, for the album gain
+#[derive(Debug, Clone, Copy)]
+pub struct Loudness {
+	pub gain_db: f32,
+	pub peak: f32,
+	pub seconds: f64,
+	/// Tracks on the album, the album gain is only computed once all of them are known
+	pub album_tracks: usize,
+}
+
+/// Album gain and peak of the whole album, if every track of it was measured.
+/// The gain is the duration weighted mean loudness, the peak the highest track peak
+pub fn album_replaygain(tracks: &[Loudness]) -> Option<(f32, f32)> {
+	let album_tracks = tracks.first()?.album_tracks;
+	if tracks.len() < album_tracks {
+		return None;
+	}
+	let seconds: f64 = tracks.iter().map(|t| t.seconds).sum();
+	if seconds <= 0.0 {
+		return None;
+	}
+	// Gains are negated loudness in dB, average them as power
+	let power = tracks
+		.iter()
+		.map(|t| t.seconds * 10f64.powf(-t.gain_db as f64 / 10.0))
+		.sum::<f64>()
+		/ seconds;
+	let gain_db = -10.0 * power.log10();
+	let peak = tracks.iter().map(|t| t.peak).fold(0.0, f32::max);
+	Some((gain_db as f32, peak))
+}
+
+/// When a download ran and how much it transferred
+#[derive(Debug, Clone, Default)]
+pub struct DownloadTiming {
+	pub started: Option<SystemTime>,
+	pub finished: Option<SystemTime>,
+	pub bytes: usize,
+}
+
+impl DownloadTiming {
+	/// Time since first received bytes, up to now for running downloads
+	pub fn elapsed(&self) -> Option<Duration> {
+		let end = self.finished.unwrap_or_else(SystemTime::now);
+		end.duration_since(self.started?).ok()
+	}
+
+	/// Average throughput in bytes per second, up to now for running downloads
+	pub fn throughput(&self) -> Option<f64> {
+		let secs = self.elapsed()?.as_secs_f64();
+		(secs > 0.0).then(|| self.bytes as f64 / secs)
+	}
+}
+
+/// Bytes transferred by all downloads and the time they took together
+#[derive(Debug, Clone, Default)]
+pub struct DownloadStats {
+	pub bytes: usize,
+	pub started: Option<SystemTime>,
+	pub finished: Option<SystemTime>,
+	pub requests: RequestStats,
+}
+
+impl DownloadStats {
+	/// Time from the first download starting to the last one finishing
+	pub fn duration(&self) -> Option<Duration> {
+		self.finished?.duration_since(self.started?).ok()
+	}
+
+	/// Average throughput in bytes per second, over the wall clock time of concurrent downloads
+	pub fn throughput(&self) -> Option<f64> {
+		let secs = self.duration()?.as_secs_f64();
+		(secs > 0.0).then(|| self.bytes as f64 / secs)
+	}
+}
+
+impl From<&[Download]> for DownloadStats {
+	fn from(downloads: &[Download]) -> Self {
+		let timings = downloads.iter().map(|d| &d.timing);
+		DownloadStats {
+			bytes: timings.clone().map(|t| t.bytes).sum(),
+			started: timings.clone().filter_map(|t| t.started).min(),
+			finished: timings.filter_map(|t| t.finished).max(),
+			requests: RequestStats::default(),
+		}
+	}
+}
+
+/// Shown in place of the artist for tracks without any
+const UNKNOWN_ARTIST: &str = "Unknown Artist";
+
+/// Name of the primary artist, some odd tracks have none
+pub fn first_artist(artists: &[SimplifiedArtist]) -> &str {
+	artists
+		.first()
+		.map(|a| a.name.as_str())
+		.unwrap_or(UNKNOWN_ARTIST)
+}
+
+/// Track fields used in the path and filename templates
+pub struct TemplateTrack<'a> {
+	pub id: String,
+	pub name: &'a str,
+	pub artists: &'a [SimplifiedArtist],
+	pub track_number: u32,
+	pub disc_number: i32,
+	/// Episodes are named by `episode_filename_template`
+	pub kind: DownloadKind,
+}
+
+impl TemplateTrack<'_> {
+	/// Title with featured artists normalized
+	pub fn title(&self, mode: NormalizeFeat) -> String {
+		let featured: Vec<&str> = self
+			.artists
+			.iter()
+			.skip(1)
+			.map(|a| a.name.as_str())
+			.collect();
+		mode.apply(self.name, &featured)
+	}
+}
+
+impl<'a> From<&'a rspotify::model::FullTrack> for TemplateTrack<'a> {
+	fn from(val: &'a rspotify::model::FullTrack) -> Self {
+		TemplateTrack {
+			id: val
+				.id
+				.as_ref()
+				.map(|id| id.id().to_string())
+				.unwrap_or_default(),
+			name: &val.name,
+			artists: &val.artists,
+			track_number: val.track_number,
+			disc_number: val.disc_number,
+			kind: DownloadKind::Track,
+		}
+	}
+}
+
+impl<'a> From<&'a rspotify::model::SimplifiedTrack> for TemplateTrack<'a> {
+	fn from(val: &'a rspotify::model::SimplifiedTrack) -> Self {
+		TemplateTrack {
+			id: val
+				.id
+				.as_ref()
+				.map(|id| id.id().to_string())
+				.unwrap_or_default(),
+			name: &val.name,
+			artists: &val.artists,
+			track_number: val.track_number,
+			disc_number: val.disc_number,
+			kind: DownloadKind::Track,
+		}
+	}
+}
+
+/// Shown in place of the year for albums without a release date
+const UNKNOWN_YEAR: &str = "Unknown Year";
+
+/// Year of a release date, which can be of year, month or day precision
+fn release_year(date: &str) -> &str {
+	parse_year(date).unwrap_or(UNKNOWN_YEAR)
+}
+
+/// Leading four digit year of a date, `None` for empty or garbage dates
+fn parse_year(date: &str) -> Option<&str> {
+	date.get(..4)
+		.filter(|year| *year != "0000" && year.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Date worth tagging, older releases often come with an empty or invalid one
+fn taggable_date(date: &str) -> Option<String> {
+	let date = date.trim();
+	match parse_year(date) {
+		Some(_) => Some(date.to_string()),
+		None => {
+			info!("Skipping invalid release date: {:?}", date);
+			None
+		}
+	}
+}
+
+/// Album artist used for albums over `various_artists_threshold`
+const VARIOUS_ARTISTS: &str = "Various Artists";
+
+/// Album artists, or Various Artists if the album has more distinct artists than the threshold
+fn album_artists(album: &FullAlbum, threshold: usize) -> Vec<String> {
+	if threshold > 0 {
+		let contributors = album
+			.tracks
+			.items
+			.iter()
+			.flat_map(|t| &t.artists)
+			.map(|a| a.name.as_str())
+			.collect::<HashSet<&str>>();
+		if contributors.len() > threshold {
+			return vec![VARIOUS_ARTISTS.to_string()];
+		}
+	}
+	album.artists.iter().map(|a| a.name.to_string()).collect()
+}
+
+/// Search tracks, leaving out the ones Spotify reports as unplayable
+pub async fn search_tracks(
+	spotify: &impl MetadataClient,
+	query: &str,
+	config: &DownloaderConfig,
+) -> Result<Vec<SearchResult>, SpotifyError> {
+	// Spotify only reports playability when a market is given
+	Ok(spotify
+		.search(query, config.market)
+		.await?
+		.into_iter()
+		.filter(|t| t.is_playable != Some(false))
+		.map(SearchResult::from)
+		.collect())
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct SearchResult {
 	pub track_id: String,
 	pub author: String,
 	pub title: String,
 }
 
+impl SearchResult {
+	/// Whether both title and artist appear as whole words in the search term
+	pub fn matches_exactly(&self, query: &str) -> bool {
+		let query = format!(" {} ", match_words(query));
+		[&self.title, &self.author]
+			.iter()
+			.all(|part| query.contains(&format!(" {} ", match_words(part))))
+	}
+}
+
+/// Part of the user library an input can name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Library {
+	LikedSongs,
+	SavedAlbums,
+}
+
+/// Part of the user library input names, by keyword, URI or URL
+fn library_input(input: &str) -> Option<Library> {
+	let input = input.trim();
+	let uri = Spotify::parse_uri(input).unwrap_or_else(|_| input.to_string());
+	if input == "saved" || uri == "spotify:collection:albums" {
+		return Some(Library::SavedAlbums);
+	}
+	let liked = input == "liked"
+		|| uri == "spotify:collection"
+		|| uri == "spotify:collection:tracks"
+		|| (uri.starts_with("spotify:user:") && uri.ends_with(":collection"));
+	liked.then_some(Library::LikedSongs)
+}
+
+/// Lowercase words of a string, ignoring punctuation
+fn match_words(s: &str) -> String {
+	s.to_lowercase()
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|w| !w.is_empty())
+		.collect::<Vec<&str>>()
+		.join(" ")
+}
+
 impl From<rspotify::model::FullTrack> for SearchResult {
 	fn from(val: rspotify::model::FullTrack) -> Self {
 		SearchResult {
 			track_id: val.id.unwrap().id().to_string(),
-			author: val.artists[0].name.to_owned(),
+			author: first_artist(&val.artists).to_owned(),
 			title: val.name,
 		}
 	}
@@ -951,22 +2677,34 @@ impl From<rspotify::model::FullTrack> for Download {
 		if val.is_local == false {
 			Download {
 				id: 0,
+				order: 0,
 				track_id: val.id.unwrap().id().to_string(),
 				title: val.name,
-				subtitle: val
-					.artists
-					.first()
-					.map(|a| a.name.to_owned())
-					.unwrap_or_default(),
+				subtitle: first_artist(&val.artists).to_owned(),
 				state: DownloadState::None,
+				warnings: vec![],
+				timing: DownloadTiming::default(),
+				album_id: val.album.id.map(|id| id.id().to_string()),
+				quality: None,
+				quality_fallback: None,
+				file: None,
+				kind: DownloadKind::Track,
 				}
 		}  else {
 			Download { // Random data, main part is the error state to not download it
 				id: 0,
+				order: 0,
 				track_id: "This should not be a valid ID".to_string(),
 				title: "Local Track: ".to_owned() + &val.name,
 				subtitle: "Invalid Track".to_string(),
 				state: DownloadState::Error("Cannot Download Local Track".to_string()),
+				warnings: vec![],
+				timing: DownloadTiming::default(),
+				album_id: None,
+				quality: None,
+				quality_fallback: None,
+				file: None,
+				kind: DownloadKind::Track,
 			}
 		}
 	}
@@ -976,14 +2714,42 @@ impl From<rspotify::model::SimplifiedTrack> for Download {
 	fn from(val: rspotify::model::SimplifiedTrack) -> Self {
 		Download {
 			id: 0,
+			order: 0,
 			track_id: val.id.unwrap().id().to_string(),
 			title: val.name,
-			subtitle: val
-				.artists
-				.first()
-				.map(|a| a.name.to_owned())
-				.unwrap_or_default(),
+			subtitle: first_artist(&val.artists).to_owned(),
+			state: DownloadState::None,
+			warnings: vec![],
+			timing: DownloadTiming::default(),
+			// Only set when the track comes with its album, like artist and saved album tracks
+			album_id: val
+				.album
+				.and_then(|album| album.id)
+				.map(|id| id.id().to_string()),
+			quality: None,
+			quality_fallback: None,
+			file: None,
+			kind: DownloadKind::Track,
+		}
+	}
+}
+
+impl From<rspotify::model::FullEpisode> for Download {
+	fn from(val: rspotify::model::FullEpisode) -> Self {
+		Download {
+			id: 0,
+			order: 0,
+			track_id: val.id.id().to_string(),
+			title: val.name,
+			subtitle: val.show.name,
 			state: DownloadState::None,
+			warnings: vec![],
+			timing: DownloadTiming::default(),
+			album_id: None,
+			quality: None,
+			quality_fallback: None,
+			file: None,
+			kind: DownloadKind::Episode { number: None },
 		}
 	}
 }
@@ -993,6 +2759,7 @@ impl From<Download> for DownloadJob {
 		DownloadJob {
 			id: val.id,
 			track_id: val.track_id,
+			kind: val.kind,
 		}
 	}
 }
@@ -1004,11 +2771,154 @@ pub enum DownloadState {
 	Downloading(usize, usize),
 	Post,
 	Done,
+	/// Downloaded, but writing the tags failed
+	Untagged(String),
+	Skipped(String),
 	Error(String),
 }
 
+impl DownloadState {
+	/// Whether the download ended, successfully or not
+	pub fn is_finished(&self) -> bool {
+		matches!(
+			self,
+			DownloadState::Done
+				| DownloadState::Untagged(_)
+				| DownloadState::Skipped(_)
+				| DownloadState::Error(_)
+		)
+	}
+
+	/// Whether the file was saved, even if tagging it failed
+	pub fn is_success(&self) -> bool {
+		matches!(self, DownloadState::Done | DownloadState::Untagged(_))
+	}
+
+	/// Whether the download was handed to the worker and hasn't finished yet
+	pub fn is_active(&self) -> bool {
+		matches!(
+			self,
+			DownloadState::Lock | DownloadState::Downloading(..) | DownloadState::Post
+		)
+	}
+
+	/// Whether the download can still be cancelled, postprocessing is left to finish
+	pub fn can_cancel(&self) -> bool {
+		matches!(
+			self,
+			DownloadState::None | DownloadState::Lock | DownloadState::Downloading(..)
+		)
+	}
+
+	/// Whether the download failed or was cancelled, so it can be queued again
+	pub fn can_retry(&self) -> bool {
+		match self {
+			DownloadState::Error(_) => true,
+			DownloadState::Skipped(reason) => *reason == SpotifyError::Cancelled.to_string(),
+			_ => false,
+		}
+	}
+}
+
+/// Next queued download, holding back albums at `per_album_concurrency`
+fn next_download<'a>(
+	queue: &'a mut [Download],
+	config: &DownloaderConfig,
+) -> Option<&'a mut Download> {
+	let mut active: HashMap<&str, usize> = HashMap::new();
+	if config.per_album_concurrency.is_some() {
+		for d in queue.iter().filter(|d| d.state.is_active()) {
+			if let Some(album_id) = &d.album_id {
+				*active.entry(album_id).or_default() += 1;
+			}
+		}
+	}
+	let index = queue.iter().position(|d| {
+		d.state == DownloadState::None
+			&& match (config.per_album_concurrency, &d.album_id) {
+				(Some(limit), Some(album_id)) => {
+					active.get(album_id.as_str()).copied().unwrap_or(0) < limit
+				}
+				_ => true,
+			}
+	})?;
+	Some(&mut queue[index])
+}
+
+/// Album groups downloaded for artists
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlbumGroups {
+	pub album: bool,
+	pub single: bool,
+	pub compilation: bool,
+	pub appears_on: bool,
+}
+
+impl AlbumGroups {
+	/// Enabled groups, as requested from the API
+	pub fn album_types(&self) -> Vec<AlbumType> {
+		[
+			(self.album, AlbumType::Album),
+			(self.single, AlbumType::Single),
+			(self.compilation, AlbumType::Compilation),
+			(self.appears_on, AlbumType::AppearsOn),
+		]
+		.into_iter()
+		.filter(|(enabled, _)| *enabled)
+		.map(|(_, album_type)| album_type)
+		.collect()
+	}
+}
+
+impl Default for AlbumGroups {
+	fn default() -> Self {
+		AlbumGroups {
+			album: true,
+			single: true,
+			compilation: true,
+			appears_on: true,
+		}
+	}
+}
+
+/// Recommendations downloaded for `recommend:` inputs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecommendationsConfig {
+	/// Number of tracks, at most 100
+	pub limit: u32,
+	/// Between 0.0 and 1.0, unset to not target any value
+	pub target_energy: Option<f32>,
+	pub target_danceability: Option<f32>,
+}
+
+impl RecommendationsConfig {
+	/// Tunable attributes, as requested from the API
+	pub fn attributes(&self) -> Vec<RecommendationsAttribute> {
+		let mut attributes = vec![];
+		if let Some(energy) = self.target_energy {
+			attributes.push(RecommendationsAttribute::TargetEnergy(energy));
+		}
+		if let Some(danceability) = self.target_danceability {
+			attributes.push(RecommendationsAttribute::TargetDanceability(danceability));
+		}
+		attributes
+	}
+}
+
+impl Default for RecommendationsConfig {
+	fn default() -> Self {
+		RecommendationsConfig {
+			limit: 20,
+			target_energy: None,
+			target_danceability: None,
+		}
+	}
+}
+
 /// Bitrate of music
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Copy)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Copy)]
 pub enum Quality {
 	Q320,
 	Q256,
@@ -1028,19 +2938,279 @@ impl ToString for Quality {
 	}
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DownloaderConfig {
-	pub concurrent_downloads: usize,
-	pub quality: Quality,
-	pub path: String,
-	pub filename_template: String,
-	pub id3v24: bool,
+/// Casing applied to values substituted into the path and filename templates
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilenameCase {
+	Original,
+	Lower,
+	Upper,
+	Title,
+}
+
+impl FilenameCase {
+	/// Apply casing to value
+	pub fn apply(&self, value: &str) -> String {
+		match self {
+			FilenameCase::Original => value.to_string(),
+			FilenameCase::Lower => value.to_lowercase(),
+			FilenameCase::Upper => value.to_uppercase(),
+			FilenameCase::Title => {
+				let mut out = String::with_capacity(value.len());
+				let mut word_start = true;
+				for c in value.chars() {
+					if word_start {
+						out.extend(c.to_uppercase());
+					} else {
+						out.extend(c.to_lowercase());
+					}
+					word_start = c.is_whitespace();
+				}
+				out
+			}
+		}
+	}
+}
+
+/// How featured artists from the artist list show up in track titles
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalizeFeat {
+	/// Title as Spotify has it
+	Original,
+	/// Append "(feat. X)" when the title doesn't mention the feature
+	Append,
+	/// Strip "(feat. X)" as the artists are tagged already
+	Strip,
+}
+
+impl NormalizeFeat {
+	/// Apply to title, `featured` being every artist but the first
+	pub fn apply(&self, title: &str, featured: &[&str]) -> String {
+		if featured.is_empty() {
+			return title.to_string();
+		}
+		match (self, feat_group(title)) {
+			(NormalizeFeat::Append, None) => format!("{} (feat. {})", title, featured.join(", ")),
+			(NormalizeFeat::Strip, Some((start, end))) => {
+				format!("{}{}", title[..start].trim_end(), &title[end..])
+			}
+			_ => title.to_string(),
+		}
+	}
+}
+
+/// What to do with tracks only available as AAC or MP4
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum NonOggPolicy {
+	/// Download the file as is
+	Accept,
+	/// Convert like `convert_to_mp3` does, with `converter_backend`
+	Convert,
+	/// Skip the track
+	Skip,
+}
+
+/// Converter used by `convert_to_mp3`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ConverterBackend {
+	/// Built in OGG to MP3 converter
+	Internal,
+	/// External ffmpeg binary, `args` are the output options and `extension` the output extension
+	Ffmpeg {
+		args: Vec<String>,
+		extension: String,
+	},
+}
+
+impl ConverterBackend {
+	/// Format of converted files, files ffmpeg writes in other formats can't be tagged
+	pub fn output_format(&self) -> AudioFormat {
+		match self {
+			ConverterBackend::Internal => AudioFormat::Mp3,
+			ConverterBackend::Ffmpeg { extension, .. } => match extension.as_str() {
+				"ogg" | "oga" => AudioFormat::Ogg,
+				"mp3" => AudioFormat::Mp3,
+				"m4a" | "aac" => AudioFormat::Aac,
+				"mp4" => AudioFormat::Mp4,
+				_ => AudioFormat::Unknown,
+			},
+		}
+	}
+
+	/// Extension of converted files
+	pub fn extension(&self, config: &DownloaderConfig) -> String {
+		match self {
+			ConverterBackend::Internal => config.extension(&AudioFormat::Mp3),
+			ConverterBackend::Ffmpeg { extension, .. } => {
+				extension.trim_start_matches('.').to_string()
+			}
+		}
+	}
+}
+
+/// Image format covers are embedded in
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverFormat {
+	/// Keep the cover as Spotify serves it
+	Original,
+	Jpeg,
+	Png,
+}
+
+impl CoverFormat {
+	/// Re-encode cover, the original is kept if it can't be converted
+	pub fn convert(&self, cover: CoverArt) -> CoverArt {
+		let (target, format) = match self {
+			CoverFormat::Original => return cover,
+			CoverFormat::Jpeg => ("image/jpeg", image::ImageOutputFormat::Jpeg(90)),
+			CoverFormat::Png => ("image/png", image::ImageOutputFormat::Png),
+		};
+		if cover.mime == target {
+			return cover;
+		}
+		let mut out = std::io::Cursor::new(Vec::new());
+		// JPEG has no alpha channel
+		let result = image::load_from_memory(&cover.data).and_then(|image| match self {
+			CoverFormat::Jpeg => image.to_rgb8().write_to(&mut out, format),
+			_ => image.write_to(&mut out, format),
+		});
+		match result {
+			Ok(_) => CoverArt {
+				mime: target.to_string(),
+				data: out.into_inner(),
+				..cover
+			},
+			Err(e) => {
+				warn!("Converting cover from {} failed: {}", cover.mime, e);
+				cover
+			}
+		}
+	}
+}
+
+/// Byte range of a bracketed "(feat. X)" style group in title
+fn feat_group(title: &str) -> Option<(usize, usize)> {
+	const PREFIXES: [&str; 6] = ["feat.", "feat ", "ft.", "ft ", "featuring ", "with "];
+	for (start, open) in title.char_indices() {
+		let close = match open {
+			'(' => ')',
+			'[' => ']',
+			_ => continue,
+		};
+		let inner = &title[start + 1..];
+		let lower = inner.to_lowercase();
+		if PREFIXES.iter().any(|p| lower.starts_with(p)) {
+			let end = inner
+				.find(close)
+				.map_or(title.len(), |i| start + 1 + i + close.len_utf8());
+			return Some((start, end));
+		}
+	}
+	None
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloaderConfig {
+	pub concurrent_downloads: usize,
+	/// Album metadata fetched at once while expanding artists and saved albums and tagging
+	pub prefetch_concurrency: usize,
+	pub quality: Quality,
+	pub path: String,
+	pub filename_template: String,
+	/// Filename template of podcast episodes, the show takes the place of the album
+	pub episode_filename_template: String,
+	pub id3v24: bool,
 	pub convert_to_mp3: bool,
+	pub converter_backend: ConverterBackend,
+	/// Save the original stream when converting it fails
+	pub convert_fallback_raw: bool,
+	pub raw_passthrough: bool,
+	/// Write ReplayGain tags, the album gain is recomputed once a whole album is downloaded
+	pub compute_replaygain_album: bool,
 	pub separator: String,
+	pub write_original_date: bool,
+	pub original_date_overrides: HashMap<String, String>,
+	pub multivalue_mode: MultivalueMode,
+	pub tag_fields: HashSet<Field>,
 	pub skip_existing: bool,
+	pub strict_tagging: bool,
+	pub history: bool,
+	/// Download archive file, `history.txt` in the config folder by default
+	pub history_file: Option<PathBuf>,
+	/// Keep the queue on disk while downloading, so `--resume` can pick it up after an interruption
+	pub queue_journal: bool,
+	pub audio_open_timeout: u64,
+	pub audio_open_attempts: u32,
+	/// Times a download is queued again when opening the audio file keeps timing out
+	pub requeue_attempts: u32,
+	/// Restart downloads that receive no data for this long
+	pub stall_timeout_secs: Option<u64>,
+	/// Times a stalled download is started over before it fails
+	pub stall_retries: u32,
+	/// Warn about downloads whose duration differs from Spotify's by more than the tolerance
+	pub verify_duration: bool,
+	pub duration_tolerance_ms: u64,
+	/// Download tracks off by more than the tolerance again, up to `requeue_attempts` times
+	pub retry_duration_mismatch: bool,
+	pub various_artists_threshold: usize,
+	pub album_groups: AlbumGroups,
+	pub recommendations: RecommendationsConfig,
 	pub download_lrc: bool,
+	/// Embed lyrics into the tags, fetched once together with the LRC file
+	pub embed_lyrics: bool,
 	pub sp_dc: String,
 	pub enhanced_lrc: bool,
+	pub lyrics_path: Option<String>,
+	pub lyrics_extension: String,
+	pub lyrics_token_attempts: u32,
+	pub lyrics_providers: Vec<LyricsProvider>,
+	pub lrc_offset_ms: i32,
+	pub lrc_metadata: bool,
+	pub filename_case: FilenameCase,
+	pub normalize_feat: NormalizeFeat,
+	pub space_replacement: Option<String>,
+	pub limit_total: Option<usize>,
+	pub skip_unavailable: bool,
+	pub extensions: HashMap<AudioFormat, String>,
+	pub format_preference: Vec<AudioFormat>,
+	pub per_album_concurrency: Option<usize>,
+	pub confirm_above: Option<usize>,
+	pub cover_format: CoverFormat,
+	pub non_ogg_policy: NonOggPolicy,
+	pub max_albums: Option<usize>,
+	pub hires_cover: bool,
+	/// Embed the cover into each track
+	pub embed_cover: bool,
+	/// Save the cover once per album folder
+	pub save_cover_file: bool,
+	pub generate_sort_tags: bool,
+	/// Leading words moved to the end of sort names
+	pub sort_articles: Vec<String>,
+	/// Embed album images that aren't the front cover as other pictures
+	pub extra_covers: bool,
+	pub rename_existing: bool,
+	pub rename_suffix_format: String,
+	pub tag_provenance: bool,
+	/// Path template per downloaded quality, instead of `path`
+	pub quality_paths: HashMap<Quality, String>,
+	/// Refuse to start when less space is free where tracks are saved
+	pub min_free_space_bytes: Option<u64>,
+	/// Web API request rate to stay under, used for backoff and warnings
+	pub requests_per_minute: usize,
+	/// Queue large collections without asking, set by `--yes` and in watch mode
+	#[serde(skip)]
+	pub assume_yes: bool,
+	/// Country code search results are checked against, e.g. `"US"`
+	pub market: Option<Country>,
+	/// Only queue playlist tracks added on or after this date, set by `--since`
+	#[serde(skip)]
+	pub added_since: Option<NaiveDate>,
+	/// When this run started, for the `%date%` and `%runId%` template variables
+	#[serde(skip)]
+	pub run_started: NaiveDateTime,
 }
 
 impl DownloaderConfig {
@@ -1048,16 +3218,960 @@ impl DownloaderConfig {
 	pub fn new() -> DownloaderConfig {
 		DownloaderConfig {
 			concurrent_downloads: 4,
+			prefetch_concurrency: 4,
 			quality: Quality::Q320,
 			path: "downloads".to_string(),
 			filename_template: "%artist% - %title%".to_string(),
+			episode_filename_template: "%album%/%releaseDate% - %title%".to_string(),
 			id3v24: true,
 			convert_to_mp3: false,
+			converter_backend: ConverterBackend::Internal,
+			convert_fallback_raw: false,
+			raw_passthrough: false,
+			compute_replaygain_album: false,
 			separator: ", ".to_string(),
+			write_original_date: false,
+			original_date_overrides: HashMap::new(),
+			multivalue_mode: MultivalueMode::Separator,
+			tag_fields: Field::all(),
 			skip_existing: true,
+			strict_tagging: false,
+			history: false,
+			history_file: None,
+			queue_journal: true,
+			audio_open_timeout: 30,
+			audio_open_attempts: 3,
+			requeue_attempts: 2,
+			stall_timeout_secs: Some(60),
+			stall_retries: 2,
+			verify_duration: false,
+			duration_tolerance_ms: 2000,
+			retry_duration_mismatch: false,
+			various_artists_threshold: 0,
+			album_groups: AlbumGroups::default(),
+			recommendations: RecommendationsConfig::default(),
 			download_lrc: false,
+			embed_lyrics: false,
 			sp_dc: "https://github.com/akashrchandran/syrics/wiki/Finding-sp_dc".to_string(),
 			enhanced_lrc: true,
+			lyrics_path: None,
+			lyrics_extension: "lrc".to_string(),
+			lyrics_token_attempts: 3,
+			lyrics_providers: vec![LyricsProvider::BeautifulLyrics],
+			lrc_offset_ms: 0,
+			lrc_metadata: true,
+			filename_case: FilenameCase::Original,
+			normalize_feat: NormalizeFeat::Original,
+			space_replacement: None,
+			limit_total: None,
+			skip_unavailable: false,
+			extensions: HashMap::new(),
+			format_preference: vec![AudioFormat::Ogg, AudioFormat::Aac, AudioFormat::Mp3],
+			added_since: None,
+			run_started: Local::now().naive_local(),
+			per_album_concurrency: None,
+			confirm_above: Some(100),
+			cover_format: CoverFormat::Original,
+			non_ogg_policy: NonOggPolicy::Accept,
+			max_albums: None,
+			hires_cover: false,
+			embed_cover: true,
+			save_cover_file: false,
+			extra_covers: false,
+			generate_sort_tags: false,
+			sort_articles: vec!["The".to_string(), "A".to_string(), "An".to_string()],
+			rename_existing: false,
+			rename_suffix_format: " ({n})".to_string(),
+			tag_provenance: false,
+			quality_paths: HashMap::new(),
+			requests_per_minute: 60,
+			min_free_space_bytes: None,
+			assume_yes: false,
+			market: None,
 		}
 	}
+
+	/// Formats of quality to try, ordered and filtered by `format_preference`
+	pub fn file_formats(&self, quality: &Quality) -> Vec<FileFormat> {
+		let formats = quality.get_file_formats();
+		self.format_preference
+			.iter()
+			.flat_map(|preferred| {
+				formats
+					.iter()
+					.filter(move |f| AudioFormat::from(**f) == *preferred)
+			})
+			.copied()
+			.collect()
+	}
+
+	/// File extension of format, overridden by `extensions`
+	pub fn extension(&self, format: &AudioFormat) -> String {
+		match self.extensions.get(format) {
+			Some(extension) => extension.trim_start_matches('.').to_string(),
+			None => format.extension(),
+		}
+	}
+
+	/// First free path with `rename_suffix_format` appended to the file name
+	pub fn unique_path(&self, path: &Path) -> PathBuf {
+		let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+		let extension = path.extension().unwrap_or_default().to_string_lossy();
+		(1..)
+			.map(|n| {
+				let suffix = self.rename_suffix_format.replace("{n}", &n.to_string());
+				path.with_file_name(format!("{}{}.{}", stem, suffix, extension))
+			})
+			.find(|candidate| !candidate.exists())
+			.unwrap()
+	}
+
+	/// Check values serde can't, returns what is wrong
+	pub fn validate(&self) -> Result<(), String> {
+		if !self.rename_suffix_format.contains("{n}") {
+			return Err("rename_suffix_format must contain {n}".to_string());
+		}
+		if self.requests_per_minute == 0 {
+			return Err("requests_per_minute must be at least 1".to_string());
+		}
+		Ok(())
+	}
+
+	/// Fail if any folder tracks are saved to has less than `min_free_space_bytes` free
+	pub fn check_free_space(&self) -> Result<(), SpotifyError> {
+		let required = match self.min_free_space_bytes {
+			Some(required) => required,
+			None => return Ok(()),
+		};
+		let roots: HashSet<PathBuf> = std::iter::once(&self.path)
+			.chain(self.quality_paths.values())
+			.map(|template| download_root(template))
+			.collect();
+		for root in roots {
+			let free = fs2::available_space(&root)?;
+			if free < required {
+				return Err(SpotifyError::InsufficientSpace(
+					root.display().to_string(),
+					free,
+					required,
+				));
+			}
+		}
+		Ok(())
+	}
+
+	/// Configured sp_dc cookie, `None` if unset or still the placeholder
+	pub fn sp_dc(&self) -> Option<&str> {
+		match self.sp_dc.as_str() {
+			"" => None,
+			s if s.starts_with("http") => None,
+			s => Some(s),
+		}
+	}
+
+	/// Format a metadata value for use in the path or filename template
+	pub fn template_value(&self, value: impl AsRef<str>) -> String {
+		let mut value = self.filename_case.apply(value.as_ref());
+		if let Some(replacement) = &self.space_replacement {
+			value = value.replace(' ', replacement);
+		}
+		sanitize(value)
+	}
+
+	/// Values of the template variables for track
+	fn template_tags(&self, track: &TemplateTrack, album: &AlbumTags) -> Vec<(&str, String)> {
+		vec![
+			(
+				"%title%",
+				self.template_value(track.title(self.normalize_feat)),
+			),
+			("%rawTitle%", self.template_value(track.name)),
+			("%artist%", self.template_value(first_artist(track.artists))),
+			(
+				"%artists%",
+				self.template_value(
+					track
+						.artists
+						.iter()
+						.map(|a| a.name.as_str())
+						.collect::<Vec<&str>>()
+						.join(", "),
+				),
+			),
+			("%track%", track.track_number.to_string()),
+			("%0track%", format!("{:02}", track.track_number)),
+			("%disc%", track.disc_number.to_string()),
+			("%0disc%", format!("{:02}", track.disc_number)),
+			("%id%", track.id.to_string()),
+			("%album%", self.template_value(&album.name)),
+			(
+				"%albumArtist%",
+				self.template_value(
+					album
+						.artists
+						.first()
+						.map(|a| a.as_str())
+						.unwrap_or(UNKNOWN_ARTIST),
+				),
+			),
+			(
+				"%albumArtists%",
+				self.template_value(album.artists.join(", ")),
+			),
+			("%year%", release_year(&album.release_date).to_string()),
+			("%releaseDate%", self.template_value(&album.release_date)),
+			(
+				"%episode%",
+				match track.kind {
+					DownloadKind::Episode {
+						number: Some(number),
+					} => number.to_string(),
+					_ => String::new(),
+				},
+			),
+			("%date%", self.run_started.format("%Y-%m-%d").to_string()),
+			(
+				"%runId%",
+				self.run_started.format("%Y-%m-%d_%H-%M-%S").to_string(),
+			),
+		]
+	}
+
+	/// Path of track without extension and path of its lyrics file, known without downloading
+	pub fn track_paths(
+		&self,
+		track: &TemplateTrack,
+		album: &AlbumTags,
+		quality: Quality,
+	) -> (PathBuf, PathBuf) {
+		let mut filename_template = match track.kind {
+			DownloadKind::Track => self.filename_template.clone(),
+			DownloadKind::Episode { .. } => self.episode_filename_template.clone(),
+		};
+		let mut path_template = self
+			.quality_paths
+			.get(&quality)
+			.unwrap_or(&self.path)
+			.clone();
+		let mut lyrics_path_template = self.lyrics_path.clone();
+		for (tag, value) in self.template_tags(track, album) {
+			filename_template = filename_template.replace(tag, &value);
+			path_template = path_template.replace(tag, &value);
+			if let Some(lyrics_path) = lyrics_path_template.as_mut() {
+				*lyrics_path = lyrics_path.replace(tag, &value);
+			}
+		}
+		let path_stem = Path::new(&path_template).join(&filename_template);
+		// Lyrics go next to the audio file unless a separate tree is configured
+		let lyrics_stem = match &lyrics_path_template {
+			Some(lyrics_path) => Path::new(lyrics_path).join(&filename_template),
+			None => path_stem.clone(),
+		};
+		let lyrics_file = PathBuf::from(format!(
+			"{}.{}",
+			lyrics_stem.to_str().unwrap(),
+			self.lyrics_extension
+		));
+		(path_stem, lyrics_file)
+	}
+
+	/// Whether track was downloaded already, with any extension it could have been saved with
+	pub fn existing_audio(&self, track: &TemplateTrack, album: &AlbumTags) -> bool {
+		// Any quality the track could have been downloaded in, they may be saved to different paths
+		let path_stems = std::iter::successors(Some(self.quality), |q| q.fallback())
+			.map(|quality| self.track_paths(track, album, quality).0)
+			.collect::<HashSet<PathBuf>>();
+		let extensions = match self.convert_to_mp3 && !self.raw_passthrough {
+			true => vec![self.converter_backend.extension(self)],
+			false => self
+				.format_preference
+				.iter()
+				.map(|f| self.extension(f))
+				.collect(),
+		};
+		path_stems.iter().any(|path_stem| {
+			extensions
+				.iter()
+				.any(|e| PathBuf::from(format!("{}.{}", path_stem.display(), e)).is_file())
+		})
+	}
+}
+
+impl Default for DownloaderConfig {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::spotify::mock::{artist, full_album, full_episode, simplified_track, MockClient};
+	use id3::TagLike;
+
+	#[test]
+	fn filename_case_title() {
+		let case = FilenameCase::Title;
+		assert_eq!(case.apply("hello WORLD"), "Hello World");
+		assert_eq!(case.apply("  two  spaces "), "  Two  Spaces ");
+		assert_eq!(case.apply("don't stop"), "Don't Stop");
+		assert_eq!(case.apply(""), "");
+	}
+
+	#[test]
+	fn filename_case_non_ascii() {
+		assert_eq!(FilenameCase::Title.apply("éTÉ à paris"), "Été À Paris");
+		assert_eq!(FilenameCase::Title.apply("ærø"), "Ærø");
+		// Some characters grow when uppercased
+		assert_eq!(FilenameCase::Upper.apply("straße"), "STRASSE");
+		assert_eq!(FilenameCase::Title.apply("ßig"), "SSig");
+		assert_eq!(FilenameCase::Lower.apply("ÅNGSTRÖM"), "ångström");
+		assert_eq!(FilenameCase::Original.apply("MiXeD ü"), "MiXeD ü");
+	}
+
+	#[test]
+	fn template_value_case_and_spaces() {
+		let mut config = DownloaderConfig::new();
+		config.filename_case = FilenameCase::Lower;
+		config.space_replacement = Some("_".to_string());
+		assert_eq!(config.template_value("AC/DC Live"), "acdc_live");
+	}
+
+	#[test]
+	fn album_artists_various_over_threshold() {
+		let tracks = vec![
+			simplified_track("t1", &["A"], 1, 1),
+			simplified_track("t2", &["B", "C"], 1, 2),
+			simplified_track("t3", &["A"], 1, 3),
+		];
+		let album = full_album("a", &["A", "B"], AlbumType::Album, tracks);
+		// Three distinct contributors
+		assert_eq!(album_artists(&album, 3), ["A", "B"]);
+		assert_eq!(album_artists(&album, 2), [VARIOUS_ARTISTS]);
+		// Disabled
+		assert_eq!(album_artists(&album, 0), ["A", "B"]);
+	}
+
+	#[test]
+	fn album_tags_fields() {
+		let tracks = vec![
+			simplified_track("t1", &["A"], 1, 1),
+			simplified_track("t2", &["A"], 2, 1),
+		];
+		let mut album = full_album("a", &["A"], AlbumType::Album, tracks);
+		album.genres = vec!["rock".to_string()];
+		let tags = AlbumTags::new(&album, 0);
+		assert_eq!(tags.id, "a");
+		assert_eq!(tags.total_tracks, 2);
+		assert_eq!(tags.total_discs, 2);
+		assert!(!tags.compilation);
+		let fields = tags.fields();
+		assert!(fields.contains(&(Field::Album, vec!["Album a".to_string()])));
+		assert!(fields.contains(&(Field::AlbumArtist, vec!["A".to_string()])));
+		assert!(fields.contains(&(Field::TotalDiscs, vec!["2".to_string()])));
+		assert!(fields.contains(&(Field::Genre, vec!["rock".to_string()])));
+		assert!(!fields.iter().any(|(field, _)| *field == Field::Compilation));
+	}
+
+	#[test]
+	fn album_tags_compilation() {
+		let tracks = vec![
+			simplified_track("t1", &["A"], 1, 1),
+			simplified_track("t2", &["B"], 1, 2),
+		];
+		let album = full_album("a", &["A"], AlbumType::Compilation, tracks.clone());
+		assert!(AlbumTags::new(&album, 0).compilation);
+
+		// Various Artists albums are compilations whatever their type
+		let album = full_album("a", &["A"], AlbumType::Album, tracks);
+		let tags = AlbumTags::new(&album, 1);
+		assert_eq!(tags.artists, [VARIOUS_ARTISTS]);
+		assert!(tags.compilation);
+		assert!(tags
+			.fields()
+			.contains(&(Field::Compilation, vec!["1".to_string()])));
+	}
+
+	#[test]
+	fn show_tags_of_episode() {
+		let image = |url: &str| Image {
+			height: None,
+			url: url.to_string(),
+			width: None,
+		};
+		let mut episode = full_episode("e1");
+		episode.show.images = vec![image("show")];
+		let show = AlbumTags::show(&episode);
+		assert_eq!(show.id, "show");
+		assert_eq!(show.name, "Show");
+		assert_eq!(show.artists, ["Publisher"]);
+		assert_eq!(show.release_date, "2020-01-02");
+		assert_eq!(show.images[0].url, "show");
+
+		episode.images = vec![image("episode")];
+		assert_eq!(AlbumTags::show(&episode).images[0].url, "episode");
+	}
+
+	#[test]
+	fn episode_filename_template_expands() {
+		let episode = full_episode("e1");
+		let show = AlbumTags::show(&episode);
+		let mut episode_track = TemplateTrack {
+			id: "e1".to_string(),
+			name: &episode.name,
+			artists: &[],
+			track_number: 3,
+			disc_number: 1,
+			kind: DownloadKind::Episode { number: Some(3) },
+		};
+		let mut config = DownloaderConfig::new();
+		let (path, _) = config.track_paths(&episode_track, &show, config.quality);
+		assert_eq!(path, Path::new("downloads/Show/2020-01-02 - Episode e1"));
+
+		config.episode_filename_template = "%episode% %title%".to_string();
+		let (path, _) = config.track_paths(&episode_track, &show, config.quality);
+		assert_eq!(path, Path::new("downloads/3 Episode e1"));
+		// Single episodes have no number
+		episode_track.kind = DownloadKind::Episode { number: None };
+		let (path, _) = config.track_paths(&episode_track, &show, config.quality);
+		assert_eq!(path, Path::new("downloads/ Episode e1"));
+	}
+
+	#[test]
+	fn episode_download() {
+		let download = Download::from(full_episode("e1"));
+		assert_eq!(download.track_id, "e1");
+		assert_eq!(download.subtitle, "Show");
+		assert_eq!(download.kind, DownloadKind::Episode { number: None });
+		assert_eq!(
+			DownloadJob::from(download).kind,
+			DownloadKind::Episode { number: None }
+		);
+	}
+
+	#[test]
+	fn first_artist_fallback() {
+		assert_eq!(first_artist(&[artist("A"), artist("B")]), "A");
+		assert_eq!(first_artist(&[]), UNKNOWN_ARTIST);
+	}
+
+	#[test]
+	fn artistless_track_names() {
+		let mut track = simplified_track("t1", &[], 1, 1);
+		track.name = "Song".to_string();
+		let album = full_album("a", &[], AlbumType::Album, vec![track.clone()]);
+		let album = AlbumTags::new(&album, 0);
+		let mut config = DownloaderConfig::new();
+		config.path = "%albumArtist%".to_string();
+		let (path, _) = config.track_paths(&TemplateTrack::from(&track), &album, config.quality);
+		assert_eq!(path, Path::new("Unknown Artist/Unknown Artist - Song"));
+		let download: Download = track.into();
+		assert_eq!(download.subtitle, UNKNOWN_ARTIST);
+	}
+
+	#[test]
+	fn skip_header_short_streams() {
+		for len in [0, 1, 0xa6] {
+			let stream = std::io::Cursor::new(vec![0u8; len]);
+			assert!(matches!(
+				DownloaderInternal::skip_header(stream),
+				Err(SpotifyError::NoDownloadableFile)
+			));
+		}
+	}
+
+	#[test]
+	fn skip_header_keeps_audio() {
+		let mut data = vec![1u8; 0xa7];
+		data.extend([2, 3]);
+		let (rest, header) = DownloaderInternal::skip_header(std::io::Cursor::new(data)).unwrap();
+		assert_eq!(header, [1u8; 0xa7]);
+		assert_eq!(&rest.get_ref()[rest.position() as usize..], [2, 3]);
+
+		// Exactly the header is enough
+		let header_only = std::io::Cursor::new(vec![0u8; 0xa7]);
+		assert!(DownloaderInternal::skip_header(header_only).is_ok());
+	}
+
+	#[test]
+	fn malformed_track_ids() {
+		let parse = |id: &str| -> Result<String, SpotifyError> {
+			Ok(TrackId::from_id(id)?.id().to_string())
+		};
+		assert_eq!(
+			parse("4uLU6hMCjMI75M1A2tKUQC").unwrap(),
+			"4uLU6hMCjMI75M1A2tKUQC"
+		);
+		// URIs and URLs have to be parsed before they are queued
+		for id in ["spotify:track:4uLU6hMCjMI75M1A2tKUQC", "bad id", "é"] {
+			assert!(matches!(parse(id), Err(SpotifyError::SpotifyIdError)));
+		}
+	}
+
+	#[test]
+	fn cover_mime_from_data() {
+		let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+		let jpeg = b"\xff\xd8\xff\xe0\0\x10JFIF\0";
+		assert_eq!(cover_mime(None, png).unwrap(), "image/png");
+		assert_eq!(cover_mime(None, jpeg).unwrap(), "image/jpeg");
+		// The header wins over the data
+		let header = Some("image/webp".to_string());
+		assert_eq!(cover_mime(header, jpeg).unwrap(), "image/webp");
+		assert!(cover_mime(None, b"<html>").is_err());
+		assert!(cover_mime(None, b"").is_err());
+	}
+
+	/// Cover of a small image with transparency, encoded as PNG
+	fn png_cover() -> CoverArt {
+		let image = image::RgbaImage::from_pixel(3, 2, image::Rgba([255, 0, 0, 128]));
+		let mut data = std::io::Cursor::new(Vec::new());
+		image::DynamicImage::ImageRgba8(image)
+			.write_to(&mut data, image::ImageOutputFormat::Png)
+			.unwrap();
+		CoverArt::new("image/png".to_string(), data.into_inner())
+	}
+
+	#[test]
+	fn cover_format_convert() {
+		let cover = png_cover();
+		assert_eq!((cover.width, cover.height), (Some(3), Some(2)));
+
+		let jpeg = CoverFormat::Jpeg.convert(cover.clone());
+		assert_eq!(jpeg.mime, "image/jpeg");
+		assert_eq!(&jpeg.data[..2], [0xff, 0xd8]);
+		assert_eq!((jpeg.width, jpeg.height), (Some(3), Some(2)));
+
+		let png = CoverFormat::Png.convert(jpeg);
+		assert_eq!(png.mime, "image/png");
+		assert_eq!(cover_mime(None, &png.data).unwrap(), "image/png");
+
+		// Covers already in the format are kept as they are
+		assert_eq!(CoverFormat::Png.convert(cover.clone()).data, cover.data);
+		assert_eq!(
+			CoverFormat::Original.convert(cover.clone()).data,
+			cover.data
+		);
+	}
+
+	#[test]
+	fn cover_format_keeps_undecodable() {
+		let cover = CoverArt::new("image/webp".to_string(), b"not an image".to_vec());
+		let converted = CoverFormat::Jpeg.convert(cover);
+		assert_eq!(converted.mime, "image/webp");
+		assert_eq!(converted.data, b"not an image");
+	}
+
+	#[test]
+	fn taggable_date_precisions() {
+		assert_eq!(taggable_date("1999").as_deref(), Some("1999"));
+		assert_eq!(taggable_date("1999-05").as_deref(), Some("1999-05"));
+		assert_eq!(taggable_date("1999-05-17").as_deref(), Some("1999-05-17"));
+		assert_eq!(taggable_date(" 1999-05 ").as_deref(), Some("1999-05"));
+	}
+
+	#[test]
+	fn taggable_date_invalid() {
+		for date in [
+			"",
+			"   ",
+			"0000",
+			"0000-00-00",
+			"19",
+			"abcd-01",
+			"١٩٩٩",
+			"é999",
+		] {
+			assert_eq!(taggable_date(date), None, "{:?}", date);
+		}
+		assert_eq!(release_year(""), UNKNOWN_YEAR);
+	}
+
+	#[test]
+	fn sort_tags_only_differing() {
+		let tags = vec![
+			(
+				Field::Artist,
+				vec!["The Band".to_string(), "Other".to_string()],
+			),
+			(Field::Title, vec!["Song".to_string()]),
+			(Field::Genre, vec!["The Genre".to_string()]),
+		];
+		let sorted = sort_tags(&tags, &["The".to_string()]);
+		assert_eq!(sorted, [(Field::ArtistSort, vec!["Band, The".to_string()])]);
+	}
+
+	/// Empty folder in the temp dir, unique to test
+	fn temp_folder(test: &str) -> PathBuf {
+		let folder =
+			std::env::temp_dir().join(format!("down_on_spot-{}-{}", test, std::process::id()));
+		std::fs::remove_dir_all(&folder).ok();
+		std::fs::create_dir_all(&folder).unwrap();
+		folder
+	}
+
+	#[async_std::test]
+	async fn save_cover_file_by_format() {
+		let folder = temp_folder("cover-file");
+		assert_eq!(cover_file(&folder), None);
+
+		let mut config = DownloaderConfig::new();
+		config.cover_format = CoverFormat::Original;
+		DownloaderInternal::save_cover_file(&folder, &png_cover(), &config).await;
+		assert_eq!(cover_file(&folder), Some(folder.join("cover.png")));
+		std::fs::remove_file(folder.join("cover.png")).unwrap();
+
+		config.cover_format = CoverFormat::Jpeg;
+		DownloaderInternal::save_cover_file(&folder, &png_cover(), &config).await;
+		assert_eq!(cover_file(&folder), Some(folder.join("cover.jpg")));
+		std::fs::remove_dir_all(&folder).ok();
+	}
+
+	#[test]
+	fn partial_file_removed_unless_complete() {
+		let folder = temp_folder("partial-file");
+		let path = folder.join("track.ogg");
+		std::fs::write(&path, b"half").unwrap();
+		drop(PartialFile(Some(&path)));
+		assert!(!path.exists());
+
+		std::fs::write(&path, b"whole").unwrap();
+		let mut partial = PartialFile(Some(&path));
+		partial.0 = None;
+		drop(partial);
+		assert!(path.exists());
+		std::fs::remove_dir_all(&folder).ok();
+	}
+
+	/// OGG page header with the granule position, without segments
+	fn ogg_page(granule: u64) -> Vec<u8> {
+		let mut page = b"OggS\x00\x00".to_vec();
+		page.extend(granule.to_le_bytes());
+		page.extend([0; 13]);
+		page
+	}
+
+	#[test]
+	fn ogg_duration_from_last_page() {
+		let mut head = ogg_page(0);
+		head.extend(b"\x01vorbis\x00\x00\x00\x00\x02");
+		head.extend(44100u32.to_le_bytes());
+
+		let mut tail = vec![7; 100];
+		tail.extend(ogg_page(441000));
+		// "OggS" in audio data and pages without a position don't count
+		tail.extend(b"OggS\x05 audio");
+		tail.extend(ogg_page(u64::MAX));
+		tail.extend([7; 30]);
+		assert_eq!(ogg_duration(&head, &tail), Some(Duration::from_secs(10)));
+
+		assert_eq!(ogg_duration(&head, b"no pages"), None);
+		assert_eq!(ogg_duration(b"no header", &tail), None);
+	}
+
+	#[test]
+	fn mp3_duration_from_frame_headers() {
+		// MPEG 1 layer III at 44.1 kHz, 128 and 320 kbps, 1152 samples each
+		let frame = |bitrate: u8, length: usize| {
+			let mut frame = vec![0xff, 0xfb, bitrate << 4, 0];
+			frame.resize(length, 0);
+			frame
+		};
+		let mut mp3 = b"ID3\x04\x00\x00\x00\x00\x01\x00".to_vec();
+		mp3.extend([0; 128]);
+		for i in 0..100 {
+			match i % 2 {
+				0 => mp3.extend(frame(9, 417)),
+				_ => mp3.extend(frame(14, 1044)),
+			}
+		}
+		mp3.extend(b"TAG");
+		let duration = mp3_duration(&mp3).unwrap();
+		assert_eq!(duration.as_millis(), 100 * 1152 * 1000 / 44100);
+
+		assert_eq!(mp3_duration(b"not an mp3 file"), None);
+	}
+
+	#[test]
+	fn only_conversion_errors_fall_back_to_raw() {
+		assert!(SpotifyError::LameConverterError("Init".to_string()).is_conversion());
+		assert!(SpotifyError::FfmpegError("exit status: 1".to_string()).is_conversion());
+		for e in [
+			SpotifyError::DownloadStalled(30),
+			SpotifyError::Cancelled,
+			SpotifyError::ChannelError,
+			SpotifyError::IoError(std::io::ErrorKind::Other, "disk full".to_string()),
+		] {
+			assert!(!e.is_conversion(), "{}", e);
+		}
+	}
+
+	#[test]
+	fn untagged_downloads_are_successful() {
+		assert!(DownloadState::Done.is_success());
+		assert!(DownloadState::Untagged("no tags".to_string()).is_success());
+		assert!(!DownloadState::Error("failed".to_string()).is_success());
+		assert!(!DownloadState::Skipped(SpotifyError::Cancelled.to_string()).is_success());
+	}
+
+	#[test]
+	fn cancelled_downloads_can_be_retried() {
+		let cancelled = DownloadState::Skipped(SpotifyError::Cancelled.to_string());
+		assert!(cancelled.can_retry());
+		assert!(DownloadState::Error("failed".to_string()).can_retry());
+		assert!(!DownloadState::Skipped(SpotifyError::AlreadyDownloaded.to_string()).can_retry());
+		assert!(!DownloadState::Done.can_retry());
+	}
+
+	fn loudness(gain_db: f32, peak: f32, seconds: f64) -> Loudness {
+		Loudness {
+			gain_db,
+			peak,
+			seconds,
+			album_tracks: 2,
+		}
+	}
+
+	#[test]
+	fn album_replaygain_weighted_by_duration() {
+		let (gain, peak) =
+			album_replaygain(&[loudness(-6.0, 0.5, 100.0), loudness(-6.0, 0.9, 50.0)]).unwrap();
+		assert!((gain + 6.0).abs() < 0.001, "{}", gain);
+		assert_eq!(peak, 0.9);
+		// The louder track dominates, more so the longer it is
+		let (even, _) =
+			album_replaygain(&[loudness(0.0, 1.0, 10.0), loudness(-10.0, 1.0, 10.0)]).unwrap();
+		assert!((even + 7.404).abs() < 0.001, "{}", even);
+		let (long, _) =
+			album_replaygain(&[loudness(0.0, 1.0, 10.0), loudness(-10.0, 1.0, 90.0)]).unwrap();
+		assert!(long < even);
+	}
+
+	#[test]
+	fn album_replaygain_needs_every_track() {
+		assert_eq!(album_replaygain(&[]), None);
+		assert_eq!(album_replaygain(&[loudness(-6.0, 0.5, 100.0)]), None);
+		assert_eq!(
+			album_replaygain(&[loudness(-6.0, 0.5, 0.0), loudness(-6.0, 0.5, 0.0)]),
+			None
+		);
+	}
+
+	#[async_std::test]
+	async fn artist_downloads_carry_album_id() {
+		let client = MockClient::new(2).with_album("a", 1).with_album("b", 3);
+		let downloads: Vec<Download> = client
+			.full_artist("x", &[], None)
+			.await
+			.unwrap()
+			.into_iter()
+			.map(Download::from)
+			.collect();
+		let album_ids: Vec<Option<&str>> =
+			downloads.iter().map(|d| d.album_id.as_deref()).collect();
+		assert_eq!(album_ids, [Some("a"), Some("b"), Some("b"), Some("b")]);
+	}
+
+	/// Ogg stream of an identification and an empty comment header, enough to be tagged
+	fn ogg_file(path: &Path) {
+		let comments = oggvorbismeta::make_comment_header(&oggvorbismeta::CommentHeader {
+			vendor: "test".to_string(),
+			comment_list: vec![],
+		});
+		let mut writer = ogg::PacketWriter::new(vec![]);
+		let packets = [
+			(b"\x01vorbis".to_vec(), ogg::PacketWriteEndInfo::EndPage),
+			(comments, ogg::PacketWriteEndInfo::EndStream),
+		];
+		for (packet, end) in packets {
+			writer
+				.write_packet(packet.into_boxed_slice(), 1, end, 0)
+				.unwrap();
+		}
+		std::fs::write(path, writer.into_inner()).unwrap();
+	}
+
+	/// Names of the vorbis comments of an Ogg file
+	fn ogg_comments(path: &Path) -> Vec<String> {
+		let file = std::fs::File::open(path).unwrap();
+		oggvorbismeta::read_comment_header(file)
+			.comment_list
+			.into_iter()
+			.map(|(name, _)| name.to_uppercase())
+			.collect()
+	}
+
+	fn tag_metadata(date: &str, covers: Vec<(CoverType, CoverArt)>) -> TrackMetadata {
+		TrackMetadata {
+			fields: vec![(Field::Title, vec!["Song".to_string()])],
+			date: taggable_date(date),
+			original_date: None,
+			replaygain: None,
+			covers,
+			lyrics: None,
+		}
+	}
+
+	#[test]
+	fn invalid_release_date_not_tagged() {
+		let folder = temp_folder("invalid-date");
+		let (mp3, ogg) = (folder.join("track.mp3"), folder.join("track.ogg"));
+		for (date, tagged) in [("1999-05", true), ("0000-00-00", false)] {
+			std::fs::write(&mp3, []).unwrap();
+			let metadata = tag_metadata(date, vec![]);
+			let config = DownloaderConfig::new();
+			DownloaderInternal::write_tags(&mp3, AudioFormat::Mp3, metadata, config).unwrap();
+			let tag = id3::Tag::read_from_path(&mp3).unwrap();
+			assert_eq!(tag.title(), Some("Song"));
+			assert_eq!(tag.date_released().is_some(), tagged, "{}", date);
+
+			ogg_file(&ogg);
+			let metadata = tag_metadata(date, vec![]);
+			let config = DownloaderConfig::new();
+			DownloaderInternal::write_tags(&ogg, AudioFormat::Ogg, metadata, config).unwrap();
+			let comments = ogg_comments(&ogg);
+			assert!(comments.contains(&"TITLE".to_string()));
+			assert_eq!(comments.contains(&"DATE".to_string()), tagged, "{}", date);
+		}
+		std::fs::remove_dir_all(&folder).ok();
+	}
+
+	#[test]
+	fn covers_not_embedded_when_disabled() {
+		let folder = temp_folder("no-embed");
+		let (mp3, ogg) = (folder.join("track.mp3"), folder.join("track.ogg"));
+		let covers = || {
+			vec![(
+				CoverType::Front,
+				CoverArt {
+					mime: "image/jpeg".to_string(),
+					data: vec![0xff, 0xd8, 0xff],
+					width: None,
+					height: None,
+				},
+			)]
+		};
+		for embed_cover in [true, false] {
+			let mut config = DownloaderConfig::new();
+			config.embed_cover = embed_cover;
+			config.cover_format = CoverFormat::Original;
+
+			std::fs::write(&mp3, []).unwrap();
+			let metadata = tag_metadata("1999", covers());
+			DownloaderInternal::write_tags(&mp3, AudioFormat::Mp3, metadata, config.clone())
+				.unwrap();
+			let tag = id3::Tag::read_from_path(&mp3).unwrap();
+			assert_eq!(tag.pictures().count() > 0, embed_cover);
+
+			ogg_file(&ogg);
+			let metadata = tag_metadata("1999", covers());
+			DownloaderInternal::write_tags(&ogg, AudioFormat::Ogg, metadata, config).unwrap();
+			let comments = ogg_comments(&ogg);
+			assert_eq!(
+				comments.contains(&"METADATA_BLOCK_PICTURE".to_string()),
+				embed_cover
+			);
+		}
+		std::fs::remove_dir_all(&folder).ok();
+	}
+
+	#[test]
+	fn album_gain_replaces_existing() {
+		let folder = temp_folder("album-gain");
+		let path = folder.join("track.mp3");
+		std::fs::write(&path, []).unwrap();
+		let mut tag_wrap = TagWrap::new(&path, AudioFormat::Mp3).unwrap();
+		let tag = tag_wrap.get_tag();
+		tag.set_replaygain("TRACK", -5.0, 0.8);
+		tag.set_replaygain("ALBUM", -4.0, 0.7);
+		tag.save().unwrap();
+
+		DownloaderInternal::write_album_gain(&path, AudioFormat::Mp3, -6.5, 0.95, true).unwrap();
+		let tag = id3::Tag::read_from_path(&path).unwrap();
+		let values = |description: &str| -> Vec<String> {
+			tag.extended_texts()
+				.filter(|t| t.description == description)
+				.map(|t| t.value.clone())
+				.collect()
+		};
+		assert_eq!(values("REPLAYGAIN_TRACK_GAIN"), ["-5.00 dB"]);
+		assert_eq!(values("REPLAYGAIN_ALBUM_GAIN"), ["-6.50 dB"]);
+		assert_eq!(values("REPLAYGAIN_ALBUM_PEAK"), ["0.950000"]);
+		std::fs::remove_dir_all(&folder).ok();
+	}
+
+	#[async_std::test]
+	async fn saved_album_downloads_get_album_gain() {
+		let folder = temp_folder("saved-album-gain");
+		let client = MockClient::new(2).with_saved_album("a", 2);
+		let mut downloads: Vec<Download> = client
+			.full_saved_albums()
+			.await
+			.unwrap()
+			.into_iter()
+			.map(Download::from)
+			.collect();
+		for d in &mut downloads {
+			let path = folder.join(format!("{}.mp3", d.track_id));
+			std::fs::write(&path, []).unwrap();
+			d.file = Some(SavedFile {
+				path,
+				format: AudioFormat::Mp3,
+				album: "Album a".to_string(),
+				loudness: Some(loudness(-6.0, 0.5, 100.0)),
+			});
+		}
+		// Completed albums are found by the album id of their downloads
+		let album_id = downloads[0].album_id.clone().unwrap();
+		write_album_replaygain(&album_id, &downloads, &DownloaderConfig::new()).await;
+		for d in &downloads {
+			let tag = id3::Tag::read_from_path(&d.file.as_ref().unwrap().path).unwrap();
+			let gains: Vec<&str> = tag
+				.extended_texts()
+				.filter(|t| t.description == "REPLAYGAIN_ALBUM_GAIN")
+				.map(|t| t.value.as_str())
+				.collect();
+			assert_eq!(gains, ["-6.00 dB"], "{}", d.track_id);
+		}
+		std::fs::remove_dir_all(&folder).ok();
+	}
+
+	#[test]
+	fn library_inputs() {
+		for input in [
+			"liked",
+			" liked ",
+			"spotify:collection",
+			"spotify:collection:tracks",
+			"spotify:user:someone:collection",
+			"https://open.spotify.com/collection/tracks",
+		] {
+			assert_eq!(library_input(input), Some(Library::LikedSongs), "{}", input);
+		}
+		for input in [
+			"saved",
+			"spotify:collection:albums",
+			"https://open.spotify.com/collection/albums",
+		] {
+			assert_eq!(
+				library_input(input),
+				Some(Library::SavedAlbums),
+				"{}",
+				input
+			);
+		}
+		for input in [
+			"saved songs",
+			"spotify:album:x",
+			"https://open.spotify.com/track/x",
+		] {
+			assert_eq!(library_input(input), None, "{}", input);
+		}
+	}
+
+	#[test]
+	fn unknown_keys_are_rejected() {
+		let e = serde_json::from_str::<DownloaderConfig>("{\n\"qualty\": \"Q320\"}").unwrap_err();
+		assert!(e.to_string().contains("qualty"));
+		assert_eq!(e.line(), 2);
+	}
 }
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::downloader::AudioFormat;
+
 #[derive(Debug, Clone)]
 pub enum SpotifyError {
 	Error(String),
@@ -10,6 +12,9 @@ pub enum SpotifyError {
 	SpotifyIdError,
 	ChannelError,
 	AudioKeyError,
+	AudioFileTimeout,
+	NoDownloadableFile,
+	MissingUserScope(String),
 	LameConverterError(String),
 	JoinError,
 	RSpotify(String),
@@ -20,9 +25,31 @@ pub enum SpotifyError {
 	Reqwest(String),
 	InvalidFormat,
 	AlreadyDownloaded,
+	LyricsToken(String),
+	LyricsNotFound,
+	UnsupportedTagFormat(AudioFormat),
+	Watch(String),
+	InvalidSettings(String, String),
+	Cancelled,
+	SkippedFormat(AudioFormat),
+	InsufficientSpace(String, u64, u64),
+	InvalidReport(String, String),
+	DownloadStalled(u64),
+	FfmpegError(String),
+	DurationMismatch(String),
 }
 
 impl std::error::Error for SpotifyError {}
+
+impl SpotifyError {
+	/// Whether converting failed, the audio itself may still be fine
+	pub fn is_conversion(&self) -> bool {
+		matches!(
+			self,
+			SpotifyError::LameConverterError(_) | SpotifyError::FfmpegError(_)
+		)
+	}
+}
 impl fmt::Display for SpotifyError {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		match self {
@@ -34,6 +61,11 @@ impl fmt::Display for SpotifyError {
 			SpotifyError::SpotifyIdError => write!(f, "Invalid Spotify ID"),
 			SpotifyError::ChannelError => write!(f, "Channel Error"),
 			SpotifyError::AudioKeyError => write!(f, "Audio Key Error"),
+			SpotifyError::AudioFileTimeout => write!(f, "Opening audio file timed out"),
+			SpotifyError::NoDownloadableFile => write!(f, "No downloadable file, stream too short"),
+			SpotifyError::MissingUserScope(scope) => {
+				write!(f, "Missing {} scope, log in with --login first", scope)
+			}
 			SpotifyError::LameConverterError(e) => write!(f, "Lame error: {}", e),
 			SpotifyError::JoinError => write!(f, "Tokio Join Error"),
 			SpotifyError::RSpotify(e) => write!(f, "Spotify Error: {}", e),
@@ -44,6 +76,28 @@ impl fmt::Display for SpotifyError {
 			SpotifyError::Reqwest(e) => write!(f, "Reqwest Error: {}", e),
 			SpotifyError::InvalidFormat => write!(f, "Invalid Format!"),
 			SpotifyError::AlreadyDownloaded => write!(f, "Already Downloaded"),
+			SpotifyError::LyricsToken(e) => write!(f, "Lyrics token fetch failed: {}", e),
+			SpotifyError::LyricsNotFound => write!(f, "Lyrics not found"),
+			SpotifyError::UnsupportedTagFormat(format) => {
+				write!(f, "Tagging {:?} files is not supported", format)
+			}
+			SpotifyError::Watch(e) => write!(f, "Watch Error: {}", e),
+			SpotifyError::InvalidSettings(path, e) => write!(f, "{} is invalid: {}", path, e),
+			SpotifyError::Cancelled => write!(f, "Cancelled"),
+			SpotifyError::SkippedFormat(format) => {
+				write!(f, "Only available as {:?}, skipped", format)
+			}
+			SpotifyError::InsufficientSpace(path, free, required) => write!(
+				f,
+				"Only {} bytes free at {}, min_free_space_bytes requires {}",
+				free, path, required
+			),
+			SpotifyError::DownloadStalled(secs) => write!(f, "No data received for {}s", secs),
+			SpotifyError::FfmpegError(e) => write!(f, "ffmpeg error: {}", e),
+			SpotifyError::DurationMismatch(e) => write!(f, "Duration mismatch: {}", e),
+			SpotifyError::InvalidReport(path, e) => {
+				write!(f, "{} is not a valid report: {}", path, e)
+			}
 		}
 	}
 }
@@ -105,6 +159,12 @@ impl From<rspotify::ClientError> for SpotifyError {
 	}
 }
 
+impl From<rspotify::model::IdError> for SpotifyError {
+	fn from(_: rspotify::model::IdError) -> Self {
+		Self::SpotifyIdError
+	}
+}
+
 impl From<serde_json::Error> for SpotifyError {
 	fn from(e: serde_json::Error) -> Self {
 		Self::Serde(e.to_string(), e.line(), e.column())
@@ -134,3 +194,9 @@ impl From<lewton::VorbisError> for SpotifyError {
 		SpotifyError::Error(format!("Lewton: {}", e))
 	}
 }
+
+impl From<notify::Error> for SpotifyError {
+	fn from(e: notify::Error) -> Self {
+		Self::Watch(e.to_string())
+	}
+}
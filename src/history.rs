@@ -0,0 +1,53 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use crate::error::SpotifyError;
+
+/// Track ids of finished downloads, kept across runs so interrupted downloads can resume
+#[derive(Debug, Clone)]
+pub struct History {
+	path: PathBuf,
+	ids: HashSet<String>,
+}
+
+impl History {
+	/// Load from file, one track id per line. Missing file is an empty history
+	pub async fn load(path: impl AsRef<Path>) -> Result<History, SpotifyError> {
+		let path = path.as_ref().to_owned();
+		let ids = match fs::read_to_string(&path).await {
+			Ok(data) => data
+				.lines()
+				.map(|l| l.trim())
+				.filter(|l| !l.is_empty())
+				.map(|l| l.to_string())
+				.collect(),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+			Err(e) => return Err(e.into()),
+		};
+		Ok(History { path, ids })
+	}
+
+	/// Whether the track was already downloaded
+	pub fn contains(&self, track_id: &str) -> bool {
+		self.ids.contains(track_id)
+	}
+
+	/// Append finished track, written immediately so an interrupted run keeps its progress
+	pub async fn record(&mut self, track_id: &str) -> Result<(), SpotifyError> {
+		if !self.ids.insert(track_id.to_string()) {
+			return Ok(());
+		}
+		if let Some(parent) = self.path.parent() {
+			fs::create_dir_all(parent).await?;
+		}
+		let mut file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&self.path)
+			.await?;
+		file.write_all(format!("{}\n", track_id).as_bytes()).await?;
+		Ok(())
+	}
+}
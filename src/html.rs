@@ -0,0 +1,114 @@
+use std::fmt::Write;
+use std::path::Path;
+use tokio::fs;
+
+use crate::downloader::{cover_file, Download, DownloadState};
+use crate::error::SpotifyError;
+
+/// Write a static page listing the saved tracks of downloads, linking to their files
+pub async fn write_index(
+	path: impl AsRef<Path>,
+	downloads: &[Download],
+) -> Result<(), SpotifyError> {
+	let path = path.as_ref();
+	// Links are relative to the page where possible, so the folder can be moved or shared
+	let base = path
+		.parent()
+		.filter(|p| !p.as_os_str().is_empty())
+		.unwrap_or(Path::new("."));
+	let base = fs::canonicalize(base).await?;
+
+	let mut rows = String::new();
+	for download in downloads {
+		let file = match (&download.state, &download.file) {
+			(DownloadState::Done | DownloadState::Untagged(_), Some(file)) => file,
+			_ => continue,
+		};
+		let track = link(&base, &file.path).await;
+		let cover = match file.path.parent().and_then(cover_file) {
+			Some(cover) => format!(
+				"<img src=\"{}\" alt=\"\" width=\"48\" height=\"48\">",
+				link(&base, &cover).await
+			),
+			None => String::new(),
+		};
+		writeln!(
+			rows,
+			"<tr><td>{}</td><td>{}</td><td><a href=\"{}\">{}</a></td><td>{}</td></tr>",
+			cover,
+			escape(&download.subtitle),
+			track,
+			escape(&download.title),
+			escape(&file.album)
+		)
+		.unwrap();
+	}
+
+	let page = format!(
+		"<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>DownOnSpot</title>
+<style>
+body {{ font-family: sans-serif; }}
+td, th {{ padding: 4px 8px; text-align: left; }}
+</style>
+</head>
+<body>
+<table>
+<tr><th></th><th>Artist</th><th>Title</th><th>Album</th></tr>
+{}</table>
+</body>
+</html>
+",
+		rows
+	);
+	fs::write(path, page).await?;
+	Ok(())
+}
+
+/// Escaped link to file, relative to base if the file is inside it
+async fn link(base: &Path, file: &Path) -> String {
+	let file = fs::canonicalize(file)
+		.await
+		.unwrap_or_else(|_| file.to_owned());
+	let href = match file.strip_prefix(base) {
+		Ok(relative) => encode(&relative.to_string_lossy().replace('\\', "/")),
+		Err(_) => match url::Url::from_file_path(&file) {
+			Ok(url) => url.to_string(),
+			Err(_) => encode(&file.to_string_lossy()),
+		},
+	};
+	escape(&href)
+}
+
+/// Percent encode a path for use in a link, keeping its separators
+fn encode(path: &str) -> String {
+	let mut encoded = String::with_capacity(path.len());
+	for byte in path.bytes() {
+		match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+				encoded.push(byte as char)
+			}
+			_ => write!(encoded, "%{:02X}", byte).unwrap(),
+		}
+	}
+	encoded
+}
+
+/// Escape text for HTML, titles often contain & and quotes
+fn escape(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&#39;"),
+			_ => escaped.push(c),
+		}
+	}
+	escaped
+}
@@ -0,0 +1,111 @@
+use rspotify::model::{FullTrack, Id, SimplifiedTrack};
+use serde::Serialize;
+
+use crate::downloader::{first_artist, DownloaderConfig};
+use crate::error::SpotifyError;
+use crate::spotify::{MetadataClient, Spotify, SpotifyItem};
+
+/// Single row of a track listing
+#[derive(Debug, Clone, Serialize)]
+pub struct ListedTrack {
+	pub index: usize,
+	pub id: Option<String>,
+	pub artist: String,
+	pub title: String,
+	pub duration_ms: i64,
+	pub available: bool,
+}
+
+impl ListedTrack {
+	/// Tab separated row, so output can be piped into other tools
+	pub fn row(&self) -> String {
+		let seconds = self.duration_ms / 1000;
+		format!(
+			"{}\t{}\t{}\t{}:{:02}\t{}",
+			self.index,
+			self.artist,
+			self.title,
+			seconds / 60,
+			seconds % 60,
+			match self.available {
+				true => "available",
+				false => "unavailable",
+			}
+		)
+	}
+}
+
+impl From<FullTrack> for ListedTrack {
+	fn from(val: FullTrack) -> Self {
+		ListedTrack {
+			index: 0,
+			id: val.id.map(|id| id.id().to_string()),
+			artist: first_artist(&val.artists).to_owned(),
+			title: val.name,
+			duration_ms: val.duration.num_milliseconds(),
+			// Markets are only listed when no market was requested
+			available: !val.is_local
+				&& val.is_playable.unwrap_or(!val.available_markets.is_empty()),
+		}
+	}
+}
+
+impl From<SimplifiedTrack> for ListedTrack {
+	fn from(val: SimplifiedTrack) -> Self {
+		ListedTrack {
+			index: 0,
+			id: val.id.map(|id| id.id().to_string()),
+			artist: first_artist(&val.artists).to_owned(),
+			title: val.name,
+			duration_ms: val.duration.num_milliseconds(),
+			available: !val.is_local
+				&& val.is_playable.unwrap_or(
+					val.available_markets
+						.is_none_or(|markets| !markets.is_empty()),
+				),
+		}
+	}
+}
+
+/// Resolve URL or URI into its full track list without queueing anything
+pub async fn list_uri(
+	spotify: &Spotify,
+	uri: &str,
+	config: &DownloaderConfig,
+) -> Result<Vec<ListedTrack>, SpotifyError> {
+	let uri = Spotify::parse_uri(uri)?;
+	let groups = config.album_groups.album_types();
+	let tracks: Vec<ListedTrack> = match spotify.resolve_uri(&uri, config.market).await? {
+		SpotifyItem::Track(t) => vec![t.into()],
+		SpotifyItem::Album(a) => into_listed(spotify.full_album(a.id.id()).await?),
+		SpotifyItem::Playlist(p) => {
+			into_listed(spotify.full_playlist(p.id.id(), config.added_since).await?)
+		}
+		SpotifyItem::Artist(a) => into_listed(
+			spotify
+				.full_artist(a.id.id(), &groups, config.max_albums)
+				.await?,
+		),
+		// Listings are of tracks, so `--sync` can't mistake episodes for them
+		SpotifyItem::Episode(_) | SpotifyItem::Show(_) => {
+			error!("Podcast episodes and shows can't be listed: {}", uri);
+			return Err(SpotifyError::Unavailable);
+		}
+		SpotifyItem::Other(u) => {
+			error!("Unsupported URI: {}", u);
+			return Err(SpotifyError::Unavailable);
+		}
+	};
+	Ok(tracks
+		.into_iter()
+		.enumerate()
+		.map(|(i, mut track)| {
+			track.index = i + 1;
+			track
+		})
+		.collect())
+}
+
+fn into_listed<T: Into<ListedTrack>>(tracks: Vec<T>) -> Vec<ListedTrack> {
+	tracks.into_iter().map(Into::into).collect()
+}
@@ -0,0 +1,616 @@
+use async_std::sync::Mutex;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::downloader::DownloaderConfig;
+use crate::error::SpotifyError;
+
+/// Attempts made to download lyrics before giving up
+const LRC_ATTEMPTS: u32 = 3;
+
+/// beautiful-lyrics backend the synced lyrics are fetched from
+const BEAUTIFUL_LYRICS_API: &str = "https://beautiful-lyrics.socalifornian.live";
+
+/// lrclib.net asks clients to identify themselves
+const LRCLIB_USER_AGENT: &str = "DownOnSpot (https://github.com/oSumAtrIX/DownOnSpot)";
+
+/// Seconds a search result may be off from the track, lrclib.net's exact lookup allows as much
+const LRCLIB_DURATION_TOLERANCE: f64 = 2.0;
+
+/// Where lyrics are downloaded from
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LyricsProvider {
+	/// Synced lyrics from the beautiful-lyrics backend, looked up by Spotify id
+	BeautifulLyrics,
+	/// Synced or plain lyrics from lrclib.net, looked up by metadata
+	Lrclib,
+}
+
+/// Track lyrics are looked up by, providers without Spotify ids match the metadata
+#[derive(Debug, Clone)]
+pub struct LyricsQuery {
+	pub id: Option<String>,
+	pub isrc: Option<String>,
+	pub artist: String,
+	pub title: String,
+	pub album: String,
+	pub duration: i64,
+}
+
+/// Lyrics in LRC format
+#[derive(Debug, Clone)]
+pub struct Lyrics {
+	pub lrc: String,
+}
+
+impl Lyrics {
+	/// LRC text with the header tags enabled in config
+	pub fn to_lrc(&self, query: &LyricsQuery, config: &DownloaderConfig) -> String {
+		let mut lrc = String::new();
+		if config.lrc_metadata {
+			lrc.push_str(&format!("[ti:{}]\n", query.title));
+			lrc.push_str(&format!("[ar:{}]\n", query.artist));
+			lrc.push_str(&format!("[al:{}]\n", query.album));
+			lrc.push_str(&format!(
+				"[length:{:02}:{:02}]\n",
+				query.duration / 60,
+				query.duration % 60
+			));
+		}
+		// Players shift the timestamps by the offset, positive values show lyrics earlier
+		if config.lrc_offset_ms != 0 {
+			lrc.push_str(&format!("[offset:{}]\n", config.lrc_offset_ms));
+		}
+		lrc.push_str(&self.lrc);
+		lrc
+	}
+
+	/// Save as LRC file
+	pub async fn write_lrc(
+		&self,
+		path: impl AsRef<Path>,
+		query: &LyricsQuery,
+		config: &DownloaderConfig,
+	) -> Result<(), SpotifyError> {
+		let lrc = self.to_lrc(query, config);
+		if let Some(parent) = path.as_ref().parent() {
+			tokio::fs::create_dir_all(parent).await?;
+		}
+		let mut file = File::create(&path).await?;
+		file.write_all(lrc.as_bytes()).await?;
+		Ok(())
+	}
+}
+
+/// Provider of lyrics, tried in the order of `lyrics_providers`
+pub trait LyricsSource {
+	/// Fetch lyrics of track, `None` if the provider has none
+	async fn fetch(
+		&self,
+		query: &LyricsQuery,
+		config: &DownloaderConfig,
+	) -> Result<Option<Lyrics>, SpotifyError>;
+}
+
+/// surfbryce's beautiful-lyrics backend, authorized with a Spotify web token
+pub struct BeautifulLyrics {
+	token: Mutex<Option<LyricsToken>>,
+}
+
+impl BeautifulLyrics {
+	pub fn new() -> BeautifulLyrics {
+		BeautifulLyrics {
+			token: Mutex::new(None),
+		}
+	}
+
+	/// Get access token, cached for the whole run and refreshed once expired
+	async fn token(&self, sp_dc: Option<&str>, attempts: u32) -> Result<String, SpotifyError> {
+		let mut cached = self.token.lock().await;
+		if let Some(token) = cached.as_ref() {
+			if !token.is_expired() {
+				return Ok(token.access_token.clone());
+			}
+		}
+
+		let mut attempt = 1;
+		let token = loop {
+			match BeautifulLyrics::fetch_token(sp_dc).await {
+				Ok(token) => break token,
+				Err(e) if attempt < attempts => {
+					warn!(
+						"Lyrics token attempt {} failed: {}, retrying...",
+						attempt, e
+					);
+					async_std::task::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+					attempt += 1;
+				}
+				Err(e) => return Err(SpotifyError::LyricsToken(e.to_string())),
+			}
+		};
+		let access_token = token.access_token.clone();
+		*cached = Some(token);
+		Ok(access_token)
+	}
+
+	/// Request access token, anonymously if no sp_dc cookie is configured
+	async fn fetch_token(sp_dc: Option<&str>) -> Result<LyricsToken, SpotifyError> {
+		let mut request = reqwest::Client::new()
+			.get("https://open.spotify.com/get_access_token")
+			.header("Accept", "application/json")
+			.header("User-Agent", "User-Agent: Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/101.0.0.0 Safari/537.36");
+		match sp_dc {
+			Some(sp_dc) => request = request.header("Cookie", format!("sp_dc={}", sp_dc)),
+			None => info!("No sp_dc configured, requesting anonymous lyrics token."),
+		}
+		let token_res = request.send().await?;
+
+		if token_res.status() != StatusCode::OK {
+			return Err(SpotifyError::Error(format!(
+				"Failed to get token! {}",
+				token_res.status(),
+			)));
+		}
+
+		Ok(serde_json::from_str(&token_res.text().await?)?)
+	}
+
+	/// Download synced lyrics and convert to LRC format
+	async fn fetch_lrc(
+		api: &str,
+		id: &str,
+		token: &str,
+		enhanced_lrc: bool,
+	) -> Result<Option<Lyrics>, SpotifyError> {
+		let url = format!("{}/lyrics/{}", api, id);
+		let client = reqwest::Client::new();
+
+		let lyrics = client
+			.get(url)
+			.header("Authorization", format!("Bearer {}", token))
+			.send()
+			.await?;
+
+		if lyrics.status() == StatusCode::INTERNAL_SERVER_ERROR {
+			return Ok(None);
+		} else if lyrics.status() != StatusCode::OK {
+			return Err(SpotifyError::Error(format!(
+				"Failed to fetch lyrics! {}",
+				lyrics.status()
+			)));
+		}
+
+		BeautifulLyrics::parse_lrc(&lyrics.text().await?, enhanced_lrc)
+	}
+
+	/// Convert response body to LRC, `None` if it's empty
+	fn parse_lrc(body: &str, enhanced_lrc: bool) -> Result<Option<Lyrics>, SpotifyError> {
+		// Chunked responses have no Content-Length, so the body itself is checked
+		if body.trim().is_empty() {
+			return Ok(None);
+		}
+		// A malformed body is an error, so it ends up as a warning instead of a crash
+		let lyrics: SyncedLyrics = serde_json::from_str(body)?;
+		Ok(Some(Lyrics {
+			lrc: lyrics.to_lrc(enhanced_lrc),
+		}))
+	}
+}
+
+impl LyricsSource for BeautifulLyrics {
+	/// Retries with backoff, except when the token can't be fetched
+	async fn fetch(
+		&self,
+		query: &LyricsQuery,
+		config: &DownloaderConfig,
+	) -> Result<Option<Lyrics>, SpotifyError> {
+		let Some(id) = query.id.as_deref() else {
+			return Ok(None);
+		};
+		let mut attempt = 1;
+		loop {
+			let result = match self
+				.token(config.sp_dc(), config.lyrics_token_attempts)
+				.await
+			{
+				Ok(token) => {
+					BeautifulLyrics::fetch_lrc(
+						BEAUTIFUL_LYRICS_API,
+						id,
+						&token,
+						config.enhanced_lrc,
+					)
+					.await
+				}
+				Err(e) => Err(e),
+			};
+			match result {
+				Ok(lyrics) => return Ok(lyrics),
+				// Token fetch is already retried on its own
+				Err(e @ SpotifyError::LyricsToken(_)) => return Err(e),
+				Err(e) if attempt < LRC_ATTEMPTS => {
+					warn!(
+						"Lyrics attempt {} for {} failed: {}, retrying...",
+						attempt, id, e
+					);
+					async_std::task::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+					attempt += 1;
+				}
+				Err(e) => return Err(e),
+			}
+		}
+	}
+}
+
+/// Response of the beautiful-lyrics backend, by the kind of sync it has
+#[derive(Debug, Deserialize)]
+#[serde(tag = "Type")]
+enum SyncedLyrics {
+	Syllable {
+		#[serde(rename = "Content")]
+		content: Vec<SyllableLine>,
+	},
+	Line {
+		#[serde(rename = "Content")]
+		content: Vec<TimedLine>,
+	},
+	Static {
+		#[serde(rename = "Lines")]
+		lines: Vec<StaticLine>,
+	},
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SyllableLine {
+	lead: SyllableLead,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SyllableLead {
+	start_time: f64,
+	syllables: Vec<Syllable>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Syllable {
+	start_time: f64,
+	text: String,
+	is_part_of_word: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TimedLine {
+	start_time: f64,
+	text: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StaticLine {
+	text: String,
+}
+
+impl SyncedLyrics {
+	/// Convert to LRC, with word timestamps if enhanced_lrc is enabled
+	fn to_lrc(&self, enhanced_lrc: bool) -> String {
+		let mut lrc = String::new();
+		match self {
+			SyncedLyrics::Syllable { content } => {
+				for line in content {
+					lrc.push_str(&format!("[{}]", lrc_timestamp(line.lead.start_time)));
+					for syllable in &line.lead.syllables {
+						if enhanced_lrc {
+							lrc.push_str(&format!("<{}>", lrc_timestamp(syllable.start_time)));
+						}
+						lrc.push_str(&syllable.text);
+						if !syllable.is_part_of_word {
+							lrc.push(' ');
+						}
+					}
+					lrc.push('\n');
+				}
+			}
+			SyncedLyrics::Line { content } => {
+				for line in content {
+					lrc.push_str(&format!(
+						"[{}]{}\n",
+						lrc_timestamp(line.start_time),
+						line.text
+					));
+				}
+			}
+			SyncedLyrics::Static { lines } => {
+				for line in lines {
+					lrc.push_str(&format!("{}\n", line.text));
+				}
+			}
+		}
+		lrc
+	}
+}
+
+/// Seconds as an LRC mm:ss.xx timestamp, hundredths are truncated
+fn lrc_timestamp(seconds: f64) -> String {
+	let ms = (seconds * 1000.0) as u64;
+	format!(
+		"{:02}:{:02}.{:02}",
+		ms / 60000,
+		(ms % 60000) / 1000,
+		(ms % 1000) / 10
+	)
+}
+
+/// lrclib.net, which has no Spotify id lookup so tracks are matched by metadata.
+/// When the exact lookup misses, search results with the track's ISRC are preferred
+pub struct Lrclib;
+
+impl Lrclib {
+	/// Body of a successful request, `None` if nothing was found
+	async fn request(path: &str, query: &[(&str, &str)]) -> Result<Option<String>, SpotifyError> {
+		let res = reqwest::Client::new()
+			.get(format!("https://lrclib.net/api/{}", path))
+			.query(query)
+			.header("User-Agent", LRCLIB_USER_AGENT)
+			.send()
+			.await?;
+
+		if res.status() == StatusCode::NOT_FOUND {
+			return Ok(None);
+		} else if res.status() != StatusCode::OK {
+			return Err(SpotifyError::Error(format!(
+				"Failed to fetch lyrics from lrclib! {}",
+				res.status()
+			)));
+		}
+		Ok(Some(res.text().await?))
+	}
+
+	/// Search result of the same recording, by ISRC if lrclib has one, otherwise by duration
+	fn best_match(results: Vec<LrclibLyrics>, query: &LyricsQuery) -> Option<LrclibLyrics> {
+		let same_isrc = |result: &LrclibLyrics| match (&result.isrc, &query.isrc) {
+			(Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+			_ => false,
+		};
+		let close = |result: &LrclibLyrics| {
+			result
+				.duration
+				.is_some_and(|d| (d - query.duration as f64).abs() <= LRCLIB_DURATION_TOLERANCE)
+		};
+		let position = results
+			.iter()
+			.position(same_isrc)
+			.or_else(|| results.iter().position(close))?;
+		results.into_iter().nth(position)
+	}
+}
+
+impl LyricsSource for Lrclib {
+	async fn fetch(
+		&self,
+		query: &LyricsQuery,
+		_config: &DownloaderConfig,
+	) -> Result<Option<Lyrics>, SpotifyError> {
+		let duration = query.duration.to_string();
+		let found = Lrclib::request(
+			"get",
+			&[
+				("artist_name", query.artist.as_str()),
+				("track_name", query.title.as_str()),
+				("album_name", query.album.as_str()),
+				("duration", duration.as_str()),
+			],
+		)
+		.await?;
+		let lyrics = match found {
+			Some(body) => Some(serde_json::from_str::<LrclibLyrics>(&body)?),
+			// Album names and durations differ between releases, the search is less strict
+			None => match Lrclib::request(
+				"search",
+				&[
+					("artist_name", query.artist.as_str()),
+					("track_name", query.title.as_str()),
+				],
+			)
+			.await?
+			{
+				Some(body) => Lrclib::best_match(serde_json::from_str(&body)?, query),
+				None => None,
+			},
+		};
+
+		// Synced lyrics are LRC formatted already, plain lyrics are the fallback
+		Ok(lyrics
+			.and_then(|l| l.synced_lyrics.or(l.plain_lyrics))
+			.filter(|l| !l.trim().is_empty())
+			.map(|lrc| Lyrics { lrc }))
+	}
+}
+
+/// Response of lrclib.net, a single record or one of the search results
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LrclibLyrics {
+	synced_lyrics: Option<String>,
+	plain_lyrics: Option<String>,
+	#[serde(default)]
+	duration: Option<f64>,
+	#[serde(default)]
+	isrc: Option<String>,
+}
+
+/// Access token for the lyrics backend
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LyricsToken {
+	access_token: String,
+	access_token_expiration_timestamp_ms: u64,
+}
+
+impl LyricsToken {
+	/// Whether the token expired or is about to
+	fn is_expired(&self) -> bool {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_millis() as u64;
+		now + 30_000 >= self.access_token_expiration_timestamp_ms
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{Read, Write};
+	use std::net::TcpListener;
+
+	fn lrc(body: &str, enhanced_lrc: bool) -> Option<String> {
+		BeautifulLyrics::parse_lrc(body, enhanced_lrc)
+			.unwrap()
+			.map(|l| l.lrc)
+	}
+
+	#[test]
+	fn empty_body_has_no_lyrics() {
+		assert_eq!(lrc("", false), None);
+		assert_eq!(lrc(" \n\t", false), None);
+	}
+
+	#[test]
+	fn malformed_body_is_an_error() {
+		for body in [
+			"not json",
+			r#"{"Type": "Karaoke", "Content": []}"#,
+			r#"{"Type": "Line", "Content": [{"Text": "no time"}]}"#,
+			r#"{"Type": "Static"}"#,
+			r#"[]"#,
+		] {
+			assert!(BeautifulLyrics::parse_lrc(body, false).is_err(), "{}", body);
+		}
+	}
+
+	/// Answer a single request on a local port with the raw response, returning its URL
+	fn serve_once(response: String) -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		std::thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			// The whole request is read first, so closing the connection doesn't reset it
+			let mut request: Vec<u8> = vec![];
+			let mut buffer = [0; 1024];
+			while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+				match stream.read(&mut buffer).unwrap() {
+					0 => break,
+					n => request.extend(&buffer[..n]),
+				}
+			}
+			stream.write_all(response.as_bytes()).unwrap();
+		});
+		format!("http://{}", address)
+	}
+
+	/// Body split into HTTP chunks, without any Content-Length
+	fn chunked(body: &str) -> String {
+		let mut response = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_string();
+		let middle = body.len() / 2;
+		for chunk in [&body[..middle], &body[middle..]] {
+			if !chunk.is_empty() {
+				response.push_str(&format!("{:x}\r\n{}\r\n", chunk.len(), chunk));
+			}
+		}
+		response.push_str("0\r\n\r\n");
+		response
+	}
+
+	async fn fetch(response: String) -> Option<String> {
+		let api = serve_once(response);
+		BeautifulLyrics::fetch_lrc(&api, "id", "token", false)
+			.await
+			.unwrap()
+			.map(|l| l.lrc)
+	}
+
+	#[async_std::test]
+	async fn bodies_without_length_are_read() {
+		let body = r#"{"Type": "Static", "Lines": [{"Text": "One"}, {"Text": "Two"}]}"#;
+		assert_eq!(fetch(chunked(body)).await.as_deref(), Some("One\nTwo\n"));
+		// The body ends when the connection is closed
+		let response = format!("HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n{}", body);
+		assert_eq!(fetch(response).await.as_deref(), Some("One\nTwo\n"));
+		assert_eq!(fetch(chunked("")).await, None);
+	}
+
+	#[test]
+	fn line_and_static_lyrics() {
+		let body = r#"{"Type": "Line", "Content": [
+			{"StartTime": 1.5, "Text": "First"},
+			{"StartTime": 61.239, "Text": "Second"}
+		]}"#;
+		assert_eq!(
+			lrc(body, false).unwrap(),
+			"[00:01.50]First\n[01:01.23]Second\n"
+		);
+		let body = r#"{"Type": "Static", "Lines": [{"Text": "One"}, {"Text": "Two"}]}"#;
+		assert_eq!(lrc(body, false).unwrap(), "One\nTwo\n");
+	}
+
+	fn lrclib_result(isrc: Option<&str>, duration: f64, lyrics: &str) -> LrclibLyrics {
+		LrclibLyrics {
+			synced_lyrics: None,
+			plain_lyrics: Some(lyrics.to_string()),
+			duration: Some(duration),
+			isrc: isrc.map(|i| i.to_string()),
+		}
+	}
+
+	#[test]
+	fn lrclib_prefers_same_isrc() {
+		let mut query = LyricsQuery {
+			id: None,
+			isrc: Some("USABC1234567".to_string()),
+			artist: "A".to_string(),
+			title: "T".to_string(),
+			album: "Album".to_string(),
+			duration: 200,
+		};
+		let results = || {
+			vec![
+				lrclib_result(None, 300.0, "long"),
+				lrclib_result(None, 201.0, "close"),
+				lrclib_result(Some("usabc1234567"), 230.0, "same isrc"),
+			]
+		};
+		let plain =
+			|query: &LyricsQuery| Lrclib::best_match(results(), query).and_then(|l| l.plain_lyrics);
+		assert_eq!(plain(&query).as_deref(), Some("same isrc"));
+
+		// Without a matching ISRC the duration decides
+		query.isrc = Some("GBXYZ7654321".to_string());
+		assert_eq!(plain(&query).as_deref(), Some("close"));
+		query.isrc = None;
+		assert_eq!(plain(&query).as_deref(), Some("close"));
+		query.duration = 100;
+		assert_eq!(plain(&query), None);
+	}
+
+	#[test]
+	fn syllable_lyrics() {
+		let body = r#"{"Type": "Syllable", "Content": [{"Lead": {"StartTime": 2.0, "Syllables": [
+			{"StartTime": 2.0, "Text": "Hel", "IsPartOfWord": true},
+			{"StartTime": 2.456, "Text": "lo", "IsPartOfWord": false},
+			{"StartTime": 3.0, "Text": "world", "IsPartOfWord": false}
+		]}}]}"#;
+		assert_eq!(lrc(body, false).unwrap(), "[00:02.00]Hello world \n");
+		assert_eq!(
+			lrc(body, true).unwrap(),
+			"[00:02.00]<00:02.00>Hel<00:02.45>lo <00:03.00>world \n"
+		);
+	}
+}
@@ -1,20 +1,40 @@
 #[macro_use]
 extern crate log;
 
+mod cli;
 mod converter;
 mod downloader;
 mod error;
+mod history;
+mod html;
+mod list;
+mod lyrics;
+mod probe;
+mod progress;
+mod report;
 mod settings;
 mod spotify;
+mod sync;
 mod tag;
+mod tui;
+mod watch;
 
 use async_std::task;
+use clap::Parser;
+use cli::{Args, Cli, Command, ConfigArgs, QueueArgs, SearchArgs};
 use colored::Colorize;
-use downloader::{DownloadState, Downloader};
+use downloader::{search_tracks, Download, DownloadEvent, DownloadState, Downloader, SearchResult};
+use error::SpotifyError;
+use progress::{JsonEvent, JsonProgress};
+use report::{Report, ReportState};
+use rspotify::clients::OAuthClient;
 use settings::Settings;
 use spotify::Spotify;
-use std::env;
+use std::io::IsTerminal;
+use std::path::Path;
 use std::time::{Duration, Instant};
+use sync::SyncPlan;
+use tui::{Action, LogWriter, Overview};
 
 #[cfg(not(windows))]
 #[tokio::main]
@@ -33,16 +53,47 @@ async fn main() {
 }
 
 async fn start() {
-	env_logger::init();
+	let command = Cli::parse().into_command();
+	// RUST_LOG still takes precedence over -v
+	let mut logger = env_logger::Builder::from_env(
+		env_logger::Env::default().default_filter_or(command.log_level()),
+	);
+	// Logs are held back while the overview is shown, colors are kept as for stderr
+	logger.target(env_logger::Target::Pipe(Box::new(LogWriter)));
+	if std::env::var_os("RUST_LOG_STYLE").is_none() && std::io::stderr().is_terminal() {
+		logger.write_style(env_logger::WriteStyle::Always);
+	}
+	logger.init();
+
+	match command {
+		Command::Download(args) => download(*args).await,
+		Command::Search(args) => search(args).await,
+		Command::Queue(args) => queue(args).await,
+		Command::Config(args) => config(args).await,
+	}
+}
 
-	let settings = match Settings::load().await {
+/// Load the settings, writing default ones if there are none
+async fn load_settings(quiet: bool) -> Option<Settings> {
+	match Settings::load().await {
 		Ok(settings) => {
+			if !quiet {
+				println!(
+					"{} {}.",
+					"Settings successfully loaded.\nContinuing with spotify account:".green(),
+					settings.username
+				);
+			}
+			Some(settings)
+		}
+		// Keep invalid settings as they are, so the user can fix the reported key
+		Err(e @ SpotifyError::InvalidSettings(..)) => {
 			println!(
-				"{} {}.",
-				"Settings successfully loaded.\nContinuing with spotify account:".green(),
-				settings.username
+				"{} {}",
+				"Settings could not be loaded, because of the following error:".red(),
+				e
 			);
-			settings
+			None
 		}
 		Err(e) => {
 			println!(
@@ -66,30 +117,19 @@ async fn start() {
 					);
 				}
 			};
-			return;
+			None
 		}
-	};
-
-	let args: Vec<String> = env::args().collect();
-	if args.len() <= 1 {
-		println!(
-			"Usage:\n{} <search_term> | <track_url> | <album_url> | <playlist_url> | <artist_url>",
-			args[0]
-		);
-		return;
 	}
+}
 
-	let spotify = match Spotify::new(
-		&settings.username,
-		&settings.password,
-		&settings.client_id,
-		&settings.client_secret,
-	)
-	.await
-	{
+/// Log in with the settings
+async fn connect(settings: &Settings, quiet: bool) -> Option<Spotify> {
+	match Spotify::new(settings).await {
 		Ok(spotify) => {
-			println!("{}", "Login succeeded.".green());
-			spotify
+			if !quiet {
+				println!("{}", "Login succeeded.".green());
+			}
+			Some(spotify)
 		}
 		Err(e) => {
 			println!(
@@ -97,128 +137,586 @@ async fn start() {
 				"Login failed, possibly due to invalid credentials or settings:".red(),
 				e
 			);
+			None
+		}
+	}
+}
+
+/// Print search results, as tab separated rows or JSON
+async fn search(args: SearchArgs) {
+	let Some(settings) = load_settings(true).await else {
+		return;
+	};
+	let Some(spotify) = connect(&settings, true).await else {
+		return;
+	};
+	let query = args.query.join(" ");
+	let results = match search_tracks(&spotify, &query, &settings.downloader).await {
+		Ok(results) => results,
+		Err(e) => {
+			error!("{} {}", "Searching failed:".red(), e);
 			return;
 		}
 	};
+	if args.json {
+		match serde_json::to_string_pretty(&results) {
+			Ok(json) => println!("{}", json),
+			Err(e) => error!("{} {}", "Serializing search results failed:".red(), e),
+		}
+		return;
+	}
+	for (i, track) in results.iter().enumerate() {
+		println!(
+			"{}\t{}\t{}\tspotify:track:{}",
+			i + 1,
+			track.author,
+			track.title,
+			track.track_id
+		);
+	}
+}
 
-	let input = args[1..].join(" ");
-
-	let max_requests_per_min = 60.0;
-	let timeout:u64 = ((((1.0/(max_requests_per_min/60.0)) * 1000.0) * (1.0)) as f32) as u64;
-	println!("timeout set to: {:?}", timeout);
+/// Print the tracks of the queue journal or a report
+async fn queue(args: QueueArgs) {
+	let path = args.report.unwrap_or_else(Report::journal_path);
+	let mut report = match Report::load(&path).await {
+		Ok(report) => report,
+		Err(e) => {
+			error!("{} {}: {}", "Reading".red(), path.display(), e);
+			return;
+		}
+	};
+	if args.unfinished {
+		report
+			.entries
+			.retain(|e| matches!(e.state, ReportState::Failed | ReportState::Pending));
+	}
+	if args.json {
+		match serde_json::to_string_pretty(&report) {
+			Ok(json) => println!("{}", json),
+			Err(e) => error!("{} {}", "Serializing queue failed:".red(), e),
+		}
+		return;
+	}
+	for entry in &report.entries {
+		println!("{}", entry.row());
+	}
+}
 
-	let downloader = Downloader::new(settings.downloader, spotify);
-	match downloader.handle_input(&input).await {
-		Ok(search_results) => {
-			if let Some(search_results) = search_results {
-				print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
+/// Print the settings path, or the settings with secrets hidden
+async fn config(args: ConfigArgs) {
+	if !args.show {
+		println!("{}", Settings::path().display());
+		return;
+	}
+	let Some(settings) = load_settings(true).await else {
+		return;
+	};
+	match serde_json::to_string_pretty(&settings.redacted()) {
+		Ok(json) => println!("{}", json),
+		Err(e) => error!("{} {}", "Serializing settings failed:".red(), e),
+	}
+}
 
-				for (i, track) in search_results.iter().enumerate() {
-					println!("{}: {} - {}", i + 1, track.author, track.title);
+/// Download the input, or run one of the modes selected by the flags
+async fn download(args: Args) {
+	if args.clear_cache {
+		match Spotify::clear_cache().await {
+			Ok(removed) if removed.is_empty() => println!("{}", "Nothing to clear.".green()),
+			Ok(removed) => {
+				for path in removed {
+					println!("{} {}", "Removed".green(), path.display());
 				}
-				println!("{}", "Select the track (default: 1): ".green());
+			}
+			Err(e) => error!("{} {}", "Clearing cache failed:".red(), e),
+		}
+		return;
+	}
+	// Listings and JSON progress go to stdout for piping, so keep status messages out of it
+	let quiet = args.list.is_some() || args.probe.is_some() || args.json;
+
+	let Some(mut settings) = load_settings(quiet).await else {
+		return;
+	};
 
-				let mut selection;
-				loop {
-					let mut input = String::new();
-					std::io::stdin()
-						.read_line(&mut input)
-						.expect("Failed to read line");
+	settings.downloader.album_groups = args.album_groups(settings.downloader.album_groups);
+	if let Some(limit_total) = args.limit_total {
+		settings.downloader.limit_total = Some(limit_total);
+	}
+	settings.downloader.skip_unavailable |= args.skip_unavailable;
+	if let Some(path) = &args.download_archive {
+		settings.downloader.history = true;
+		settings.downloader.history_file = Some(path.clone());
+	}
+	settings.downloader.added_since = args.since;
+	// Nobody is there to answer in watch mode, and questions would break JSON output
+	settings.downloader.assume_yes = args.yes || args.watch.is_some() || args.json;
+
+	if args.login {
+		match login(&settings).await {
+			Ok(_) => println!("{}", "Logged in.".green()),
+			Err(e) => error!("{} {}", "Logging in failed:".red(), e),
+		}
+		return;
+	}
 
-					selection = input.trim().parse::<usize>().unwrap_or(1) - 1;
+	let Some(spotify) = connect(&settings, quiet).await else {
+		return;
+	};
 
-					if selection < search_results.len() {
-						break;
-					}
-					println!("{}", "Invalid selection. Try again or quit (CTRL+C):".red());
+	if let Some(uri) = &args.list {
+		match list::list_uri(&spotify, uri, &settings.downloader).await {
+			Ok(tracks) if args.json => match serde_json::to_string_pretty(&tracks) {
+				Ok(json) => println!("{}", json),
+				Err(e) => error!("{} {}", "Serializing track list failed:".red(), e),
+			},
+			Ok(tracks) => {
+				for track in &tracks {
+					println!("{}", track.row());
 				}
+			}
+			Err(e) => error!("{} {}", "Listing tracks failed:".red(), e),
+		}
+		return;
+	}
 
-				let track = &search_results[selection];
+	if let Some(uri) = &args.probe {
+		match probe::probe_uri(&spotify, uri, &settings.downloader).await {
+			Ok(tracks) if args.json => match serde_json::to_string_pretty(&tracks) {
+				Ok(json) => println!("{}", json),
+				Err(e) => error!("{} {}", "Serializing probe results failed:".red(), e),
+			},
+			Ok(tracks) => {
+				for track in &tracks {
+					println!("{}", track.row());
+				}
+			}
+			Err(e) => error!("{} {}", "Probing tracks failed:".red(), e),
+		}
+		return;
+	}
 
-				if let Err(e) = downloader
-					.add_uri(&format!("spotify:track:{}", track.track_id))
-					.await
-				{
-					error!(
-						"{}",
-						format!(
-							"{}: {}",
-							"Track could not be added to download queue.".red(),
-							e
-						)
-					);
+	if let Err(e) = settings.downloader.check_free_space() {
+		println!("{} {}", "Not enough free space:".red(), e);
+		return;
+	}
+
+	if let Some(path) = &args.watch {
+		let downloader = Downloader::new(settings.downloader, spotify);
+		println!("{} {}", "Watching".green(), path.display());
+		if let Err(e) = watch::watch(&downloader, path).await {
+			error!("{} {}", "Watching failed:".red(), e);
+		}
+		return;
+	}
+
+	let input = args.input.join(" ");
+
+	// The folder is compared before the session is handed to the downloader
+	let sync_plan = match &args.sync {
+		Some(folder) => match sync::plan(&spotify, &input, folder, &settings.downloader).await {
+			Ok(plan) => Some(plan),
+			Err(e) => {
+				error!("{} {}", "Syncing failed:".red(), e);
+				return;
+			}
+		},
+		None => None,
+	};
+
+	let downloader = Downloader::new(settings.downloader, spotify);
+	let search_results = if let Some(plan) = sync_plan {
+		if !sync(&downloader, plan, quiet).await {
+			return;
+		}
+		None
+	} else if let Some(path) = &args.resume {
+		let path = path.clone().unwrap_or_else(Report::journal_path);
+		if !resume(&downloader, &path, quiet).await {
+			return;
+		}
+		None
+	} else if let Some(path) = &args.from_file {
+		if !queue_file(&downloader, path, quiet).await {
+			return;
+		}
+		None
+	} else {
+		match downloader.handle_inputs(&args.input).await {
+			Ok(search_results) => search_results,
+			Err(SpotifyError::Cancelled) => {
+				println!("{}", "Cancelled.".yellow());
+				return;
+			}
+			Err(e) => {
+				error!("{} {}", "Handling input failed:".red(), e);
+				// Tracks queued before the failure can still be downloaded
+				if downloader.get_downloads().await.is_empty() {
 					return;
 				}
+				None
 			}
+		}
+	};
 
-			let refresh = Duration::from_secs(settings.refresh_ui_seconds);
-			let now = Instant::now();
-			let mut time_elapsed: u64;
-
-			// Number of songs to download
-			let total_down = downloader.get_downloads().await.len();
-			// Number of songs downloaded
-			let mut num_down = 0;
-
-			'outer: loop {
-				print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
-				let mut exit_flag: i8 = 1;
-
-				for download in downloader.get_downloads().await {
-					let state = download.state;
-					
-					// Update the number of downloaded songs
-					num_down = total_down - downloader.get_downloads().await.len();
-					
-					let progress = if state != DownloadState::Done {
-						match state {
-							DownloadState::Downloading(r, t) => {
-								exit_flag &= 0;
-								let p = r as f32 / t as f32 * 100.0;
-								if p > 100.0 {
-									"100%".to_string()
-								} else {
-									format!("{}%", p as i8)
-								}
-							}
-							DownloadState::Post => {
-								exit_flag &= 0;
-								"Postprocessing... ".to_string()
-							}
-							DownloadState::None | DownloadState::Lock => {
-								exit_flag &= 0;
-								"Preparing... ".to_string()
-							}
-							DownloadState::Error(e) => {
-								format!("{} ", e)
-							}
-							DownloadState::Done => {
-								"Impossible state".to_string()
-							},
-						}
-					} else {
-						// num_down = num_down + 1;
-						"Done.".to_string()
-					};
-
-					println!("{:<19}| {}", progress, download.title);
+	if let Some(search_results) = search_results.as_ref().filter(|_| args.exact) {
+		// The same track is often listed once per release, those aren't ambiguous
+		let mut matches = search_results
+			.iter()
+			.filter(|t| t.matches_exactly(&input))
+			.collect::<Vec<_>>();
+		matches.dedup_by_key(|t| (t.author.to_lowercase(), t.title.to_lowercase()));
+		let track = match matches[..] {
+			[track] => track,
+			[] => {
+				println!("{} {}", "No exact match for".red(), input);
+				return;
+			}
+			_ => {
+				println!("{} {}:", "Several exact matches for".red(), input);
+				for track in matches {
+					println!("{} - {}", track.author, track.title);
 				}
-				time_elapsed = now.elapsed().as_secs();
-				if exit_flag == 1 {
-					break 'outer;
+				return;
+			}
+		};
+		if let Err(e) = downloader
+			.add_uri(&format!("spotify:track:{}", track.track_id))
+			.await
+		{
+			error!(
+				"{} {}",
+				"Track could not be added to download queue.".red(),
+				e
+			);
+			return;
+		}
+	} else if let Some(search_results) = search_results {
+		let selection = match args.selection() {
+			// Scripts can't answer, so an out of range selection fails instead of asking
+			Some(n) if n > search_results.len() => {
+				println!(
+					"{} {} result(s) for {}",
+					"Selection out of range, only".red(),
+					search_results.len(),
+					input
+				);
+				return;
+			}
+			Some(n) => n - 1,
+			None if args.json => {
+				error!("Pass --first or --select N to pick a search result with --json");
+				return;
+			}
+			None => select(&search_results),
+		};
+
+		let track = &search_results[selection];
+
+		if let Err(e) = downloader
+			.add_uri(&format!("spotify:track:{}", track.track_id))
+			.await
+		{
+			error!(
+				"{}",
+				format!(
+					"{}: {}",
+					"Track could not be added to download queue.".red(),
+					e
+				)
+			);
+			return;
+		}
+	}
+
+	let refresh = Duration::from_secs(settings.refresh_ui_seconds);
+	let now = Instant::now();
+	let mut time_elapsed: u64;
+	let events = downloader.events();
+	let mut json_progress = JsonProgress::default();
+	// Nobody would look at the overview without a terminal, only the summary is printed then
+	let mut overview = match args.json || !std::io::stdout().is_terminal() {
+		true => None,
+		false => Overview::open(),
+	};
+
+	'outer: loop {
+		while let Ok(event) = events.try_recv() {
+			if args.json {
+				JsonEvent::album_complete(&event).emit();
+				continue;
+			}
+			match event {
+				DownloadEvent::AlbumComplete { album_id, tracks } => {
+					info!("Album {} complete, {} track(s)", album_id, tracks.len())
 				}
+			}
+		}
+		// Totals are taken from each snapshot, so they grow as collections are expanded
+		let mut downloads = downloader.get_downloads().await;
+		downloads.sort_by_key(|d| d.order);
+		if args.json {
+			json_progress.update(&downloads);
+			time_elapsed = now.elapsed().as_secs();
+			if downloads.iter().all(|d| d.state.is_finished()) {
+				break 'outer;
+			}
+			task::sleep(refresh).await;
+			continue;
+		}
+
+		time_elapsed = now.elapsed().as_secs();
+		let finished = downloads.iter().all(|d| d.state.is_finished());
+		let Some(view) = overview.as_mut() else {
+			if finished {
+				break 'outer;
+			}
+			task::sleep(refresh).await;
+			continue;
+		};
+		// Failed downloads keep the overview open, so they can still be retried
+		if finished && !downloads.iter().any(|d| d.state.can_retry()) {
+			break 'outer;
+		}
 
-				println!("\nElapsed second(s): {}", time_elapsed);
-				println!("Downloaded {} out of {}", num_down, total_down);
-				task::sleep(refresh).await
+		let mut status = format!(
+			"Elapsed second(s): {}   {} of {}",
+			time_elapsed,
+			summary(&downloads),
+			downloads.len()
+		);
+		let present = downloads
+			.iter()
+			.filter(|d| {
+				d.state == DownloadState::Skipped(SpotifyError::AlreadyDownloaded.to_string())
+			})
+			.count();
+		if present > 0 {
+			status.push_str(&format!(", {} already present", present));
+		}
+		if finished {
+			status.push_str("   All finished, retry or quit");
+		}
+		// Waiting for a key takes the place of sleeping until the next refresh
+		let action = match view.draw(&downloads, &status) {
+			Ok(_) => view.next_action(&downloads, refresh),
+			Err(e) => Err(e),
+		};
+		match action {
+			Ok(Some(Action::Cancel(id))) => downloader.cancel(id).await,
+			Ok(Some(Action::Retry(id))) => downloader.retry(id).await,
+			Ok(Some(Action::Quit)) => break 'outer,
+			Ok(None) => {}
+			Err(e) => {
+				overview = None;
+				error!("{} {}", "Showing the overview failed:".red(), e);
 			}
-			// Update the number of downloaded songs one last time
-			num_down = total_down - downloader.get_downloads().await.len();
-			println!("Finished download(s) in {} second(s).", time_elapsed);
-			println!("Downloaded {} out of {}", num_down, total_down);
 		}
+	}
+	// Leave the overview before the summary is printed
+	drop(overview);
+	let mut downloads = downloader.get_downloads().await;
+	downloads.sort_by_key(|d| d.order);
+	if let Some(path) = &args.report {
+		match Report::from(downloads.as_slice()).save(path).await {
+			Ok(_) if quiet => {}
+			Ok(_) => println!("{} {}", "Report written to".green(), path.display()),
+			Err(e) => error!("{} {}", "Writing report failed:".red(), e),
+		}
+	}
+	if let Some(path) = &args.html_index {
+		match html::write_index(path, &downloads).await {
+			Ok(_) if quiet => {}
+			Ok(_) => println!("{} {}", "Index written to".green(), path.display()),
+			Err(e) => error!("{} {}", "Writing index failed:".red(), e),
+		}
+	}
+	let stats = downloader.stats().await;
+	if args.json {
+		let (downloaded, skipped, failed) = counts(&downloads);
+		JsonEvent::Summary {
+			downloaded,
+			skipped,
+			failed,
+			elapsed_secs: time_elapsed,
+			bytes: stats.bytes,
+			requests: stats.requests.total(),
+		}
+		.emit();
+		return;
+	}
+
+	for download in downloads.iter().filter(|d| !d.warnings.is_empty()) {
+		for warning in &download.warnings {
+			println!("{} {}: {}", "Warning:".yellow(), download.title, warning);
+		}
+	}
+	for download in &downloads {
+		if let DownloadState::Error(e) = &download.state {
+			println!("{} {}: {}", "Failed:".red(), download.title, e);
+		}
+		if let DownloadState::Untagged(e) = &download.state {
+			println!("{} {}: {}", "Not tagged:".yellow(), download.title, e);
+		}
+		if let Some(fallback) = &download.quality_fallback {
+			println!(
+				"{} {}: {}",
+				"Lower quality:".yellow(),
+				download.title,
+				fallback
+			);
+		}
+	}
+	println!("Finished download(s) in {} second(s).", time_elapsed);
+	println!("{}", summary(&downloads));
+	if let Some(throughput) = stats.throughput() {
+		println!(
+			"Transferred {} at {}/s on average",
+			format_mb(stats.bytes as f64),
+			format_mb(throughput)
+		);
+	}
+	let requests = stats.requests;
+	println!(
+		"Made {} request(s): {} metadata, {} cover, {} lyrics, {} token",
+		requests.total(),
+		requests.metadata,
+		requests.cover,
+		requests.lyrics,
+		requests.token
+	);
+}
+
+/// Authorize access to the user's library in the browser, the token is cached for later runs
+async fn login(settings: &Settings) -> Result<(), SpotifyError> {
+	let mut client = Spotify::user_client(settings);
+	let url = client.get_authorize_url(None)?;
+	println!(
+		"Open this URL, allow access and paste the URL you are redirected to, even if the page fails to load:\n{}",
+		url
+	);
+	let mut redirected = String::new();
+	std::io::stdin().read_line(&mut redirected)?;
+	let code = client
+		.parse_response_code(redirected.trim())
+		.ok_or_else(|| SpotifyError::Error("No authorization code in the URL".into()))?;
+	client.request_token(&code).await?;
+	Ok(())
+}
+
+/// Queue the unfinished tracks of a report, returns whether there is anything to download
+async fn resume(downloader: &Downloader, path: &Path, quiet: bool) -> bool {
+	match Report::load(path).await {
+		Ok(report) => {
+			let unfinished = report.unfinished();
+			if unfinished.is_empty() {
+				if !quiet {
+					println!("{}", "Nothing left to download.".green());
+				}
+				return false;
+			}
+			if !quiet {
+				println!("{} {} track(s)", "Resuming".green(), unfinished.len());
+			}
+			downloader.add_to_queue_multiple(unfinished).await;
+			true
+		}
+		Err(e) => {
+			error!("{} {}", "Resuming failed:".red(), e);
+			false
+		}
+	}
+}
+
+/// Ask which search result to download
+fn select(search_results: &[SearchResult]) -> usize {
+	print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
+
+	for (i, track) in search_results.iter().enumerate() {
+		println!("{}: {} - {}", i + 1, track.author, track.title);
+	}
+	println!("{}", "Select the track (default: 1): ".green());
+
+	let mut selection;
+	loop {
+		let mut input = String::new();
+		std::io::stdin()
+			.read_line(&mut input)
+			.expect("Failed to read line");
+
+		selection = input.trim().parse::<usize>().unwrap_or(1) - 1;
+
+		if selection < search_results.len() {
+			break;
+		}
+		println!("{}", "Invalid selection. Try again or quit (CTRL+C):".red());
+	}
+	selection
+}
+
+/// Queue the URLs of a file, returns whether anything was queued
+async fn queue_file(downloader: &Downloader, path: &Path, quiet: bool) -> bool {
+	let data = match tokio::fs::read_to_string(path).await {
+		Ok(data) => data,
 		Err(e) => {
-			error!("{} {}", "Handling input failed:".red(), e)
+			error!("{} {}: {}", "Reading".red(), path.display(), e);
+			return false;
+		}
+	};
+	let urls = data
+		.lines()
+		.map(str::trim)
+		.filter(|l| !l.is_empty() && !l.starts_with('#'));
+	for url in urls {
+		match downloader.add_uri(url).await {
+			Ok(_) if quiet => {}
+			Ok(_) => println!("{} {}", "Queued".green(), url),
+			Err(e) => error!("{} {}: {}", "Queueing failed".red(), url, e),
 		}
 	}
+	!downloader.get_downloads().await.is_empty()
+}
+
+/// Queue the tracks missing from the synced folder, returns whether there is anything to download
+async fn sync(downloader: &Downloader, plan: SyncPlan, quiet: bool) -> bool {
+	if !quiet {
+		println!(
+			"{} {} new track(s), {} no longer in the source",
+			"Sync:".green(),
+			plan.missing.len(),
+			plan.removed
+		);
+	}
+	if plan.missing.is_empty() {
+		if !quiet {
+			println!("{}", "Already in sync.".green());
+		}
+		return false;
+	}
+	downloader.add_to_queue_multiple(plan.missing).await;
+	true
+}
+
+/// Format bytes as megabytes
+fn format_mb(bytes: f64) -> String {
+	format!("{:.2} MB", bytes / 1_000_000.0)
+}
+
+/// Counts of finished downloads, as in "12 downloaded, 3 skipped, 1 failed"
+fn summary(downloads: &[Download]) -> String {
+	let (downloaded, skipped, failed) = counts(downloads);
+	format!(
+		"{} downloaded, {} skipped, {} failed",
+		downloaded, skipped, failed
+	)
+}
+
+/// Number of downloaded, skipped and failed downloads
+fn counts(downloads: &[Download]) -> (usize, usize, usize) {
+	let count = |f: fn(&DownloadState) -> bool| downloads.iter().filter(|d| f(&d.state)).count();
+	(
+		count(DownloadState::is_success),
+		count(|s| matches!(s, DownloadState::Skipped(_))),
+		count(|s| matches!(s, DownloadState::Error(_))),
+	)
 }
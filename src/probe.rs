@@ -0,0 +1,87 @@
+use librespot::core::session::Session;
+use librespot::core::spotify_id::SpotifyId;
+use librespot::metadata::{Metadata, Track};
+use serde::Serialize;
+
+use crate::downloader::{AudioFormat, DownloaderConfig, DownloaderInternal, Quality};
+use crate::error::SpotifyError;
+use crate::list::list_uri;
+use crate::spotify::Spotify;
+
+/// Best downloadable file of a track, found without downloading it
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbedTrack {
+	pub index: usize,
+	pub id: Option<String>,
+	pub artist: String,
+	pub title: String,
+	/// `None` if the track can't be downloaded at all
+	pub quality: Option<Quality>,
+	pub format: Option<AudioFormat>,
+}
+
+impl ProbedTrack {
+	/// Tab separated row, so output can be piped into other tools
+	pub fn row(&self) -> String {
+		let available = match (&self.quality, &self.format) {
+			(Some(quality), Some(format)) => format!("{}\t{:?}", quality.to_string(), format),
+			_ => "unavailable".to_string(),
+		};
+		format!(
+			"{}\t{}\t{}\t{}",
+			self.index, self.artist, self.title, available
+		)
+	}
+}
+
+/// Resolve URL or URI and look up the quality each of its tracks would be downloaded in
+pub async fn probe_uri(
+	spotify: &Spotify,
+	uri: &str,
+	config: &DownloaderConfig,
+) -> Result<Vec<ProbedTrack>, SpotifyError> {
+	let listed = list_uri(spotify, uri, config).await?;
+	let mut probed = Vec::with_capacity(listed.len());
+	for track in listed {
+		let file = match &track.id {
+			Some(id) => match probe_track(&spotify.session, id, config).await {
+				Ok(file) => file,
+				Err(e) => {
+					warn!("Probing {} failed: {}", id, e);
+					None
+				}
+			},
+			// Local files
+			None => None,
+		};
+		probed.push(ProbedTrack {
+			index: track.index,
+			id: track.id,
+			artist: track.artist,
+			title: track.title,
+			quality: file.as_ref().map(|(quality, _)| *quality),
+			format: file.map(|(_, format)| format),
+		});
+	}
+	Ok(probed)
+}
+
+/// Run the same file selection as a download, stopping before the audio is fetched
+async fn probe_track(
+	session: &Session,
+	id: &str,
+	config: &DownloaderConfig,
+) -> Result<Option<(Quality, AudioFormat)>, SpotifyError> {
+	let mut track = Track::get(session, SpotifyId::from_base62(id)?).await?;
+	if !track.available {
+		track = match DownloaderInternal::find_alternative(session, track).await {
+			Ok(track) => track,
+			Err(SpotifyError::Unavailable) => return Ok(None),
+			Err(e) => return Err(e),
+		};
+	}
+	Ok(
+		DownloaderInternal::select_file(track.id, &track.files, config)
+			.map(|(_, format, quality)| (quality, format.into())),
+	)
+}
@@ -0,0 +1,106 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::downloader::{Download, DownloadEvent, DownloadState};
+
+/// Progress written by `--json`, one object per line
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JsonEvent<'a> {
+	Queued {
+		id: i64,
+		track_id: &'a str,
+		title: &'a str,
+		artist: &'a str,
+	},
+	State {
+		id: i64,
+		state: &'static str,
+		/// Why the track was skipped, failed or not tagged
+		#[serde(skip_serializing_if = "Option::is_none")]
+		reason: Option<&'a str>,
+		/// Bytes downloaded and total while downloading
+		#[serde(skip_serializing_if = "Option::is_none")]
+		progress: Option<(usize, usize)>,
+		#[serde(skip_serializing_if = "<[_]>::is_empty")]
+		warnings: &'a [String],
+	},
+	AlbumComplete {
+		album_id: &'a str,
+		/// Ids of the tracks of the album
+		tracks: Vec<&'a str>,
+	},
+	Summary {
+		downloaded: usize,
+		skipped: usize,
+		failed: usize,
+		elapsed_secs: u64,
+		bytes: usize,
+		requests: usize,
+	},
+}
+
+impl JsonEvent<'_> {
+	/// Print as a single line
+	pub fn emit(&self) {
+		match serde_json::to_string(self) {
+			Ok(json) => println!("{}", json),
+			Err(e) => error!("Serializing progress failed: {}", e),
+		}
+	}
+
+	/// Event of the download queue, as sent by [`crate::downloader::Downloader::events`]
+	pub fn album_complete(event: &DownloadEvent) -> JsonEvent<'_> {
+		match event {
+			DownloadEvent::AlbumComplete { album_id, tracks } => JsonEvent::AlbumComplete {
+				album_id,
+				tracks: tracks.iter().map(|t| t.track_id.as_str()).collect(),
+			},
+		}
+	}
+}
+
+/// Last state seen of each download, to only print what changed between snapshots
+#[derive(Debug, Default)]
+pub struct JsonProgress {
+	states: HashMap<i64, DownloadState>,
+}
+
+impl JsonProgress {
+	/// Print downloads queued and states changed since the last snapshot
+	pub fn update(&mut self, downloads: &[Download]) {
+		for download in downloads {
+			let previous = self.states.get(&download.id);
+			if previous == Some(&download.state) {
+				continue;
+			}
+			if previous.is_none() {
+				JsonEvent::Queued {
+					id: download.id,
+					track_id: &download.track_id,
+					title: &download.title,
+					artist: &download.subtitle,
+				}
+				.emit();
+			}
+			let (state, reason, progress) = match &download.state {
+				DownloadState::None | DownloadState::Lock => ("preparing", None, None),
+				DownloadState::Downloading(r, t) => ("downloading", None, Some((*r, *t))),
+				DownloadState::Post => ("postprocessing", None, None),
+				DownloadState::Done => ("done", None, None),
+				DownloadState::Untagged(e) => ("untagged", Some(e.as_str()), None),
+				DownloadState::Skipped(e) => ("skipped", Some(e.as_str()), None),
+				DownloadState::Error(e) => ("failed", Some(e.as_str()), None),
+			};
+			JsonEvent::State {
+				id: download.id,
+				state,
+				reason,
+				progress,
+				warnings: &download.warnings,
+			}
+			.emit();
+			self.states.insert(download.id, download.state.clone());
+		}
+	}
+}
@@ -0,0 +1,147 @@
+use librespot::core::spotify_id::SpotifyId;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::downloader::{Download, DownloadKind, DownloadState, DownloadTiming};
+use crate::error::SpotifyError;
+use crate::settings::get_config_folder_path;
+
+/// Outcome of every download of a run, written with `--report` and read back by `--resume`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Report {
+	pub entries: Vec<ReportEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReportEntry {
+	pub track_id: String,
+	pub title: String,
+	pub artist: String,
+	pub album_id: Option<String>,
+	pub state: ReportState,
+	/// Why the track was skipped or failed
+	pub reason: Option<String>,
+	/// Missing in reports written before episodes could be downloaded
+	#[serde(default)]
+	pub kind: DownloadKind,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportState {
+	Done,
+	Skipped,
+	Failed,
+	/// Still queued or downloading when the run ended
+	Pending,
+}
+
+impl ReportEntry {
+	/// Tab separated row, so output can be piped into other tools
+	pub fn row(&self) -> String {
+		let state = match self.state {
+			ReportState::Done => "done",
+			ReportState::Skipped => "skipped",
+			ReportState::Failed => "failed",
+			ReportState::Pending => "pending",
+		};
+		format!(
+			"{}\t{}\t{}\t{}\t{}",
+			state,
+			self.artist,
+			self.title,
+			self.track_id,
+			self.reason.as_deref().unwrap_or_default()
+		)
+	}
+}
+
+impl Report {
+	pub async fn load(path: impl AsRef<Path>) -> Result<Report, SpotifyError> {
+		let path = path.as_ref();
+		let invalid = |e: String| SpotifyError::InvalidReport(path.display().to_string(), e);
+		let data = fs::read_to_string(path).await?;
+		let report: Report = serde_json::from_str(&data).map_err(|e| invalid(e.to_string()))?;
+		if let Some(entry) = report
+			.entries
+			.iter()
+			.find(|e| e.state != ReportState::Done && SpotifyId::from_base62(&e.track_id).is_err())
+		{
+			return Err(invalid(format!(
+				"{} has an invalid track id: {}",
+				entry.title, entry.track_id
+			)));
+		}
+		Ok(report)
+	}
+
+	/// Written through a temporary file, so an interrupted write keeps the previous report
+	pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), SpotifyError> {
+		let path = path.as_ref();
+		let data = serde_json::to_string_pretty(self)?;
+		let temp = path.with_extension("tmp");
+		fs::write(&temp, data).await?;
+		fs::rename(&temp, path).await?;
+		Ok(())
+	}
+
+	/// Report of the queue kept while downloading, see `queue_journal`
+	pub fn journal_path() -> PathBuf {
+		get_config_folder_path().join("queue.json")
+	}
+
+	/// Downloads of the tracks that neither finished nor were skipped
+	pub fn unfinished(self) -> Vec<Download> {
+		self.entries
+			.into_iter()
+			.filter(|e| matches!(e.state, ReportState::Failed | ReportState::Pending))
+			.map(|e| Download {
+				id: 0,
+				order: 0,
+				track_id: e.track_id,
+				title: e.title,
+				subtitle: e.artist,
+				state: DownloadState::None,
+				warnings: vec![],
+				timing: DownloadTiming::default(),
+				album_id: e.album_id,
+				quality: None,
+				quality_fallback: None,
+				file: None,
+				kind: e.kind,
+			})
+			.collect()
+	}
+}
+
+impl From<&[Download]> for Report {
+	fn from(downloads: &[Download]) -> Self {
+		let entries = downloads
+			.iter()
+			// Local tracks have no id to download them by
+			.filter(|d| SpotifyId::from_base62(&d.track_id).is_ok())
+			.map(|d| {
+				let (state, reason) = match &d.state {
+					DownloadState::Done => (ReportState::Done, None),
+					DownloadState::Untagged(e) => (ReportState::Done, Some(e.clone())),
+					DownloadState::Skipped(e) => (ReportState::Skipped, Some(e.clone())),
+					DownloadState::Error(e) => (ReportState::Failed, Some(e.clone())),
+					_ => (ReportState::Pending, None),
+				};
+				ReportEntry {
+					track_id: d.track_id.clone(),
+					title: d.title.clone(),
+					artist: d.subtitle.clone(),
+					album_id: d.album_id.clone(),
+					state,
+					reason,
+					kind: d.kind,
+				}
+			})
+			.collect();
+		Report { entries }
+	}
+}
@@ -12,21 +12,48 @@ use std::{
 	path::{Path, PathBuf},
 };
 
-// Structure for holding all the settings
+// Structure for holding all the settings, unknown keys are rejected so typos don't go unnoticed
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {
 	pub username: String,
 	pub password: String,
 	pub client_id: String,
 	pub client_secret: String,
+	#[serde(default = "default_api_base_url")]
+	pub api_base_url: String,
+	#[serde(default = "default_auth_base_url")]
+	pub auth_base_url: String,
+	/// Clear the credentials cache and log in again when logging in with it fails
+	#[serde(default = "default_clear_stale_credentials")]
+	pub clear_stale_credentials: bool,
+	/// Redirect URI of the app, used by `--login`
+	#[serde(default = "default_redirect_uri")]
+	pub redirect_uri: String,
 	pub refresh_ui_seconds: u64,
 	pub downloader: DownloaderConfig,
 }
 
+fn default_api_base_url() -> String {
+	rspotify::DEFAULT_API_BASE_URL.to_string()
+}
+
+fn default_auth_base_url() -> String {
+	rspotify::DEFAULT_AUTH_BASE_URL.to_string()
+}
+
+fn default_clear_stale_credentials() -> bool {
+	true
+}
+
+fn default_redirect_uri() -> String {
+	"http://127.0.0.1:8888/callback".to_string()
+}
+
 // On UNIX systems (eg. Linux, *BSD, even macOS), follow the
 // XDG Base Directory Specification for storing config files
 #[cfg(target_family = "unix")]
-fn get_config_folder_path() -> PathBuf {
+pub fn get_config_folder_path() -> PathBuf {
 	match env::var("XDG_CONFIG_HOME") {
 		Ok(v) => Path::new(&v).join("down_on_spot").to_path_buf(),
 		Err(_) => Path::new(&env::var("HOME").unwrap()).join(".config/down_on_spot"),
@@ -35,7 +62,7 @@ fn get_config_folder_path() -> PathBuf {
 
 // On Windows, follow whatever windows does for AppData
 #[cfg(target_family = "windows")]
-fn get_config_folder_path() -> PathBuf {
+pub fn get_config_folder_path() -> PathBuf {
 	Path::new(&env::var("APPDATA").unwrap()).join("down_on_spot")
 }
 
@@ -47,17 +74,38 @@ impl Settings {
 			password: password.to_string(),
 			client_id: client_id.to_string(),
 			client_secret: client_secret.to_string(),
+			api_base_url: default_api_base_url(),
+			auth_base_url: default_auth_base_url(),
+			clear_stale_credentials: default_clear_stale_credentials(),
+			redirect_uri: default_redirect_uri(),
 			refresh_ui_seconds: 1,
 			downloader: DownloaderConfig::new(),
 		}
 	}
 
+	// Path of the settings file
+	pub fn path() -> PathBuf {
+		get_config_folder_path().join("settings.json")
+	}
+
+	// Copy with the secrets hidden, for printing
+	pub fn redacted(&self) -> Settings {
+		let hidden = "<hidden>".to_string();
+		let mut settings = self.clone();
+		settings.password = hidden.clone();
+		settings.client_secret = hidden.clone();
+		if settings.downloader.sp_dc().is_some() {
+			settings.downloader.sp_dc = hidden;
+		}
+		settings
+	}
+
 	// Save config
 	pub async fn save(&self) -> Result<(), SpotifyError> {
 		// Get and create config folder path, generate config file path
 		let config_folder_path = get_config_folder_path();
 		create_dir_all(&config_folder_path).await?;
-		let config_file_path = config_folder_path.join("settings.json");
+		let config_file_path = Settings::path();
 
 		// Check if config file already exists and create a back up
 		if config_file_path.exists() {
@@ -73,14 +121,17 @@ impl Settings {
 
 	// Load config
 	pub async fn load() -> Result<Settings, SpotifyError> {
-		// Get config folder path, generate config file path
-		let config_folder_path = get_config_folder_path();
-		let config_file_path = config_folder_path.join("settings.json");
+		let config_file_path = Settings::path();
 
 		// Deserialize the settings from a json file
-		let mut file = File::open(config_file_path).await?;
+		let mut file = File::open(&config_file_path).await?;
 		let mut buf = String::new();
 		file.read_to_string(&mut buf).await?;
-		Ok(serde_json::from_str(&buf)?)
+		// serde_json names the offending key and where it is
+		let invalid =
+			|e: String| SpotifyError::InvalidSettings(config_file_path.display().to_string(), e);
+		let settings: Settings = serde_json::from_str(&buf).map_err(|e| invalid(e.to_string()))?;
+		settings.downloader.validate().map_err(invalid)?;
+		Ok(settings)
 	}
 }
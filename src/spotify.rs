@@ -1,78 +1,612 @@
-use futures::{pin_mut, TryStreamExt};
+use async_std::sync::Mutex;
+use async_stream::try_stream;
+use chrono::NaiveDate;
+use futures::future::try_join_all;
+use futures::{pin_mut, Stream, TryStreamExt};
 use librespot::core::authentication::Credentials;
 use librespot::core::cache::Cache;
 use librespot::core::config::SessionConfig;
-use librespot::core::session::Session;
-use rspotify::clients::BaseClient;
+use librespot::core::session::{Session, SessionError};
+use rspotify::clients::{BaseClient, OAuthClient};
 use rspotify::model::{
-	AlbumId, ArtistId, FullAlbum, FullArtist, FullPlaylist, FullTrack, PlayableItem, PlaylistId,
-	SearchResult, SearchType, SimplifiedAlbum, SimplifiedTrack, TrackId,
+	AlbumId, AlbumType, ArtistId, Country, EpisodeId, FullAlbum, FullArtist, FullEpisode,
+	FullPlaylist, FullShow, FullTrack, Id, Market, Page, PlayableItem, PlaylistId, PlaylistItem,
+	RecommendationsAttribute, SavedAlbum, SavedTrack, SearchResult, SearchType, ShowId,
+	SimplifiedAlbum, SimplifiedEpisode, SimplifiedTrack, TrackId,
 };
-use rspotify::ClientCredsSpotify;
+use rspotify::Config as ClientConfig;
 use rspotify::Credentials as ClientCredentials;
+use rspotify::{scopes, AuthCodePkceSpotify, ClientCredsSpotify, OAuth};
+use std::collections::VecDeque;
 use std::fmt;
-use std::path::Path;
+use std::future::Future;
+use std::io::{stderr, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
 use url::Url;
 
 use crate::error::SpotifyError;
+use crate::settings::{get_config_folder_path, Settings};
 
-use rspotify::ClientResult;
-use std::collections::HashMap;
-use rspotify::http::{BaseHttpClient, Query};
-use rspotify::model::Market;
-use serde::Deserialize;
-
-pub fn build_map_cpy<'key, 'value, const N: usize>(
-    array: [(&'key str, Option<&'value str>); N],
-) -> HashMap<&'key str, &'value str> {
-    // Use a manual for loop instead of iterators so we can call `with_capacity`
-    // and avoid reallocating.
-    let mut map = HashMap::with_capacity(N);
-    for (key, value) in array {
-        if let Some(value) = value {
-            map.insert(key, value);
-        }
-    }
-    map
+/// Spotify Web API calls the downloader depends on.
+///
+/// Implemented by [`Spotify`] on top of rspotify; tests can implement it with canned
+/// fixtures to exercise pagination and tagging without a live session.
+pub trait MetadataClient {
+	/// Get track by id
+	async fn track(&self, id: &str) -> Result<FullTrack, SpotifyError>;
+	/// Get album by id
+	async fn album(&self, id: &str) -> Result<FullAlbum, SpotifyError>;
+	/// Get playlist by id
+	async fn playlist(&self, id: &str) -> Result<FullPlaylist, SpotifyError>;
+	/// Get artist by id
+	async fn artist(&self, id: &str) -> Result<FullArtist, SpotifyError>;
+	/// Get podcast episode by id, shows and episodes are only found in a market
+	async fn episode(&self, id: &str, market: Option<Country>)
+		-> Result<FullEpisode, SpotifyError>;
+	/// Get podcast show by id
+	async fn show(&self, id: &str, market: Option<Country>) -> Result<FullShow, SpotifyError>;
+	/// Get single page of show episodes, newest first
+	async fn show_episodes(
+		&self,
+		id: &str,
+		market: Option<Country>,
+		limit: u32,
+		offset: u32,
+	) -> Result<Page<SimplifiedEpisode>, SpotifyError>;
+	/// Get single page of playlist items
+	async fn playlist_items(
+		&self,
+		id: &str,
+		limit: u32,
+		offset: u32,
+	) -> Result<Page<PlaylistItem>, SpotifyError>;
+	/// Get single page of album tracks
+	async fn album_tracks(
+		&self,
+		id: &str,
+		limit: u32,
+		offset: u32,
+	) -> Result<Page<SimplifiedTrack>, SpotifyError>;
+	/// Get single page of artist albums in the given groups
+	async fn artist_albums(
+		&self,
+		id: &str,
+		groups: &[AlbumType],
+		limit: u32,
+		offset: u32,
+	) -> Result<Page<SimplifiedAlbum>, SpotifyError>;
+	/// Get single page of the tracks saved by the logged in user
+	async fn saved_tracks(&self, limit: u32, offset: u32)
+		-> Result<Page<SavedTrack>, SpotifyError>;
+	/// Get single page of the albums saved by the logged in user
+	async fn saved_albums(&self, limit: u32, offset: u32)
+		-> Result<Page<SavedAlbum>, SpotifyError>;
+	/// Get several albums in one request, at most [`ALBUMS_BATCH_SIZE`] ids
+	async fn several_albums(&self, ids: &[&str]) -> Result<Vec<FullAlbum>, SpotifyError>;
+	/// Get search results for query, with playability in market if given
+	async fn search(
+		&self,
+		query: &str,
+		market: Option<Country>,
+	) -> Result<Vec<FullTrack>, SpotifyError>;
+	/// Get recommendations seeded by a track or artist URI
+	async fn recommendations(
+		&self,
+		seed: &str,
+		attributes: Vec<RecommendationsAttribute>,
+		limit: u32,
+	) -> Result<Vec<SimplifiedTrack>, SpotifyError>;
+
+	/// Get any number of albums, batched into as few requests as possible
+	async fn albums(&self, ids: &[&str]) -> Result<Vec<FullAlbum>, SpotifyError> {
+		chunked(ids, ALBUMS_BATCH_SIZE, |chunk| self.several_albums(chunk)).await
+	}
+
+	/// Get playlist tracks, one batch per fetched page
+	/// Tracks are filtered to those added on or after `added_since`, tracks without a date are kept
+	fn playlist_batches<'a>(
+		&'a self,
+		id: &'a str,
+		added_since: Option<NaiveDate>,
+	) -> impl Stream<Item = Result<Vec<FullTrack>, SpotifyError>> + 'a {
+		try_stream! {
+			let mut offset = 0;
+			loop {
+				let page = self.playlist_items(id, PAGE_LIMIT, offset).await?;
+				offset += page.items.len() as u32;
+				let last = page.next.is_none() || offset >= page.total;
+				fetch_progress("playlist tracks", offset, page.total);
+				yield page
+					.items
+					.into_iter()
+					.filter(|item| match (added_since, item.added_at) {
+						(Some(since), Some(added_at)) => added_at.date_naive() >= since,
+						_ => true,
+					})
+					.filter_map(|item| item.track)
+					.filter_map(|item| match item {
+						PlayableItem::Track(track) => Some(track),
+						_ => None,
+					})
+					.collect::<Vec<FullTrack>>();
+				if last {
+					break;
+				}
+			}
+		}
+	}
+
+	/// Get album tracks, one batch per fetched page
+	fn album_batches<'a>(
+		&'a self,
+		id: &'a str,
+	) -> impl Stream<Item = Result<Vec<SimplifiedTrack>, SpotifyError>> + 'a {
+		try_stream! {
+			let mut offset = 0;
+			loop {
+				let page = self.album_tracks(id, PAGE_LIMIT, offset).await?;
+				offset += page.items.len() as u32;
+				let last = page.next.is_none() || offset >= page.total;
+				yield page.items;
+				if last {
+					break;
+				}
+			}
+		}
+	}
+
+	/// Get show episodes with their number counted from the oldest, one batch per fetched page
+	fn show_episode_batches<'a>(
+		&'a self,
+		id: &'a str,
+		market: Option<Country>,
+	) -> impl Stream<Item = Result<Vec<(u32, SimplifiedEpisode)>, SpotifyError>> + 'a {
+		try_stream! {
+			let mut offset = 0;
+			loop {
+				let page = self.show_episodes(id, market, PAGE_LIMIT, offset).await?;
+				let total = page.total;
+				let numbered = page
+					.items
+					.into_iter()
+					.enumerate()
+					.map(|(i, episode)| (total.saturating_sub(offset + i as u32), episode))
+					.collect::<Vec<_>>();
+				offset += numbered.len() as u32;
+				let last = page.next.is_none() || offset >= total;
+				fetch_progress("show episodes", offset, total);
+				yield numbered;
+				if last {
+					break;
+				}
+			}
+		}
+	}
+
+	/// Get the tracks saved by the logged in user, one batch per fetched page
+	fn saved_track_batches(&self) -> impl Stream<Item = Result<Vec<FullTrack>, SpotifyError>> + '_ {
+		try_stream! {
+			let mut offset = 0;
+			loop {
+				let page = self.saved_tracks(PAGE_LIMIT, offset).await?;
+				offset += page.items.len() as u32;
+				let last = page.next.is_none() || offset >= page.total;
+				fetch_progress("saved tracks", offset, page.total);
+				yield page.items.into_iter().map(|saved| saved.track).collect::<Vec<FullTrack>>();
+				if last {
+					break;
+				}
+			}
+		}
+	}
+
+	/// Get the tracks of the albums saved by the logged in user, one batch per album
+	fn saved_album_batches(
+		&self,
+	) -> impl Stream<Item = Result<Vec<SimplifiedTrack>, SpotifyError>> + '_ {
+		try_stream! {
+			let mut offset = 0;
+			loop {
+				let page = self.saved_albums(PAGE_LIMIT, offset).await?;
+				offset += page.items.len() as u32;
+				let last = page.next.is_none() || offset >= page.total;
+				// Capped albums are completed concurrently, the client bounds how many at once
+				let albums = page
+					.items
+					.into_iter()
+					.map(|saved| self.album_tracks_of(saved.album));
+				for tracks in try_join_all(albums).await? {
+					yield tracks;
+				}
+				fetch_progress("saved albums", offset, page.total);
+				if last {
+					break;
+				}
+			}
+		}
+	}
+
+	/// Get artist tracks, one batch per album. Only the first `max_albums` albums are expanded
+	fn artist_batches<'a>(
+		&'a self,
+		id: &'a str,
+		groups: &'a [AlbumType],
+		max_albums: Option<usize>,
+	) -> impl Stream<Item = Result<Vec<SimplifiedTrack>, SpotifyError>> + 'a {
+		try_stream! {
+			let mut offset = 0;
+			let mut expanded = 0;
+			loop {
+				let page = self.artist_albums(id, groups, PAGE_LIMIT, offset).await?;
+				offset += page.items.len() as u32;
+				let mut last = page.next.is_none() || offset >= page.total;
+				let mut album_ids = page
+					.items
+					.iter()
+					.filter_map(|album| album.id.as_ref())
+					.map(|album_id| album_id.id())
+					.collect::<Vec<&str>>();
+				if let Some(max_albums) = max_albums {
+					let limited = limit_albums(&mut album_ids, expanded, page.total, max_albums);
+					if let Some(omitted) = limited {
+						if omitted > 0 {
+							warn!(
+								"Album limit of {} reached, {} album(s) omitted.",
+								max_albums, omitted
+							);
+						}
+						last = true;
+					}
+				}
+				expanded += album_ids.len();
+				// Capped albums are completed concurrently, the client bounds how many at once
+				let albums = self.albums(&album_ids).await?;
+				let albums = albums.into_iter().map(|album| self.album_tracks_of(album));
+				for tracks in try_join_all(albums).await? {
+					yield tracks;
+				}
+				let total = max_albums.map_or(page.total, |max| page.total.min(max as u32));
+				fetch_progress("artist albums", expanded as u32, total);
+				if last {
+					break;
+				}
+			}
+		}
+	}
+
+	/// Every track of a fetched album, each referencing the album.
+	/// Album tracks don't carry their album, which per album limits and ReplayGain rely on
+	async fn album_tracks_of(
+		&self,
+		album: FullAlbum,
+	) -> Result<Vec<SimplifiedTrack>, SpotifyError> {
+		let reference = SimplifiedAlbum {
+			id: Some(album.id.clone()),
+			name: album.name.clone(),
+			artists: album.artists.clone(),
+			..Default::default()
+		};
+		// Embedded track page is capped, fetch the rest separately
+		let tracks = match album.tracks.next {
+			Some(_) => self.full_album(album.id.id()).await?,
+			None => album.tracks.items,
+		};
+		Ok(tracks
+			.into_iter()
+			.map(|track| SimplifiedTrack {
+				album: Some(reference.clone()),
+				..track
+			})
+			.collect())
+	}
+
+	/// Get all tracks from album
+	async fn full_album(&self, id: &str) -> Result<Vec<SimplifiedTrack>, SpotifyError> {
+		collect_batches(self.album_batches(id)).await
+	}
+
+	/// Get all tracks from playlist
+	async fn full_playlist(
+		&self,
+		id: &str,
+		added_since: Option<NaiveDate>,
+	) -> Result<Vec<FullTrack>, SpotifyError> {
+		collect_batches(self.playlist_batches(id, added_since)).await
+	}
+
+	/// Get all tracks of the albums saved by the logged in user
+	async fn full_saved_albums(&self) -> Result<Vec<SimplifiedTrack>, SpotifyError> {
+		collect_batches(self.saved_album_batches()).await
+	}
+
+	/// Get all tracks from artist
+	async fn full_artist(
+		&self,
+		id: &str,
+		groups: &[AlbumType],
+		max_albums: Option<usize>,
+	) -> Result<Vec<SimplifiedTrack>, SpotifyError> {
+		collect_batches(self.artist_batches(id, groups, max_albums)).await
+	}
+}
+
+/// Truncate the album ids of a page to what is left of `max_albums` after `expanded` albums.
+/// Returns how many of the `total` albums are left out once the limit is reached
+fn limit_albums(
+	album_ids: &mut Vec<&str>,
+	expanded: usize,
+	total: u32,
+	max_albums: usize,
+) -> Option<usize> {
+	if expanded + album_ids.len() < max_albums {
+		return None;
+	}
+	album_ids.truncate(max_albums - expanded);
+	Some((total as usize).saturating_sub(expanded + album_ids.len()))
 }
 
-pub(crate) fn convert_result_cpy<'a, T: Deserialize<'a>>(input: &'a str) -> ClientResult<T> {
-    serde_json::from_str::<T>(input).map_err(Into::into)
+/// Report progress of resolving a collection spanning several pages.
+/// Written to stderr, so piped listings stay clean
+fn fetch_progress(what: &str, fetched: u32, total: u32) {
+	if total <= PAGE_LIMIT {
+		return;
+	}
+	info!("Fetched {}/{} {}", fetched, total, what);
+	if stderr().is_terminal() {
+		eprint!("\rFetched {}/{} {}...", fetched, total, what);
+		if fetched >= total {
+			eprintln!();
+		}
+	}
 }
 
+/// Flatten batches into a single list
+async fn collect_batches<T>(
+	batches: impl Stream<Item = Result<Vec<T>, SpotifyError>>,
+) -> Result<Vec<T>, SpotifyError> {
+	pin_mut!(batches);
+	let mut items = vec![];
+	while let Some(batch) = batches.try_next().await? {
+		items.extend(batch);
+	}
+	Ok(items)
+}
+
+/// Fetch ids in chunks of at most `size`, sequentially so requests are not bursted
+async fn chunked<'a, T, F, Fut>(
+	ids: &'a [&'a str],
+	size: usize,
+	fetch: F,
+) -> Result<Vec<T>, SpotifyError>
+where
+	F: Fn(&'a [&'a str]) -> Fut,
+	Fut: Future<Output = Result<Vec<T>, SpotifyError>>,
+{
+	let chunks = ids.len().div_ceil(size);
+	let mut items = Vec::with_capacity(ids.len());
+	for (i, chunk) in ids.chunks(size).enumerate() {
+		match fetch(chunk).await {
+			Ok(batch) => items.extend(batch),
+			Err(e) => {
+				error!(
+					"Fetching chunk {} of {} ({} ids) failed: {}",
+					i + 1,
+					chunks,
+					chunk.len(),
+					e
+				);
+				return Err(e);
+			}
+		}
+	}
+	Ok(items)
+}
+
+/// Items requested per page when paginating
+const PAGE_LIMIT: u32 = 50;
+/// Maximum ids per several albums request
+pub const ALBUMS_BATCH_SIZE: usize = 20;
+/// Attempts made to refresh the Web API token before failing
+const TOKEN_ATTEMPTS: u32 = 3;
+/// librespot credentials cache, relative to the working directory
+const CREDENTIALS_CACHE: &str = "credentials_cache";
+const USER_TOKEN_CACHE: &str = "user_token.json";
+
 pub struct Spotify {
 	// librespotify sessopm
 	pub session: Session,
 	pub spotify: ClientCredsSpotify,
+	/// Logged in user, for private playlists and saved tracks. See [`Spotify::user_client`]
+	pub user: Option<AuthCodePkceSpotify>,
+	// Held while refreshing the token, so concurrent jobs don't request their own
+	token_refresh: Arc<Mutex<()>>,
+	/// Requests made through this instance and its clones
+	pub requests: Arc<RequestCounter>,
+	/// Bounds concurrent album fetches to `prefetch_concurrency`, shared by the clones
+	prefetch: Arc<Semaphore>,
 }
 
 impl Spotify {
 	/// Create new instance
-	pub async fn new(
-		username: &str,
-		password: &str,
-		client_id: &str,
-		client_secret: &str,
-	) -> Result<Spotify, SpotifyError> {
+	pub async fn new(settings: &Settings) -> Result<Spotify, SpotifyError> {
 		// librespot
-		let credentials = Credentials::with_password(username, password);
+		let credentials = Credentials::with_password(&settings.username, &settings.password);
+		let session = Spotify::connect(credentials, settings.clear_stale_credentials).await?;
+
+		// rspotify
+		let credentials = ClientCredentials {
+			id: settings.client_id.to_string(),
+			secret: Some(settings.client_secret.to_string()),
+		};
+		// Base URLs can point to a regional gateway or a local mock server
+		let config = ClientConfig {
+			api_base_url: settings.api_base_url.to_string(),
+			auth_base_url: settings.auth_base_url.to_string(),
+			..Default::default()
+		};
+		let spotify = ClientCredsSpotify::with_config(credentials, config);
+		let requests = Arc::new(RequestCounter::default());
+		requests.count(RequestKind::Token);
+		spotify.request_token().await?;
+
+		// The user token is only there after --login, it is refreshed when expired
+		let user = Spotify::user_client(settings);
+		let user = match user.read_token_cache(true).await {
+			Ok(Some(token)) => {
+				*user.get_token().lock().await.unwrap() = Some(token);
+				Some(user)
+			}
+			Ok(None) => None,
+			Err(e) => {
+				debug!("No user token cached: {}", e);
+				None
+			}
+		};
+
+		Ok(Spotify {
+			session,
+			spotify,
+			user,
+			token_refresh: Arc::new(Mutex::new(())),
+			prefetch: Arc::new(Semaphore::new(
+				settings.downloader.prefetch_concurrency.max(1),
+			)),
+			requests,
+		})
+	}
+
+	/// Client for the PKCE authorization flow of `--login`, its token is cached in the config folder
+	pub fn user_client(settings: &Settings) -> AuthCodePkceSpotify {
+		let credentials = ClientCredentials {
+			id: settings.client_id.to_string(),
+			secret: None,
+		};
+		let oauth = OAuth {
+			redirect_uri: settings.redirect_uri.to_string(),
+			scopes: scopes!(
+				"playlist-read-private",
+				"playlist-read-collaborative",
+				"user-library-read"
+			),
+			..Default::default()
+		};
+		let config = ClientConfig {
+			api_base_url: settings.api_base_url.to_string(),
+			auth_base_url: settings.auth_base_url.to_string(),
+			token_cached: true,
+			token_refreshing: true,
+			cache_path: Spotify::user_token_path(),
+			..Default::default()
+		};
+		AuthCodePkceSpotify::with_config(credentials, oauth, config)
+	}
+
+	fn user_token_path() -> PathBuf {
+		get_config_folder_path().join(USER_TOKEN_CACHE)
+	}
+
+	/// Connect to Spotify, clearing cached credentials and retrying once if logging in with them fails
+	async fn connect(
+		credentials: Credentials,
+		clear_stale_credentials: bool,
+	) -> Result<Session, SpotifyError> {
+		let open_cache = || Cache::new(Some(Path::new(CREDENTIALS_CACHE)), None, None, None);
+		let cache = open_cache()?;
+		let cached = cache.credentials().is_some();
+		let e = match Session::connect(
+			SessionConfig::default(),
+			credentials.clone(),
+			Some(cache),
+			true,
+		)
+		.await
+		{
+			Ok((session, _)) => return Ok(session),
+			Err(e @ SessionError::AuthenticationError(_)) if cached && clear_stale_credentials => e,
+			Err(e) => return Err(e.into()),
+		};
+
+		warn!(
+			"Login failed with cached credentials ({}), clearing the cache and retrying",
+			e
+		);
+		// Only librespot's cache, the user token of `--login` still works
+		Spotify::remove_cache_path(Path::new(CREDENTIALS_CACHE)).await?;
 		let (session, _) = Session::connect(
 			SessionConfig::default(),
 			credentials,
-			Some(Cache::new(Some(Path::new("credentials_cache")), None, None, None).unwrap()),
+			Some(open_cache()?),
 			true,
 		)
 		.await?;
+		info!("Login succeeded after clearing the credentials cache");
+		Ok(session)
+	}
 
-		// rspotify
-		let credentials = ClientCredentials {
-			id: client_id.to_string(),
-			secret: Some(client_secret.to_string()),
+	/// Remove the credentials cache and rspotify's token caches, returns what was removed
+	pub async fn clear_cache() -> Result<Vec<PathBuf>, SpotifyError> {
+		let mut removed = vec![];
+		let token_cache = ClientConfig::default().cache_path;
+		let user_token = Spotify::user_token_path();
+		for path in [PathBuf::from(CREDENTIALS_CACHE), token_cache, user_token] {
+			if Spotify::remove_cache_path(&path).await? {
+				removed.push(path);
+			}
+		}
+		Ok(removed)
+	}
+
+	/// Remove a cache file or folder, returns whether there was one
+	async fn remove_cache_path(path: &Path) -> Result<bool, SpotifyError> {
+		if path.is_dir() {
+			tokio::fs::remove_dir_all(path).await?;
+		} else if path.is_file() {
+			tokio::fs::remove_file(path).await?;
+		} else {
+			return Ok(false);
+		}
+		Ok(true)
+	}
+
+	/// Make sure a valid Web API token is present, refreshing it with backoff only once expired
+	pub async fn ensure_token(&self) -> Result<(), SpotifyError> {
+		let _refreshing = self.token_refresh.lock().await;
+		let valid = match self.spotify.get_token().lock().await {
+			Ok(token) => token.as_ref().is_some_and(|t| !t.is_expired()),
+			Err(_) => false,
 		};
-		let spotify = ClientCredsSpotify::new(credentials);
-		spotify.request_token().await?;
+		if valid {
+			return Ok(());
+		}
 
-		Ok(Spotify { session, spotify })
+		let mut attempt = 1;
+		loop {
+			self.requests.count(RequestKind::Token);
+			match self.spotify.request_token().await {
+				Ok(_) => return Ok(()),
+				Err(e) if attempt < TOKEN_ATTEMPTS => {
+					warn!(
+						"Token request attempt {} failed: {}, retrying...",
+						attempt, e
+					);
+					async_std::task::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+					attempt += 1;
+				}
+				Err(e) => return Err(e.into()),
+			}
+		}
+	}
+
+	/// Spotify treats shows and episodes without a market as unavailable,
+	/// so the country of the session is used unless one is configured
+	fn podcast_market(&self, market: Option<Country>) -> Option<Market> {
+		market
+			.or_else(|| serde_json::from_value(self.session.country().into()).ok())
+			.map(Market::Country)
 	}
 
 	/// Parse URI or URL into URI
@@ -101,204 +635,247 @@ impl Spotify {
 		Err(SpotifyError::InvalidUri)
 	}
 
-	/// Fetch data for URI
-	pub async fn resolve_uri(&self, uri: &str) -> Result<SpotifyItem, SpotifyError> {
+	/// Fetch data for URI, episodes and shows in market
+	pub async fn resolve_uri(
+		&self,
+		uri: &str,
+		market: Option<Country>,
+	) -> Result<SpotifyItem, SpotifyError> {
 		let parts = uri.split(':').skip(1).collect::<Vec<&str>>();
 		let id = parts[1];
 		match parts[0] {
-			"track" => {
-				let track = self
-					.spotify
-					.track(TrackId::from_id(id).unwrap(), None)
-					.await?;
-				Ok(SpotifyItem::Track(track))
-			}
-			"playlist" => {
-				let playlist = self
-					.spotify
-					.playlist(PlaylistId::from_id(id).unwrap(), None, None)
-					.await?;
-				Ok(SpotifyItem::Playlist(playlist))
-			}
-			"album" => {
-				let album = self
-					.spotify
-					.album(AlbumId::from_id(id).unwrap(), None)
-					.await?;
-				Ok(SpotifyItem::Album(album))
-			}
-			"artist" => {
-				let artist = self.spotify.artist(ArtistId::from_id(id).unwrap()).await?;
-				Ok(SpotifyItem::Artist(artist))
-			}
+			"track" => Ok(SpotifyItem::Track(self.track(id).await?)),
+			"playlist" => Ok(SpotifyItem::Playlist(self.playlist(id).await?)),
+			"album" => Ok(SpotifyItem::Album(self.album(id).await?)),
+			"artist" => Ok(SpotifyItem::Artist(self.artist(id).await?)),
+			"episode" => Ok(SpotifyItem::Episode(self.episode(id, market).await?)),
+			"show" => Ok(SpotifyItem::Show(self.show(id, market).await?)),
 			// Unsupported / Unimplemented
 			_ => Ok(SpotifyItem::Other(uri.to_string())),
 		}
 	}
+}
 
-	/// Get search results for query
-	pub async fn search(&self, query: &str) -> Result<Vec<FullTrack>, SpotifyError> {
+impl MetadataClient for Spotify {
+	async fn track(&self, id: &str) -> Result<FullTrack, SpotifyError> {
+		self.requests.count(RequestKind::Metadata);
+		Ok(self.spotify.track(TrackId::from_id(id)?, None).await?)
+	}
+
+	async fn album(&self, id: &str) -> Result<FullAlbum, SpotifyError> {
+		let _permit = self.prefetch.acquire().await;
+		self.requests.count(RequestKind::Metadata);
+		Ok(self.spotify.album(AlbumId::from_id(id)?, None).await?)
+	}
+
+	async fn playlist(&self, id: &str) -> Result<FullPlaylist, SpotifyError> {
+		self.requests.count(RequestKind::Metadata);
+		// Private and collaborative playlists are only visible to their user
+		let id = PlaylistId::from_id(id)?;
+		Ok(match &self.user {
+			Some(user) => user.playlist(id, None, None).await?,
+			None => self.spotify.playlist(id, None, None).await?,
+		})
+	}
+
+	async fn artist(&self, id: &str) -> Result<FullArtist, SpotifyError> {
+		self.requests.count(RequestKind::Metadata);
+		Ok(self.spotify.artist(ArtistId::from_id(id)?).await?)
+	}
+
+	async fn episode(
+		&self,
+		id: &str,
+		market: Option<Country>,
+	) -> Result<FullEpisode, SpotifyError> {
+		self.requests.count(RequestKind::Metadata);
+		let market = self.podcast_market(market);
 		Ok(self
 			.spotify
-			.search(query, SearchType::Track, None, None, Some(50), Some(0))
-			.await
-			.map(|result| match result {
-				SearchResult::Tracks(page) => page.items,
-				_ => Vec::new(),
-			})?)
+			.get_an_episode(EpisodeId::from_id(id)?, market)
+			.await?)
 	}
 
-	/// Get all tracks from playlist
-	pub async fn full_playlist(&self, id: &str) -> Result<Vec<FullTrack>, SpotifyError> {
-		// This is to get the entire playlist instead of just the first 100, as that is what the first request gives you to start with
-		let playlist = self // store playlist information for later
+	async fn show(&self, id: &str, market: Option<Country>) -> Result<FullShow, SpotifyError> {
+		self.requests.count(RequestKind::Metadata);
+		let market = self.podcast_market(market);
+		Ok(self
 			.spotify
-			.playlist(PlaylistId::from_id(id).unwrap(), None, None)
-			.await?;
-		let total_tracks = playlist.tracks.total; // Total number of tracks in playlist
-		let mut collected = playlist // The collection of tracks in memory (list gotten so far)
-			.tracks
-			.items
-			.into_iter()
-			.filter_map(|item| item.track)
-			.flat_map(|p_item| match p_item {
-				PlayableItem::Track(track) => Some(track),
-				_ => None,
-			}).collect::<Vec<FullTrack>>();
-
-		let mut attempts = 1; // Track number of requests
-
-		// If the playlist is less than 100 tracks, no need to loop for more
-		if playlist.tracks.next != None{
-			let mut _next = playlist
-				.tracks
-				.next
-				.unwrap();
-			// While the queue doesn't have all of the songs
-			while collected.len() < total_tracks.try_into().unwrap() {
-				attempts = attempts + 1;
-
-				// HTTP request for next 100 tracks
-				// Setup
-				let fields: Option<&str> = None;
-				let market: Option<Market> = None;
-				let params: HashMap<&str, &str> = build_map_cpy([
-					("fields", fields),
-					("market", market.map(Into::into))
-					]);
-				let payload: &Query<'_> = &params;
-				let headers = self
-					.spotify
-					.auth_headers()
-					.await?;
-				// Request and result
-				let mut result: String = self.
-					spotify
-					.get_http()
-					.get(&_next, Some(&headers), payload)
-					.await
-					.unwrap();
-
-				// This is to modify the response of the playlists track offset/limit request
-				// to be compliant for the JSON parsing that is expected for the FullPlaylist object
-				let tracks_temp = "{\"tracks\":";
-				result = tracks_temp.to_owned() + &result + 
-					", \"collaborative\" : " + &playlist.collaborative.to_string() +
-					", \"external_urls\": {" + "" + "}," + // TODO: add playlist's external_urls (no neat .to_string() method)
-					" \"followers\": {" +
-						"\"total\": " + &playlist.followers.total.to_string() + "}," + 
-					" \"id\": \"" + &id.to_string() + "\"," + 
-					"\"images\": [" + "" +"], " + // TODO: add playlist's images information (no neat .to_string() method)
-					"\"name\": \"" + &playlist.name.to_string() + "\"," +
-					"\"owner\": {" + // TODO: add playlist's owner information (no neat .to_string() method, and move issues)
-						"\"display_name\": \"" + "" + "\"," + 
-						" \"external_urls\":{" + "" + "}," + 
-						" \"href\":\"" + "" +"\"," + 
-						" \"id\":\"" + "" + "\"," + 
-						" \"images\": [" + "" + "]}," + 
-					" \"snapshot_id\": \"" + &playlist.snapshot_id.to_string() + "\"," + 
-					" \"href\": \"" + &_next +"\"}";
-
-        		let new_collect: ClientResult<FullPlaylist> = convert_result_cpy(&result); // The collection of tracks received from the next request
-				let modify = new_collect?; // a copy that we can modify
-				// The final response of the next item will have nothing, so don't unwrap
-				if modify.tracks.next != None {
-					_next = modify.tracks.next.unwrap();
-				}
-				let mut act_collect = modify
-					.tracks
-					.items
-					.into_iter()
-					.filter_map(|item| item.track)
-					.flat_map(|p_item| match p_item {
-						PlayableItem::Track(track) => Some(track),
-						_ => None,
-					}).collect::<Vec<FullTrack>>();
-				collected.append(&mut act_collect);
+			.get_a_show(ShowId::from_id(id)?, market)
+			.await?)
+	}
+
+	async fn show_episodes(
+		&self,
+		id: &str,
+		market: Option<Country>,
+		limit: u32,
+		offset: u32,
+	) -> Result<Page<SimplifiedEpisode>, SpotifyError> {
+		self.requests.count(RequestKind::Metadata);
+		let market = self.podcast_market(market);
+		Ok(self
+			.spotify
+			.get_shows_episodes_manual(ShowId::from_id(id)?, market, Some(limit), Some(offset))
+			.await?)
+	}
+
+	async fn playlist_items(
+		&self,
+		id: &str,
+		limit: u32,
+		offset: u32,
+	) -> Result<Page<PlaylistItem>, SpotifyError> {
+		self.requests.count(RequestKind::Metadata);
+		let id = PlaylistId::from_id(id)?;
+		Ok(match &self.user {
+			Some(user) => {
+				user.playlist_items_manual(id, None, None, Some(limit), Some(offset))
+					.await?
+			}
+			None => {
+				self.spotify
+					.playlist_items_manual(id, None, None, Some(limit), Some(offset))
+					.await?
 			}
-		}	
-		println!("Found {} total songs to be downloaded, with {} put into the queue, and required {} requests", total_tracks, collected.len(), attempts);
-		Ok(collected)
+		})
 	}
 
-	/// Get all tracks from album
-	pub async fn full_album(&self, id: &str) -> Result<Vec<SimplifiedTrack>, SpotifyError> {
+	async fn album_tracks(
+		&self,
+		id: &str,
+		limit: u32,
+		offset: u32,
+	) -> Result<Page<SimplifiedTrack>, SpotifyError> {
+		let _permit = self.prefetch.acquire().await;
+		self.requests.count(RequestKind::Metadata);
 		Ok(self
 			.spotify
-			.album(AlbumId::from_id(id).unwrap(), None)
-			.await?
-			.tracks
-			.items)
+			.album_track_manual(AlbumId::from_id(id)?, None, Some(limit), Some(offset))
+			.await?)
 	}
 
-	/// Get all tracks from artist
-	pub async fn full_artist(&self, id: &str) -> Result<Vec<SimplifiedTrack>, SpotifyError> {
-		// let mut items = vec![];
-		// let mut offset = 0;
-		// loop {
-		// 	let page = self
-		// 		.spotify
-		// 		.artists(id)
-		// 		.get_artist_albums(id, None, 50, offset, None)
-		// 		.await?;
-		//
-		// 	for album in &mut page.data.items.iter() {
-		// 		items.append(&mut self.full_album(&album.id).await?)
-		// 	}
-		//
-		// 	// End
-		// 	offset += page.data.items.len();
-		// 	if page.data.total == offset {
-		// 		return Ok(items);
-		// 	}
-		// }
-		let mut albums: Vec<SimplifiedAlbum> = Vec::new();
-		let stream = self
+	async fn artist_albums(
+		&self,
+		id: &str,
+		groups: &[AlbumType],
+		limit: u32,
+		offset: u32,
+	) -> Result<Page<SimplifiedAlbum>, SpotifyError> {
+		self.requests.count(RequestKind::Metadata);
+		Ok(self
 			.spotify
-			.artist_albums(ArtistId::from_id(id).unwrap(), None, None);
-		pin_mut!(stream);
-		while let Some(item) = stream.try_next().await.unwrap() {
-			albums.push(item);
-		}
-
-		let mut tracks: Vec<SimplifiedTrack> = Vec::new();
-		for album in albums {
-			let stream = self.spotify.album_track(album.id.unwrap(), None);
-			pin_mut!(stream);
-			while let Some(item) = stream.try_next().await.unwrap() {
-				tracks.push(item);
-			}
-		}
+			.artist_albums_manual(
+				ArtistId::from_id(id)?,
+				groups.iter().copied(),
+				None,
+				Some(limit),
+				Some(offset),
+			)
+			.await?)
+	}
+
+	async fn saved_tracks(
+		&self,
+		limit: u32,
+		offset: u32,
+	) -> Result<Page<SavedTrack>, SpotifyError> {
+		let user = self
+			.user
+			.as_ref()
+			.ok_or_else(|| SpotifyError::MissingUserScope("user-library-read".into()))?;
+		self.requests.count(RequestKind::Metadata);
+		Ok(user
+			.current_user_saved_tracks_manual(None, Some(limit), Some(offset))
+			.await?)
+	}
+
+	async fn saved_albums(
+		&self,
+		limit: u32,
+		offset: u32,
+	) -> Result<Page<SavedAlbum>, SpotifyError> {
+		let user = self
+			.user
+			.as_ref()
+			.ok_or_else(|| SpotifyError::MissingUserScope("user-library-read".into()))?;
+		self.requests.count(RequestKind::Metadata);
+		Ok(user
+			.current_user_saved_albums_manual(None, Some(limit), Some(offset))
+			.await?)
+	}
+
+	async fn several_albums(&self, ids: &[&str]) -> Result<Vec<FullAlbum>, SpotifyError> {
+		let _permit = self.prefetch.acquire().await;
+		self.requests.count(RequestKind::Metadata);
+		let ids = ids
+			.iter()
+			.map(|id| AlbumId::from_id(*id))
+			.collect::<Result<Vec<AlbumId>, _>>()?;
+		Ok(self.spotify.albums(ids, None).await?)
+	}
+
+	async fn search(
+		&self,
+		query: &str,
+		market: Option<Country>,
+	) -> Result<Vec<FullTrack>, SpotifyError> {
+		self.requests.count(RequestKind::Metadata);
+		Ok(self
+			.spotify
+			.search(
+				query,
+				SearchType::Track,
+				market.map(Market::Country),
+				None,
+				Some(50),
+				Some(0),
+			)
+			.await
+			.map(|result| match result {
+				SearchResult::Tracks(page) => page.items,
+				_ => Vec::new(),
+			})?)
+	}
 
-		Ok(tracks)
+	async fn recommendations(
+		&self,
+		seed: &str,
+		attributes: Vec<RecommendationsAttribute>,
+		limit: u32,
+	) -> Result<Vec<SimplifiedTrack>, SpotifyError> {
+		self.requests.count(RequestKind::Metadata);
+		let parts = seed.split(':').skip(1).collect::<Vec<&str>>();
+		let (artists, tracks) = match parts[..] {
+			["artist", id] => (Some([ArtistId::from_id(id)?]), None),
+			["track", id] => (None, Some([TrackId::from_id(id)?])),
+			_ => return Err(SpotifyError::InvalidUri),
+		};
+		let recommendations = self
+			.spotify
+			.recommendations(
+				attributes,
+				artists,
+				None::<Vec<&str>>,
+				tracks,
+				None,
+				Some(limit),
+			)
+			.await?;
+		Ok(recommendations.tracks)
 	}
 }
 
 impl Clone for Spotify {
 	fn clone(&self) -> Self {
+		// Clones share the token, so it is only refreshed once for all of them
 		Self {
 			session: self.session.clone(),
-			spotify: ClientCredsSpotify::new(self.spotify.creds.clone()),
+			spotify: self.spotify.clone(),
+			user: self.user.clone(),
+			token_refresh: self.token_refresh.clone(),
+			requests: self.requests.clone(),
+			prefetch: self.prefetch.clone(),
 		}
 	}
 }
@@ -310,12 +887,609 @@ impl fmt::Debug for Spotify {
 	}
 }
 
+/// Kind of request counted by [`RequestCounter`]
+#[derive(Debug, Clone, Copy)]
+pub enum RequestKind {
+	Metadata,
+	Cover,
+	Lyrics,
+	Token,
+}
+
+/// Counts requests made this run and warns when the last minute gets close to the limit
+#[derive(Debug, Default)]
+pub struct RequestCounter {
+	metadata: AtomicUsize,
+	cover: AtomicUsize,
+	lyrics: AtomicUsize,
+	token: AtomicUsize,
+	/// Requests per minute to warn about, 0 never warns
+	limit: AtomicUsize,
+	recent: StdMutex<VecDeque<Instant>>,
+}
+
+impl RequestCounter {
+	/// Share of the limit at which a warning is logged
+	const WARN_RATIO: f64 = 0.9;
+
+	pub fn set_limit(&self, limit: usize) {
+		self.limit.store(limit, Ordering::Relaxed);
+	}
+
+	pub fn count(&self, kind: RequestKind) {
+		let counter = match kind {
+			RequestKind::Metadata => &self.metadata,
+			RequestKind::Cover => &self.cover,
+			RequestKind::Lyrics => &self.lyrics,
+			RequestKind::Token => &self.token,
+		};
+		counter.fetch_add(1, Ordering::Relaxed);
+
+		let now = Instant::now();
+		let mut recent = self.recent.lock().unwrap();
+		while recent
+			.front()
+			.is_some_and(|t| now.duration_since(*t) > Duration::from_secs(60))
+		{
+			recent.pop_front();
+		}
+		recent.push_back(now);
+
+		// Only warn when crossing the threshold, not on every request above it
+		let limit = self.limit.load(Ordering::Relaxed);
+		let threshold = (limit as f64 * Self::WARN_RATIO).ceil() as usize;
+		if limit > 0 && recent.len() == threshold {
+			warn!(
+				"{} requests in the last minute, approaching the limit of {} per minute",
+				recent.len(),
+				limit
+			);
+		}
+	}
+
+	pub fn stats(&self) -> RequestStats {
+		RequestStats {
+			metadata: self.metadata.load(Ordering::Relaxed),
+			cover: self.cover.load(Ordering::Relaxed),
+			lyrics: self.lyrics.load(Ordering::Relaxed),
+			token: self.token.load(Ordering::Relaxed),
+		}
+	}
+}
+
+/// Snapshot of [`RequestCounter`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestStats {
+	pub metadata: usize,
+	pub cover: usize,
+	pub lyrics: usize,
+	pub token: usize,
+}
+
+impl RequestStats {
+	pub fn total(&self) -> usize {
+		self.metadata + self.cover + self.lyrics + self.token
+	}
+}
+
 #[derive(Debug, Clone)]
 pub enum SpotifyItem {
 	Track(FullTrack),
 	Album(FullAlbum),
 	Playlist(FullPlaylist),
 	Artist(FullArtist),
+	Episode(FullEpisode),
+	Show(FullShow),
 	/// Unimplemented
 	Other(String),
 }
+
+/// [`MetadataClient`] serving canned fixtures, for tests that shouldn't need a session
+#[cfg(test)]
+pub mod mock {
+	use chrono::Duration;
+	use rspotify::model::{
+		AlbumId, AlbumType, ArtistId, Country, DatePrecision, EpisodeId, FullAlbum, FullArtist,
+		FullEpisode, FullPlaylist, FullShow, FullTrack, Page, PlayableItem, PlaylistItem,
+		RecommendationsAttribute, SavedAlbum, SavedTrack, ShowId, SimplifiedAlbum,
+		SimplifiedArtist, SimplifiedEpisode, SimplifiedShow, SimplifiedTrack, TrackId,
+	};
+	use std::collections::HashMap;
+	use std::sync::Mutex;
+
+	use super::MetadataClient;
+	use crate::error::SpotifyError;
+
+	/// Serves pages of `page_size` items, so pagination is exercised with few fixtures
+	pub struct MockClient {
+		pub page_size: usize,
+		/// Tracks of each album by album id
+		pub albums: HashMap<String, (FullAlbum, Vec<SimplifiedTrack>)>,
+		pub playlist: Vec<PlaylistItem>,
+		pub artist_albums: Vec<SimplifiedAlbum>,
+		pub saved_tracks: Vec<SavedTrack>,
+		pub saved_albums: Vec<SavedAlbum>,
+		/// Episodes of every show, newest first
+		pub show_episodes: Vec<SimplifiedEpisode>,
+		/// Album ids in the order they were requested
+		pub requested_albums: Mutex<Vec<String>>,
+	}
+
+	impl MockClient {
+		pub fn new(page_size: usize) -> MockClient {
+			MockClient {
+				page_size,
+				albums: HashMap::new(),
+				playlist: vec![],
+				artist_albums: vec![],
+				saved_tracks: vec![],
+				saved_albums: vec![],
+				show_episodes: vec![],
+				requested_albums: Mutex::new(vec![]),
+			}
+		}
+
+		/// Add album with its tracks, the album embeds their first page like Spotify does
+		pub fn with_album(mut self, id: &str, track_count: u32) -> MockClient {
+			let tracks = (1..=track_count)
+				.map(|n| simplified_track(&format!("{}t{}", id, n), &["Artist"], 1, n))
+				.collect::<Vec<_>>();
+			let mut album = full_album(id, &["Artist"], AlbumType::Album, vec![]);
+			album.tracks = page(&tracks, self.page_size, 0);
+			self.artist_albums.push(simplified_album(id));
+			self.albums.insert(id.to_string(), (album, tracks));
+			self
+		}
+
+		/// Add album with its tracks to the saved albums
+		pub fn with_saved_album(self, id: &str, track_count: u32) -> MockClient {
+			let mut client = self.with_album(id, track_count);
+			let album = client.albums[id].0.clone();
+			client.saved_albums.push(SavedAlbum {
+				added_at: Default::default(),
+				album,
+			});
+			client
+		}
+
+		fn album_fixture(
+			&self,
+			id: &str,
+		) -> Result<&(FullAlbum, Vec<SimplifiedTrack>), SpotifyError> {
+			self.albums
+				.get(id)
+				.ok_or_else(|| SpotifyError::Error(format!("No album fixture {}", id)))
+		}
+	}
+
+	impl MetadataClient for MockClient {
+		async fn track(&self, id: &str) -> Result<FullTrack, SpotifyError> {
+			Ok(full_track(id))
+		}
+
+		async fn album(&self, id: &str) -> Result<FullAlbum, SpotifyError> {
+			self.requested_albums.lock().unwrap().push(id.to_string());
+			Ok(self.album_fixture(id)?.0.clone())
+		}
+
+		async fn playlist(&self, id: &str) -> Result<FullPlaylist, SpotifyError> {
+			Err(SpotifyError::Error(format!("No playlist fixture {}", id)))
+		}
+
+		async fn artist(&self, id: &str) -> Result<FullArtist, SpotifyError> {
+			Err(SpotifyError::Error(format!("No artist fixture {}", id)))
+		}
+
+		async fn episode(
+			&self,
+			id: &str,
+			_market: Option<Country>,
+		) -> Result<FullEpisode, SpotifyError> {
+			Err(SpotifyError::Error(format!("No episode fixture {}", id)))
+		}
+
+		async fn show(&self, id: &str, _market: Option<Country>) -> Result<FullShow, SpotifyError> {
+			Err(SpotifyError::Error(format!("No show fixture {}", id)))
+		}
+
+		async fn show_episodes(
+			&self,
+			_id: &str,
+			_market: Option<Country>,
+			_limit: u32,
+			offset: u32,
+		) -> Result<Page<SimplifiedEpisode>, SpotifyError> {
+			Ok(page(&self.show_episodes, self.page_size, offset))
+		}
+
+		async fn playlist_items(
+			&self,
+			_id: &str,
+			_limit: u32,
+			offset: u32,
+		) -> Result<Page<PlaylistItem>, SpotifyError> {
+			Ok(page(&self.playlist, self.page_size, offset))
+		}
+
+		async fn album_tracks(
+			&self,
+			id: &str,
+			_limit: u32,
+			offset: u32,
+		) -> Result<Page<SimplifiedTrack>, SpotifyError> {
+			Ok(page(&self.album_fixture(id)?.1, self.page_size, offset))
+		}
+
+		async fn artist_albums(
+			&self,
+			_id: &str,
+			_groups: &[AlbumType],
+			_limit: u32,
+			offset: u32,
+		) -> Result<Page<SimplifiedAlbum>, SpotifyError> {
+			Ok(page(&self.artist_albums, self.page_size, offset))
+		}
+
+		async fn saved_tracks(
+			&self,
+			_limit: u32,
+			offset: u32,
+		) -> Result<Page<SavedTrack>, SpotifyError> {
+			Ok(page(&self.saved_tracks, self.page_size, offset))
+		}
+
+		async fn saved_albums(
+			&self,
+			_limit: u32,
+			offset: u32,
+		) -> Result<Page<SavedAlbum>, SpotifyError> {
+			Ok(page(&self.saved_albums, self.page_size, offset))
+		}
+
+		async fn several_albums(&self, ids: &[&str]) -> Result<Vec<FullAlbum>, SpotifyError> {
+			let mut albums = vec![];
+			for id in ids {
+				albums.push(self.album(id).await?);
+			}
+			Ok(albums)
+		}
+
+		async fn search(
+			&self,
+			_query: &str,
+			_market: Option<Country>,
+		) -> Result<Vec<FullTrack>, SpotifyError> {
+			Ok(vec![])
+		}
+
+		async fn recommendations(
+			&self,
+			_seed: &str,
+			_attributes: Vec<RecommendationsAttribute>,
+			_limit: u32,
+		) -> Result<Vec<SimplifiedTrack>, SpotifyError> {
+			Ok(vec![])
+		}
+	}
+
+	/// Page of items starting at offset, with `next` set while items are left
+	pub fn page<T: Clone>(items: &[T], page_size: usize, offset: u32) -> Page<T> {
+		let start = (offset as usize).min(items.len());
+		let end = (start + page_size).min(items.len());
+		Page {
+			href: String::new(),
+			items: items[start..end].to_vec(),
+			limit: page_size as u32,
+			next: (end < items.len()).then(|| format!("offset={}", end)),
+			offset,
+			previous: None,
+			total: items.len() as u32,
+		}
+	}
+
+	pub fn artist(name: &str) -> SimplifiedArtist {
+		SimplifiedArtist {
+			external_urls: HashMap::new(),
+			href: None,
+			id: ArtistId::from_id(name.replace(' ', "")).ok(),
+			name: name.to_string(),
+		}
+	}
+
+	pub fn simplified_track(
+		id: &str,
+		artists: &[&str],
+		disc_number: i32,
+		track_number: u32,
+	) -> SimplifiedTrack {
+		SimplifiedTrack {
+			album: None,
+			artists: artists.iter().map(|a| artist(a)).collect(),
+			available_markets: None,
+			disc_number,
+			duration: Duration::seconds(180),
+			explicit: false,
+			external_urls: HashMap::new(),
+			href: None,
+			id: Some(TrackId::from_id(id.to_string()).unwrap()),
+			is_local: false,
+			is_playable: None,
+			linked_from: None,
+			restrictions: None,
+			name: format!("Track {}", id),
+			preview_url: None,
+			track_number,
+		}
+	}
+
+	pub fn simplified_album(id: &str) -> SimplifiedAlbum {
+		SimplifiedAlbum {
+			album_group: None,
+			album_type: None,
+			artists: vec![artist("Artist")],
+			available_markets: vec![],
+			external_urls: HashMap::new(),
+			href: None,
+			id: Some(AlbumId::from_id(id.to_string()).unwrap()),
+			images: vec![],
+			name: format!("Album {}", id),
+			release_date: None,
+			release_date_precision: None,
+			restrictions: None,
+		}
+	}
+
+	pub fn full_album(
+		id: &str,
+		artists: &[&str],
+		album_type: AlbumType,
+		tracks: Vec<SimplifiedTrack>,
+	) -> FullAlbum {
+		let track_count = tracks.len();
+		FullAlbum {
+			artists: artists.iter().map(|a| artist(a)).collect(),
+			album_type,
+			available_markets: None,
+			copyrights: vec![],
+			external_ids: HashMap::new(),
+			external_urls: HashMap::new(),
+			genres: vec![],
+			href: String::new(),
+			id: AlbumId::from_id(id.to_string()).unwrap(),
+			images: vec![],
+			name: format!("Album {}", id),
+			popularity: 0,
+			release_date: "2020-01-02".to_string(),
+			release_date_precision: DatePrecision::Day,
+			tracks: page(&tracks, track_count.max(1), 0),
+			label: None,
+		}
+	}
+
+	pub fn full_track(id: &str) -> FullTrack {
+		FullTrack {
+			album: simplified_album("album"),
+			artists: vec![artist("Artist")],
+			available_markets: vec![],
+			disc_number: 1,
+			duration: Duration::seconds(180),
+			explicit: false,
+			external_ids: HashMap::new(),
+			external_urls: HashMap::new(),
+			href: None,
+			id: Some(TrackId::from_id(id.to_string()).unwrap()),
+			is_local: false,
+			is_playable: None,
+			linked_from: None,
+			restrictions: None,
+			name: format!("Track {}", id),
+			popularity: 0,
+			preview_url: None,
+			track_number: 1,
+		}
+	}
+
+	// `language` is deprecated, but still part of the struct
+	#[allow(deprecated)]
+	pub fn simplified_episode(id: &str) -> SimplifiedEpisode {
+		SimplifiedEpisode {
+			audio_preview_url: None,
+			description: String::new(),
+			duration: Duration::seconds(1800),
+			explicit: false,
+			external_urls: HashMap::new(),
+			href: String::new(),
+			id: EpisodeId::from_id(id.to_string()).unwrap(),
+			images: vec![],
+			is_externally_hosted: false,
+			is_playable: true,
+			language: String::new(),
+			languages: vec![],
+			name: format!("Episode {}", id),
+			release_date: "2020-01-02".to_string(),
+			release_date_precision: DatePrecision::Day,
+			resume_point: None,
+		}
+	}
+
+	#[allow(deprecated)]
+	pub fn full_episode(id: &str) -> FullEpisode {
+		let episode = simplified_episode(id);
+		FullEpisode {
+			audio_preview_url: None,
+			description: episode.description,
+			duration: episode.duration,
+			explicit: false,
+			external_urls: HashMap::new(),
+			href: String::new(),
+			id: episode.id,
+			images: vec![],
+			is_externally_hosted: false,
+			is_playable: true,
+			language: String::new(),
+			languages: vec![],
+			name: episode.name,
+			release_date: episode.release_date,
+			release_date_precision: episode.release_date_precision,
+			resume_point: None,
+			show: SimplifiedShow {
+				available_markets: vec![],
+				copyrights: vec![],
+				description: String::new(),
+				explicit: false,
+				external_urls: HashMap::new(),
+				href: String::new(),
+				id: ShowId::from_id("show").unwrap(),
+				images: vec![],
+				is_externally_hosted: None,
+				languages: vec![],
+				media_type: "audio".to_string(),
+				name: "Show".to_string(),
+				publisher: "Publisher".to_string(),
+			},
+		}
+	}
+
+	/// Playlist entry of track, episodes and removed tracks have no `FullTrack`
+	pub fn playlist_item(track: Option<FullTrack>, added_at: Option<&str>) -> PlaylistItem {
+		PlaylistItem {
+			added_at: added_at.map(|date| date.parse().unwrap()),
+			added_by: None,
+			is_local: false,
+			track: track.map(PlayableItem::Track),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::mock::*;
+	use super::*;
+
+	/// Ids of the tracks in each batch of stream
+	async fn batch_ids<T>(
+		batches: impl Stream<Item = Result<Vec<T>, SpotifyError>>,
+		id: impl Fn(&T) -> String,
+	) -> Vec<Vec<String>> {
+		pin_mut!(batches);
+		let mut ids = vec![];
+		while let Some(batch) = batches.try_next().await.unwrap() {
+			ids.push(batch.iter().map(&id).collect());
+		}
+		ids
+	}
+
+	fn simplified_id(track: &SimplifiedTrack) -> String {
+		track.id.as_ref().unwrap().id().to_string()
+	}
+
+	#[async_std::test]
+	async fn album_batches_follow_pages() {
+		let client = MockClient::new(2).with_album("a", 5);
+		let batches = batch_ids(client.album_batches("a"), simplified_id).await;
+		assert_eq!(
+			batches,
+			vec![vec!["at1", "at2"], vec!["at3", "at4"], vec!["at5"]]
+		);
+	}
+
+	#[async_std::test]
+	async fn show_episode_batches_number_from_oldest() {
+		let mut client = MockClient::new(2);
+		client.show_episodes = ["e5", "e4", "e3", "e2", "e1"]
+			.iter()
+			.map(|id| simplified_episode(id))
+			.collect();
+		let numbered = |(n, e): &(u32, SimplifiedEpisode)| format!("{}:{}", n, e.id.id());
+		let batches = batch_ids(client.show_episode_batches("s", None), numbered).await;
+		assert_eq!(
+			batches,
+			vec![vec!["5:e5", "4:e4"], vec!["3:e3", "2:e2"], vec!["1:e1"]]
+		);
+	}
+
+	#[async_std::test]
+	async fn album_batches_single_page() {
+		let client = MockClient::new(50).with_album("a", 3);
+		let batches = batch_ids(client.album_batches("a"), simplified_id).await;
+		assert_eq!(batches, vec![vec!["at1", "at2", "at3"]]);
+	}
+
+	#[async_std::test]
+	async fn playlist_batches_skip_missing_tracks_and_filter_by_date() {
+		let mut client = MockClient::new(2);
+		client.playlist = vec![
+			playlist_item(Some(full_track("old")), Some("2020-01-01T00:00:00Z")),
+			playlist_item(None, Some("2023-01-01T00:00:00Z")),
+			playlist_item(Some(full_track("new")), Some("2023-01-01T00:00:00Z")),
+			playlist_item(Some(full_track("undated")), None),
+		];
+		let track_id = |t: &FullTrack| t.id.as_ref().unwrap().id().to_string();
+
+		let all = batch_ids(client.playlist_batches("p", None), track_id).await;
+		assert_eq!(all, vec![vec!["old"], vec!["new", "undated"]]);
+
+		let since = NaiveDate::from_ymd_opt(2022, 1, 1);
+		let recent = batch_ids(client.playlist_batches("p", since), track_id).await;
+		assert_eq!(recent, vec![vec![], vec!["new", "undated"]]);
+	}
+
+	#[async_std::test]
+	async fn artist_batches_expand_every_album() {
+		let client = MockClient::new(2)
+			.with_album("a", 1)
+			.with_album("b", 3)
+			.with_album("c", 2);
+		let batches = batch_ids(client.artist_batches("x", &[], None), simplified_id).await;
+		// The second album has more tracks than its embedded page, so they are fetched too
+		assert_eq!(
+			batches,
+			vec![vec!["at1"], vec!["bt1", "bt2", "bt3"], vec!["ct1", "ct2"]]
+		);
+	}
+
+	#[async_std::test]
+	async fn artist_batches_stop_at_max_albums() {
+		let client = MockClient::new(2)
+			.with_album("a", 1)
+			.with_album("b", 1)
+			.with_album("c", 1)
+			.with_album("d", 1)
+			.with_album("e", 1);
+		let batches = batch_ids(client.artist_batches("x", &[], Some(3)), simplified_id).await;
+		assert_eq!(batches, vec![vec!["at1"], vec!["bt1"], vec!["ct1"]]);
+		// The page after the limit isn't requested
+		assert_eq!(*client.requested_albums.lock().unwrap(), ["a", "b", "c"]);
+	}
+
+	#[test]
+	fn limit_albums_counts_omitted() {
+		// Under the limit
+		let mut ids = vec!["a", "b"];
+		assert_eq!(limit_albums(&mut ids, 0, 5, 3), None);
+		assert_eq!(ids, ["a", "b"]);
+
+		// Limit reached inside the page
+		let mut ids = vec!["c", "d"];
+		assert_eq!(limit_albums(&mut ids, 2, 5, 3), Some(2));
+		assert_eq!(ids, ["c"]);
+
+		// Limit reached exactly at the end of the artist
+		let mut ids = vec!["a", "b", "c"];
+		assert_eq!(limit_albums(&mut ids, 0, 3, 3), Some(0));
+		assert_eq!(ids, ["a", "b", "c"]);
+	}
+
+	#[async_std::test]
+	async fn saved_album_batches_expand_every_album() {
+		let client = MockClient::new(2)
+			.with_saved_album("a", 1)
+			.with_saved_album("b", 3)
+			.with_saved_album("c", 2);
+		let batches = batch_ids(client.saved_album_batches(), simplified_id).await;
+		assert_eq!(
+			batches,
+			vec![vec!["at1"], vec!["bt1", "bt2", "bt3"], vec!["ct1", "ct2"]]
+		);
+	}
+}
@@ -0,0 +1,103 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::downloader::{
+	AudioFormat, Download, DownloadKind, DownloadState, DownloadTiming, DownloaderConfig,
+};
+use crate::error::SpotifyError;
+use crate::list::list_uri;
+use crate::spotify::Spotify;
+use crate::tag::TagWrap;
+
+/// Difference between the tracks of a URL and the files already in a folder
+#[derive(Debug, Clone)]
+pub struct SyncPlan {
+	/// Tracks with no file carrying their id
+	pub missing: Vec<Download>,
+	/// Files whose track is no longer part of the URL, they are kept
+	pub removed: usize,
+}
+
+/// Compare the tracks of a URL to the Spotify ids tagged in the files of folder
+pub async fn plan(
+	spotify: &Spotify,
+	uri: &str,
+	folder: &Path,
+	config: &DownloaderConfig,
+) -> Result<SyncPlan, SpotifyError> {
+	let listed = list_uri(spotify, uri, config).await?;
+	let scan_folder = folder.to_owned();
+	let scan_config = config.clone();
+	let local =
+		tokio::task::spawn_blocking(move || local_ids(&scan_folder, &scan_config)).await??;
+
+	let remote: HashSet<&str> = listed.iter().filter_map(|t| t.id.as_deref()).collect();
+	let removed = local
+		.iter()
+		.filter(|id| !remote.contains(id.as_str()))
+		.count();
+	let missing = listed
+		.iter()
+		.filter_map(|track| {
+			// Local files have no id to download them by
+			let id = track.id.as_ref()?;
+			(!local.contains(id)).then(|| Download {
+				id: 0,
+				order: 0,
+				track_id: id.clone(),
+				title: track.title.clone(),
+				subtitle: track.artist.clone(),
+				state: DownloadState::None,
+				warnings: vec![],
+				timing: DownloadTiming::default(),
+				album_id: None,
+				quality: None,
+				quality_fallback: None,
+				file: None,
+				kind: DownloadKind::Track,
+			})
+		})
+		.collect();
+	Ok(SyncPlan { missing, removed })
+}
+
+/// Spotify ids tagged in the files of folder and its subfolders
+fn local_ids(folder: &Path, config: &DownloaderConfig) -> Result<HashSet<String>, SpotifyError> {
+	let mut ids = HashSet::new();
+	let mut folders = vec![folder.to_owned()];
+	while let Some(folder) = folders.pop() {
+		for entry in fs::read_dir(&folder)? {
+			let path = entry?.path();
+			if path.is_dir() {
+				folders.push(path);
+				continue;
+			}
+			let format = match tagged_format(&path, config) {
+				Some(format) => format,
+				None => continue,
+			};
+			match TagWrap::new(&path, format) {
+				Ok(mut tag) => match tag.get_tag().spotify_id() {
+					Some(id) => {
+						ids.insert(id);
+					}
+					None => debug!("{} has no Spotify id", path.display()),
+				},
+				Err(e) => warn!("Reading tags of {} failed: {}", path.display(), e),
+			}
+		}
+	}
+	Ok(ids)
+}
+
+/// Format of a file whose tags can hold an id, by its extension
+fn tagged_format(path: &Path, config: &DownloaderConfig) -> Option<AudioFormat> {
+	let extension = path.extension()?.to_str()?;
+	[AudioFormat::Ogg, AudioFormat::Mp3]
+		.into_iter()
+		.find(|format| {
+			extension.eq_ignore_ascii_case(&format.extension())
+				|| extension.eq_ignore_ascii_case(&config.extension(format))
+		})
+}
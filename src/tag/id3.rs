@@ -1,16 +1,20 @@
-use id3::frame::{Picture, PictureType, Timestamp};
+use id3::frame::{ExtendedText, Lyrics, Picture, PictureType, Timestamp};
 use id3::{Tag, TagLike, Version};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use crate::downloader::CoverArt;
 use crate::error::SpotifyError;
 
-use super::Field;
+use super::{CoverType, Field, MultivalueMode};
+
+const SPOTIFY_ID: &str = "SPOTIFY_ID";
 
 pub struct ID3Tag {
 	path: PathBuf,
 	tag: Tag,
 	separator: String,
+	multivalue_mode: MultivalueMode,
 	version: Version,
 }
 
@@ -23,6 +27,7 @@ impl ID3Tag {
 			path: path.as_ref().to_owned(),
 			tag,
 			separator: String::new(),
+			multivalue_mode: MultivalueMode::Separator,
 			version: Version::Id3v23,
 		})
 	}
@@ -41,8 +46,15 @@ impl super::Tag for ID3Tag {
 		self.separator = separator.to_string();
 	}
 
+	fn set_multivalue_mode(&mut self, mode: MultivalueMode) {
+		self.multivalue_mode = mode;
+	}
+
 	fn set_raw(&mut self, tag: &str, value: Vec<String>) {
-		self.tag.set_text(tag, value.join(&self.separator));
+		match self.multivalue_mode {
+			MultivalueMode::Separator => self.tag.set_text(tag, value.join(&self.separator)),
+			MultivalueMode::SeparateFrames => self.tag.set_text_values(tag, value),
+		}
 	}
 
 	fn set_field(&mut self, field: Field, value: Vec<String>) {
@@ -55,25 +67,152 @@ impl super::Tag for ID3Tag {
 			Field::Genre => "TCON",
 			Field::Label => "TPUB",
 			Field::AlbumArtist => "TPE2",
+			Field::Compilation => "TCMP",
+			Field::TitleSort => "TSOT",
+			Field::ArtistSort => "TSOP",
+			Field::AlbumSort => "TSOA",
+			Field::AlbumArtistSort => "TSO2",
+			Field::SpotifyId => {
+				if let Some(id) = value.into_iter().next() {
+					self.tag.add_frame(ExtendedText {
+						description: SPOTIFY_ID.to_string(),
+						value: id,
+					});
+				}
+				return;
+			}
+			// Totals are stored in the track and disc frames, as in 3/12
+			Field::TotalTracks | Field::TotalDiscs => {
+				let total = value.first().and_then(|v| v.parse::<u32>().ok());
+				match (field, total) {
+					(Field::TotalTracks, Some(total)) => self.tag.set_total_tracks(total),
+					(Field::TotalDiscs, Some(total)) => self.tag.set_total_discs(total),
+					_ => {}
+				}
+				return;
+			}
 		};
-		self.set_raw(tag, value);
+		self.set_raw(tag, field.values(value));
+	}
+
+	fn spotify_id(&self) -> Option<String> {
+		self.tag
+			.extended_texts()
+			.find(|t| t.description == SPOTIFY_ID)
+			.map(|t| t.value.clone())
 	}
 
 	fn save(&mut self) -> Result<(), SpotifyError> {
 		Ok(self.tag.write_to_path(&self.path, self.version)?)
 	}
 
-	fn add_cover(&mut self, mime: &str, data: Vec<u8>) {
+	fn add_cover(&mut self, cover_type: CoverType, cover: CoverArt) {
+		// Pictures of the same type need distinct descriptions, or they replace each other
+		let (picture_type, description) = match cover_type {
+			CoverType::Front => (PictureType::CoverFront, "cover".to_string()),
+			CoverType::Other => {
+				let n = self
+					.tag
+					.pictures()
+					.filter(|p| p.picture_type == PictureType::Other)
+					.count();
+				(PictureType::Other, format!("cover {}", n + 1))
+			}
+		};
 		self.tag.add_frame(Picture {
-			mime_type: mime.to_owned(),
-			picture_type: PictureType::CoverFront,
-			description: "cover".to_string(),
-			data,
+			mime_type: cover.mime,
+			picture_type,
+			description,
+			data: cover.data,
+		});
+	}
+
+	fn set_encoder(&mut self, encoder: &str) {
+		self.tag.set_text("TENC", encoder);
+	}
+
+	fn set_lyrics(&mut self, lyrics: &str) {
+		self.tag.add_frame(Lyrics {
+			lang: "XXX".to_string(),
+			description: String::new(),
+			text: lyrics.to_string(),
 		});
 	}
 
 	fn set_release_date(&mut self, date: &str) {
-		self.tag
-			.set_date_released(Timestamp::from_str(date).unwrap())
+		match Timestamp::from_str(date) {
+			Ok(timestamp) => self.tag.set_date_released(timestamp),
+			Err(_) => warn!("Invalid release date: {}", date),
+		}
+	}
+
+	fn set_replaygain(&mut self, scope: &str, gain_db: f32, peak: f32) {
+		self.tag.add_frame(ExtendedText {
+			description: format!("REPLAYGAIN_{}_GAIN", scope),
+			value: format!("{:.2} dB", gain_db),
+		});
+		self.tag.add_frame(ExtendedText {
+			description: format!("REPLAYGAIN_{}_PEAK", scope),
+			value: format!("{:.6}", peak),
+		});
+	}
+
+	fn set_original_release_date(&mut self, date: &str) {
+		match Timestamp::from_str(date) {
+			Ok(timestamp) => self.tag.set_original_date_released(timestamp),
+			Err(_) => warn!("Invalid original release date: {}", date),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tag::Tag as _;
+
+	fn tag(mode: MultivalueMode) -> ID3Tag {
+		let mut tag = ID3Tag::open("missing.mp3").unwrap();
+		tag.set_separator(", ");
+		tag.set_multivalue_mode(mode);
+		tag
+	}
+
+	fn values() -> Vec<String> {
+		vec!["A".to_string(), "B".to_string()]
+	}
+
+	#[test]
+	fn multivalue_separator() {
+		let mut tag = tag(MultivalueMode::Separator);
+		tag.set_field(Field::Artist, values());
+		assert_eq!(tag.tag.artists(), Some(vec!["A, B"]));
+	}
+
+	#[test]
+	fn multivalue_separate_frames() {
+		let mut tag = tag(MultivalueMode::SeparateFrames);
+		tag.set_field(Field::Artist, values());
+		assert_eq!(tag.tag.artists(), Some(vec!["A", "B"]));
+	}
+
+	#[test]
+	fn multivalue_single_value_field() {
+		// Single value fields keep their first value in either mode
+		for mode in [MultivalueMode::Separator, MultivalueMode::SeparateFrames] {
+			let mut tag = tag(mode);
+			tag.set_field(Field::Album, values());
+			assert_eq!(tag.tag.album(), Some("A"));
+		}
+	}
+
+	#[test]
+	fn partial_release_dates() {
+		for date in ["1999", "1999-05", "1999-05-17"] {
+			let mut tag = tag(MultivalueMode::Separator);
+			tag.set_release_date(date);
+			tag.set_original_release_date(date);
+			assert_eq!(tag.tag.date_released().unwrap().to_string(), date);
+			assert_eq!(tag.tag.original_date_released().unwrap().to_string(), date);
+		}
 	}
 }
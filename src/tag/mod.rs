@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::Path;
 
-use crate::downloader::AudioFormat;
+use crate::downloader::{AudioFormat, CoverArt};
 use crate::error::SpotifyError;
 
 use self::id3::ID3Tag;
@@ -20,10 +22,15 @@ impl TagWrap {
 		match format {
 			AudioFormat::Ogg => Ok(TagWrap::Ogg(OggTag::open(path)?)),
 			AudioFormat::Mp3 => Ok(TagWrap::Id3(ID3Tag::open(path)?)),
-			_ => Err(SpotifyError::Error("Invalid format!".into())),
+			_ => Err(SpotifyError::UnsupportedTagFormat(format)),
 		}
 	}
 
+	/// Whether files of format can be tagged
+	pub fn supports(format: &AudioFormat) -> bool {
+		matches!(format, AudioFormat::Ogg | AudioFormat::Mp3)
+	}
+
 	/// Get Tag trait
 	pub fn get_tag(&mut self) -> &mut dyn Tag {
 		match self {
@@ -36,14 +43,36 @@ impl TagWrap {
 pub trait Tag {
 	// Set tag values separator
 	fn set_separator(&mut self, separator: &str);
+	// Set how multiple values of one field are written
+	fn set_multivalue_mode(&mut self, mode: MultivalueMode);
 	fn set_raw(&mut self, tag: &str, value: Vec<String>);
 	fn set_field(&mut self, field: Field, value: Vec<String>);
+	// Spotify id written by set_field, if the file has one
+	fn spotify_id(&self) -> Option<String>;
 	fn set_release_date(&mut self, date: &str);
-	fn add_cover(&mut self, mime: &str, data: Vec<u8>);
+	fn set_original_release_date(&mut self, date: &str);
+	// Set REPLAYGAIN_{scope}_GAIN and _PEAK, scope being TRACK or ALBUM, replacing existing ones
+	fn set_replaygain(&mut self, scope: &str, gain_db: f32, peak: f32);
+	fn add_cover(&mut self, cover_type: CoverType, cover: CoverArt);
+	fn add_covers(&mut self, covers: Vec<(CoverType, CoverArt)>) {
+		for (cover_type, cover) in covers {
+			self.add_cover(cover_type, cover);
+		}
+	}
+	fn set_lyrics(&mut self, lyrics: &str);
+	// Set the software the file was made with
+	fn set_encoder(&mut self, encoder: &str);
 	fn save(&mut self) -> Result<(), SpotifyError>;
 }
 
-#[derive(Debug, Clone)]
+/// Picture type of an embedded cover
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverType {
+	Front,
+	Other,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum Field {
 	Title,
 	Artist,
@@ -53,4 +82,138 @@ pub enum Field {
 	AlbumArtist,
 	Genre,
 	Label,
+	TotalTracks,
+	TotalDiscs,
+	Compilation,
+	TitleSort,
+	ArtistSort,
+	AlbumSort,
+	AlbumArtistSort,
+	/// Base62 id of the track, read back by `--sync`
+	SpotifyId,
+}
+
+impl Field {
+	/// Every field, written by default
+	pub fn all() -> HashSet<Field> {
+		HashSet::from([
+			Field::Title,
+			Field::Artist,
+			Field::Album,
+			Field::TrackNumber,
+			Field::DiscNumber,
+			Field::AlbumArtist,
+			Field::Genre,
+			Field::Label,
+			Field::TotalTracks,
+			Field::TotalDiscs,
+			Field::Compilation,
+			Field::TitleSort,
+			Field::ArtistSort,
+			Field::AlbumSort,
+			Field::AlbumArtistSort,
+			Field::SpotifyId,
+		])
+	}
+
+	/// Field holding the sort name of this one
+	pub fn sort_field(&self) -> Option<Field> {
+		match self {
+			Field::Title => Some(Field::TitleSort),
+			Field::Artist => Some(Field::ArtistSort),
+			Field::Album => Some(Field::AlbumSort),
+			Field::AlbumArtist => Some(Field::AlbumArtistSort),
+			_ => None,
+		}
+	}
+
+	/// Whether the field can hold several values, like artists or genres
+	pub fn is_multivalue(&self) -> bool {
+		matches!(self, Field::Artist | Field::AlbumArtist | Field::Genre)
+	}
+
+	/// Values to write, single value fields only keep the first instead of joining them
+	pub fn values(&self, mut value: Vec<String>) -> Vec<String> {
+		if !self.is_multivalue() {
+			value.truncate(1);
+		}
+		value
+	}
+}
+
+/// Name with a leading article moved to the end, as in "Beatles, The"
+pub fn sort_name(name: &str, articles: &[String]) -> String {
+	for article in articles {
+		let rest = name
+			.get(..article.len())
+			.filter(|prefix| prefix.eq_ignore_ascii_case(article))
+			.and_then(|_| name[article.len()..].strip_prefix(' '));
+		if let Some(rest) = rest.map(str::trim_start).filter(|r| !r.is_empty()) {
+			return format!("{}, {}", rest, &name[..article.len()]);
+		}
+	}
+	name.to_string()
+}
+
+/// How fields with multiple values (artists, genres) are written
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MultivalueMode {
+	/// Join values into a single string using the separator
+	Separator,
+	/// Write each value separately
+	SeparateFrames,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn values(values: &[&str]) -> Vec<String> {
+		values.iter().map(|v| v.to_string()).collect()
+	}
+
+	#[test]
+	fn single_value_fields_keep_first() {
+		assert_eq!(Field::Album.values(values(&["A", "B"])), ["A"]);
+		assert_eq!(Field::TrackNumber.values(values(&["1", "2"])), ["1"]);
+		assert!(Field::Title.values(vec![]).is_empty());
+	}
+
+	#[test]
+	fn multivalue_fields_keep_all() {
+		for field in [Field::Artist, Field::AlbumArtist, Field::Genre] {
+			assert_eq!(field.values(values(&["A", "B"])), ["A", "B"]);
+		}
+	}
+
+	#[test]
+	fn sort_name_articles() {
+		let articles = values(&["The", "A"]);
+		assert_eq!(sort_name("The Beatles", &articles), "Beatles, The");
+		assert_eq!(sort_name("the national", &articles), "national, the");
+		assert_eq!(
+			sort_name("A Tribe Called Quest", &articles),
+			"Tribe Called Quest, A"
+		);
+		assert_eq!(sort_name("The  Doubled", &articles), "Doubled, The");
+	}
+
+	#[test]
+	fn sort_name_edge_cases() {
+		let articles = values(&["The", "A"]);
+		// Nothing after the article, or the article is only the start of a word
+		for name in ["The", "The ", "Theater", "Abba", "A", ""] {
+			assert_eq!(sort_name(name, &articles), name);
+		}
+		// Multibyte characters inside the prefix length don't split the name
+		for name in ["Thé Dansant", "Tĥe X", "Ä Band", "日本"] {
+			assert_eq!(sort_name(name, &articles), name);
+		}
+		let articles = values(&["Él", "Les"]);
+		assert_eq!(sort_name("Él Niño", &articles), "Niño, Él");
+		assert_eq!(
+			sort_name("Les Négresses Vertes", &articles),
+			"Négresses Vertes, Les"
+		);
+	}
 }
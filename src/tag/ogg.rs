@@ -3,7 +3,8 @@ use oggvorbismeta::{read_comment_header, replace_comment_header, CommentHeader,
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
-use super::Field;
+use super::{CoverType, Field, MultivalueMode};
+use crate::downloader::CoverArt;
 use crate::error::SpotifyError;
 
 pub struct OggTag {
@@ -26,6 +27,9 @@ impl OggTag {
 impl super::Tag for OggTag {
 	fn set_separator(&mut self, _separator: &str) {}
 
+	// Vorbis comments are always written as separate values
+	fn set_multivalue_mode(&mut self, _mode: MultivalueMode) {}
+
 	fn set_field(&mut self, field: Field, value: Vec<String>) {
 		let tag = match field {
 			Field::Title => "TITLE",
@@ -36,30 +40,42 @@ impl super::Tag for OggTag {
 			Field::Genre => "GENRE",
 			Field::Label => "LABEL",
 			Field::AlbumArtist => "ALBUMARTIST",
+			Field::TotalTracks => "TRACKTOTAL",
+			Field::TotalDiscs => "DISCTOTAL",
+			Field::Compilation => "COMPILATION",
+			Field::TitleSort => "TITLESORT",
+			Field::ArtistSort => "ARTISTSORT",
+			Field::AlbumSort => "ALBUMSORT",
+			Field::AlbumArtistSort => "ALBUMARTISTSORT",
+			Field::SpotifyId => "SPOTIFY_ID",
 		};
-		self.set_raw(tag, value);
+		self.set_raw(tag, field.values(value));
 	}
 
-	fn add_cover(&mut self, mime: &str, data: Vec<u8>) {
+	fn add_cover(&mut self, cover_type: CoverType, cover: CoverArt) {
 		let mut picture: Vec<u8> = Vec::new();
 
-		// MIME type
-		picture.extend(3u32.to_be_bytes().iter());
-		picture.extend((mime.as_bytes().len() as u32).to_be_bytes().iter());
-		picture.extend(mime.as_bytes());
+		// Picture type, MIME type
+		let picture_type: u32 = match cover_type {
+			CoverType::Front => 3,
+			CoverType::Other => 0,
+		};
+		picture.extend(picture_type.to_be_bytes().iter());
+		picture.extend((cover.mime.len() as u32).to_be_bytes().iter());
+		picture.extend(cover.mime.as_bytes());
 
 		// Description
 		picture.extend(0u32.to_be_bytes().iter());
 
-		// Width, height, depth, and number of colors
-		picture.extend(0u32.to_be_bytes().iter());
-		picture.extend(0u32.to_be_bytes().iter());
+		// Width, height, depth, and number of colors, 0 if unknown
+		picture.extend(cover.width.unwrap_or(0).to_be_bytes().iter());
+		picture.extend(cover.height.unwrap_or(0).to_be_bytes().iter());
 		picture.extend(0u32.to_be_bytes().iter());
 		picture.extend(0u32.to_be_bytes().iter());
 
 		// Image data
-		picture.extend((data.len() as u32).to_be_bytes().iter());
-		picture.extend(data);
+		picture.extend((cover.data.len() as u32).to_be_bytes().iter());
+		picture.extend(cover.data);
 
 		self.tag.add_tag_single(
 			"METADATA_BLOCK_PICTURE",
@@ -67,6 +83,10 @@ impl super::Tag for OggTag {
 		);
 	}
 
+	fn spotify_id(&self) -> Option<String> {
+		self.tag.get_tag_single("SPOTIFY_ID")
+	}
+
 	fn set_raw(&mut self, tag: &str, value: Vec<String>) {
 		self.tag.add_tag_multi(
 			tag,
@@ -82,7 +102,36 @@ impl super::Tag for OggTag {
 		Ok(())
 	}
 
+	fn set_lyrics(&mut self, lyrics: &str) {
+		self.tag.clear_tag("LYRICS");
+		self.tag.add_tag_single("LYRICS", lyrics);
+	}
+
+	// Replaces the encoder of the source stream
+	fn set_encoder(&mut self, encoder: &str) {
+		self.tag.clear_tag("ENCODER");
+		self.tag.add_tag_single("ENCODER", encoder);
+	}
+
 	fn set_release_date(&mut self, date: &str) {
 		self.tag.add_tag_single("DATE", date)
 	}
+
+	fn set_original_release_date(&mut self, date: &str) {
+		self.tag.add_tag_single("ORIGINALDATE", date)
+	}
+
+	fn set_replaygain(&mut self, scope: &str, gain_db: f32, peak: f32) {
+		// Replaced when the album gain is rewritten
+		self.tag.clear_tag(&format!("REPLAYGAIN_{}_GAIN", scope));
+		self.tag.clear_tag(&format!("REPLAYGAIN_{}_PEAK", scope));
+		self.tag.add_tag_single(
+			&format!("REPLAYGAIN_{}_GAIN", scope),
+			&format!("{:.2} dB", gain_db),
+		);
+		self.tag.add_tag_single(
+			&format!("REPLAYGAIN_{}_PEAK", scope),
+			&format!("{:.6}", peak),
+		);
+	}
 }
@@ -0,0 +1,365 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::downloader::{Download, DownloadState};
+use crate::format_mb;
+
+/// Width of the progress bars in characters
+const BAR_WIDTH: usize = 12;
+
+/// Log output held back while the overview is shown, it would draw over it otherwise
+static HELD_LOG: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Log target writing to stderr, or holding the output back while the overview is shown
+pub struct LogWriter;
+
+impl Write for LogWriter {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match HELD_LOG.lock().unwrap().as_mut() {
+			Some(held) => {
+				held.extend_from_slice(buf);
+				Ok(buf.len())
+			}
+			None => io::stderr().write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		io::stderr().flush()
+	}
+}
+
+/// What the user asked for with a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+	Cancel(i64),
+	Retry(i64),
+	Quit,
+}
+
+/// Scrollable table of the downloads, with details of the selected one
+pub struct Overview {
+	terminal: DefaultTerminal,
+	table: TableState,
+	/// Rows visible in the last frame, scrolled by PageUp and PageDown
+	page: u16,
+}
+
+impl Overview {
+	/// Switch the terminal to the overview, `None` if it can't be set up
+	pub fn open() -> Option<Overview> {
+		let terminal = match ratatui::try_init() {
+			Ok(terminal) => terminal,
+			Err(e) => {
+				ratatui::restore();
+				error!("Showing the overview failed: {}", e);
+				return None;
+			}
+		};
+		*HELD_LOG.lock().unwrap() = Some(vec![]);
+		Some(Overview {
+			terminal,
+			table: TableState::default().with_selected(Some(0)),
+			page: 1,
+		})
+	}
+
+	/// Draw the downloads with the status line below them
+	pub fn draw(&mut self, downloads: &[Download], status: &str) -> io::Result<()> {
+		let table = &mut self.table;
+		let page = &mut self.page;
+		self.terminal.draw(|frame| {
+			*page = render(frame, table, downloads, status);
+		})?;
+		Ok(())
+	}
+
+	/// Wait up to `timeout` for a key, the ones moving the selection are handled here
+	pub fn next_action(
+		&mut self,
+		downloads: &[Download],
+		timeout: Duration,
+	) -> io::Result<Option<Action>> {
+		if !event::poll(timeout)? {
+			return Ok(None);
+		}
+		match event::read()? {
+			Event::Key(key) if key.kind == KeyEventKind::Press => {
+				Ok(handle_key(&mut self.table, self.page, key, downloads))
+			}
+			_ => Ok(None),
+		}
+	}
+}
+
+impl Drop for Overview {
+	/// Restore the terminal and print the log output held back meanwhile
+	fn drop(&mut self) {
+		ratatui::restore();
+		if let Some(held) = HELD_LOG.lock().unwrap().take() {
+			io::stderr().write_all(&held).ok();
+		}
+	}
+}
+
+/// Move the selection or turn the key into an action on the selected download
+fn handle_key(
+	table: &mut TableState,
+	page: u16,
+	key: KeyEvent,
+	downloads: &[Download],
+) -> Option<Action> {
+	let selected = table.selected().and_then(|i| downloads.get(i));
+	match key.code {
+		// Raw mode swallows the signal, so Ctrl+C is a key like any other
+		KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+			return Some(Action::Quit)
+		}
+		KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+		KeyCode::Char('c') => {
+			return selected
+				.filter(|d| d.state.can_cancel())
+				.map(|d| Action::Cancel(d.id))
+		}
+		KeyCode::Char('r') => {
+			return selected
+				.filter(|d| d.state.can_retry())
+				.map(|d| Action::Retry(d.id))
+		}
+		KeyCode::Up | KeyCode::Char('k') => table.select_previous(),
+		KeyCode::Down | KeyCode::Char('j') => table.select_next(),
+		KeyCode::PageUp => table.scroll_up_by(page),
+		KeyCode::PageDown => table.scroll_down_by(page),
+		KeyCode::Home => table.select_first(),
+		KeyCode::End => table.select_last(),
+		_ => {}
+	}
+	clamp_selection(table, downloads.len());
+	None
+}
+
+/// Keep the selection on a download, the queue may be empty still
+fn clamp_selection(table: &mut TableState, len: usize) {
+	let selected = table.selected().unwrap_or(0);
+	table.select(len.checked_sub(1).map(|last| selected.min(last)));
+}
+
+/// Draw a frame, returning the number of rows the table shows
+fn render(frame: &mut Frame, table: &mut TableState, downloads: &[Download], status: &str) -> u16 {
+	let [list_area, details_area, status_area] = Layout::vertical([
+		Constraint::Min(4),
+		Constraint::Length(7),
+		Constraint::Length(2),
+	])
+	.areas(frame.area());
+
+	clamp_selection(table, downloads.len());
+	let rows = downloads.iter().map(|d| {
+		let speed = match (&d.state, d.timing.throughput()) {
+			(DownloadState::Downloading(..) | DownloadState::Done, Some(t)) => {
+				format!("{}/s", format_mb(t))
+			}
+			_ => String::new(),
+		};
+		let time = match d.timing.elapsed() {
+			Some(elapsed) => format!("{}s", elapsed.as_secs()),
+			None => String::new(),
+		};
+		Row::new(vec![
+			Line::from(progress(&d.state)),
+			Line::from(speed),
+			Line::from(time),
+			Line::from(vec![
+				Span::raw(d.title.clone()),
+				Span::raw("  "),
+				Span::raw(d.subtitle.clone()).dark_gray(),
+			]),
+		])
+		.style(state_style(&d.state))
+	});
+	let list = Table::new(
+		rows,
+		[
+			Constraint::Length(BAR_WIDTH as u16 + 6),
+			Constraint::Length(12),
+			Constraint::Length(6),
+			Constraint::Fill(1),
+		],
+	)
+	.header(Row::new(["Progress", "Speed", "Time", "Title"]).bold())
+	.row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+	.block(Block::bordered().title(format!(" Downloads ({}) ", downloads.len())));
+	frame.render_stateful_widget(list, list_area, table);
+
+	let selected = table.selected().and_then(|i| downloads.get(i));
+	let details = Paragraph::new(selected.map(details).unwrap_or_default())
+		.wrap(Wrap { trim: false })
+		.block(Block::bordered().title(" Details "));
+	frame.render_widget(details, details_area);
+
+	let keys = "↑/↓ PgUp/PgDn Home/End select   c cancel   r retry   q quit";
+	frame.render_widget(
+		Paragraph::new(vec![Line::from(status), Line::from(keys).dark_gray()]),
+		status_area,
+	);
+
+	// Borders and the header aren't rows
+	list_area.height.saturating_sub(3).max(1)
+}
+
+/// Short state of a download, with a progress bar while it downloads
+fn progress(state: &DownloadState) -> String {
+	match state {
+		DownloadState::None => "Queued".to_string(),
+		DownloadState::Lock => "Preparing".to_string(),
+		DownloadState::Downloading(read, total) => {
+			let ratio = match *total {
+				0 => 0.0,
+				total => (*read as f64 / total as f64).min(1.0),
+			};
+			format!("{} {:>3}%", progress_bar(ratio), (ratio * 100.0) as u8)
+		}
+		DownloadState::Post => "Postprocessing".to_string(),
+		DownloadState::Done => "Done".to_string(),
+		DownloadState::Untagged(_) => "Done, not tagged".to_string(),
+		DownloadState::Skipped(reason) => format!("Skipped, {}", reason),
+		DownloadState::Error(_) => "Failed".to_string(),
+	}
+}
+
+/// Bar of `BAR_WIDTH` characters filled to `ratio`
+fn progress_bar(ratio: f64) -> String {
+	let filled = (ratio.clamp(0.0, 1.0) * BAR_WIDTH as f64).round() as usize;
+	format!("{}{}", "█".repeat(filled), "░".repeat(BAR_WIDTH - filled))
+}
+
+fn state_style(state: &DownloadState) -> Style {
+	match state {
+		DownloadState::Done => Style::new().fg(Color::Green),
+		DownloadState::Untagged(_) | DownloadState::Skipped(_) => Style::new().fg(Color::Yellow),
+		DownloadState::Error(_) => Style::new().fg(Color::Red),
+		_ => Style::new(),
+	}
+}
+
+/// Everything known about the download, errors and warnings in full
+fn details(download: &Download) -> Vec<Line<'static>> {
+	let mut lines = vec![Line::from(vec![
+		Span::raw(download.title.clone()).bold(),
+		Span::raw(" - "),
+		Span::raw(download.subtitle.clone()),
+	])];
+	match &download.state {
+		DownloadState::Error(e) => lines.push(Line::from(format!("Failed: {}", e)).red()),
+		DownloadState::Untagged(e) => lines.push(Line::from(format!("Not tagged: {}", e)).yellow()),
+		DownloadState::Skipped(reason) => {
+			lines.push(Line::from(format!("Skipped: {}", reason)).yellow())
+		}
+		state => lines.push(Line::from(progress(state))),
+	}
+	if let Some(quality) = &download.quality {
+		let quality = match &download.quality_fallback {
+			Some(fallback) => format!("Quality: {}, {}", quality.to_string(), fallback),
+			None => format!("Quality: {}", quality.to_string()),
+		};
+		lines.push(Line::from(quality));
+	}
+	if let Some(file) = &download.file {
+		lines.push(Line::from(format!("File: {}", file.path.display())));
+	}
+	for warning in &download.warnings {
+		lines.push(Line::from(format!("Warning: {}", warning)).yellow());
+	}
+	lines
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::spotify::mock::simplified_track;
+
+	fn downloads(states: Vec<DownloadState>) -> Vec<Download> {
+		states
+			.into_iter()
+			.enumerate()
+			.map(|(i, state)| {
+				let mut download = Download::from(simplified_track("t", &["Artist"], 1, 1));
+				download.id = i as i64;
+				download.state = state;
+				download
+			})
+			.collect()
+	}
+
+	fn press(table: &mut TableState, downloads: &[Download], code: KeyCode) -> Option<Action> {
+		handle_key(table, 2, KeyEvent::from(code), downloads)
+	}
+
+	#[test]
+	fn progress_bar_fills_up() {
+		assert_eq!(progress_bar(0.0), "░".repeat(BAR_WIDTH));
+		assert_eq!(progress_bar(0.5), "█".repeat(6) + &"░".repeat(6));
+		assert_eq!(progress_bar(1.5), "█".repeat(BAR_WIDTH));
+		assert_eq!(
+			progress(&DownloadState::Downloading(50, 100)),
+			format!("{}  50%", progress_bar(0.5))
+		);
+		assert!(progress(&DownloadState::Downloading(1, 0)).ends_with("  0%"));
+	}
+
+	#[test]
+	fn selection_stays_on_the_queue() {
+		let downloads = downloads(vec![DownloadState::None; 5]);
+		let mut table = TableState::default().with_selected(Some(0));
+		press(&mut table, &downloads, KeyCode::Up);
+		assert_eq!(table.selected(), Some(0));
+		press(&mut table, &downloads, KeyCode::PageDown);
+		assert_eq!(table.selected(), Some(2));
+		press(&mut table, &downloads, KeyCode::End);
+		assert_eq!(table.selected(), Some(4));
+		press(&mut table, &downloads, KeyCode::Down);
+		assert_eq!(table.selected(), Some(4));
+
+		press(&mut table, &[], KeyCode::Down);
+		assert_eq!(table.selected(), None);
+	}
+
+	#[test]
+	fn cancel_and_retry_selected() {
+		let downloads = downloads(vec![
+			DownloadState::Downloading(1, 2),
+			DownloadState::Error("failed".to_string()),
+			DownloadState::Post,
+		]);
+		let mut table = TableState::default().with_selected(Some(0));
+		assert_eq!(
+			press(&mut table, &downloads, KeyCode::Char('c')),
+			Some(Action::Cancel(0))
+		);
+		assert_eq!(press(&mut table, &downloads, KeyCode::Char('r')), None);
+
+		press(&mut table, &downloads, KeyCode::Down);
+		assert_eq!(press(&mut table, &downloads, KeyCode::Char('c')), None);
+		assert_eq!(
+			press(&mut table, &downloads, KeyCode::Char('r')),
+			Some(Action::Retry(1))
+		);
+
+		// Postprocessing is left to finish
+		press(&mut table, &downloads, KeyCode::Down);
+		assert_eq!(press(&mut table, &downloads, KeyCode::Char('c')), None);
+
+		let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+		assert_eq!(
+			handle_key(&mut table, 2, ctrl_c, &downloads),
+			Some(Action::Quit)
+		);
+	}
+}
@@ -0,0 +1,108 @@
+use async_std::channel::unbounded;
+use colored::Colorize;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::downloader::Downloader;
+use crate::error::SpotifyError;
+
+/// Queue URLs appended to a text file, or to `.txt` files in a folder. Runs until interrupted
+pub async fn watch(downloader: &Downloader, path: impl AsRef<Path>) -> Result<(), SpotifyError> {
+	let path = fs::canonicalize(path).await?;
+	let (tx, rx) = unbounded();
+	let mut watcher = notify::recommended_watcher(move |event| {
+		tx.try_send(event).ok();
+	})?;
+	// Editors replace files on save, so a single file is watched through its folder
+	let (folder, file) = match path.is_dir() {
+		true => (path.clone(), None),
+		false => (path.parent().unwrap().to_owned(), Some(path.clone())),
+	};
+	watcher.watch(&folder, RecursiveMode::NonRecursive)?;
+
+	// Queue what is already there, then follow changes
+	let mut state = WatchState::default();
+	match &file {
+		Some(file) => state.read(downloader, file).await,
+		None => {
+			let mut entries = fs::read_dir(&folder).await?;
+			while let Some(entry) = entries.next_entry().await? {
+				if is_url_list(&entry.path()) {
+					state.read(downloader, &entry.path()).await;
+				}
+			}
+		}
+	}
+
+	while let Ok(event) = rx.recv().await {
+		let event: notify::Event = match event {
+			Ok(event) => event,
+			Err(e) => {
+				warn!("Watching {} failed: {}", path.display(), e);
+				continue;
+			}
+		};
+		if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+			continue;
+		}
+		for changed in event.paths {
+			let watched = match &file {
+				Some(file) => &changed == file,
+				None => is_url_list(&changed),
+			};
+			if watched {
+				state.read(downloader, &changed).await;
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Text files in a watched folder
+fn is_url_list(path: &Path) -> bool {
+	path.is_file() && path.extension().is_some_and(|e| e == "txt")
+}
+
+/// Read progress of watched files and URLs already queued this session
+#[derive(Debug, Default)]
+struct WatchState {
+	offsets: HashMap<PathBuf, usize>,
+	seen: HashSet<String>,
+}
+
+impl WatchState {
+	/// Queue lines added since the last read, an unfinished last line is left for later
+	async fn read(&mut self, downloader: &Downloader, path: &Path) {
+		let data = match fs::read(path).await {
+			Ok(data) => data,
+			Err(e) => {
+				warn!("Reading {} failed: {}", path.display(), e);
+				return;
+			}
+		};
+		let offset = self.offsets.entry(path.to_owned()).or_default();
+		// Truncated or replaced files are read from the start, queued URLs are still skipped
+		if data.len() < *offset {
+			*offset = 0;
+		}
+		let end = match data[*offset..].iter().rposition(|b| *b == b'\n') {
+			Some(i) => *offset + i + 1,
+			None => return,
+		};
+		let lines = String::from_utf8_lossy(&data[*offset..end]).to_string();
+		*offset = end;
+
+		for line in lines.lines().map(str::trim).filter(|l| !l.is_empty()) {
+			if !self.seen.insert(line.to_string()) {
+				continue;
+			}
+			match downloader.handle_input(line).await {
+				Ok(None) => println!("{} {}", "Queued".green(), line),
+				Ok(Some(_)) => warn!("Skipping {}, only URLs can be watched", line),
+				Err(e) => error!("{} {}: {}", "Queueing failed".red(), line, e),
+			}
+		}
+	}
+}